@@ -0,0 +1,818 @@
+use crate::core::{Address, Error, ResultChip8, VoidResultChip8, Word};
+use crate::cpu::PROGRAM_START;
+use crate::opcodes::{Condition, Opcode, OpcodeParam, Operation, Timer};
+use std::collections::HashMap;
+use std::fmt::{self, Display, Formatter};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+const MNEMONICS: &[&str] = &[
+    "CLS", "RET", "EXIT", "SCD", "SCU", "SCR", "SCL", "LOW", "HIGH", "SYS", "JP", "CALL", "NOP",
+    "LD", "ADD", "SE", "SNE", "SKP", "SKNP", "SAVE", "LOAD", "OR", "AND", "XOR", "SUB", "SUBN",
+    "SHR", "SHL", "RND", "DRW", "PLANE", "AUDIO", "PITCH",
+];
+const KEYWORDS: &[&str] = &["I", "F", "HF", "B", "DT", "ST", "K", "R", "LONG"];
+
+/// How deep includes and constants can nest, to stop cycles
+const MAX_DEPTH: usize = 32;
+
+/// Assembles the source file at `path` into a ROM to be loaded at the start of the program.
+///
+/// Instructions can be written as mnemonics, like `LD V0, 0x05`, or the way `Opcode` displays
+/// them, like `V0 = 05`. Included files are looked up relative to the file that includes them.
+pub fn assemble_file(path: &str) -> ResultChip8<Vec<u8>> {
+    let mut assembler = Assembler::new();
+    assembler.read_file(Path::new(path), None, 0)?;
+    assembler.finish()
+}
+
+/// Assembles `source` into a ROM to be loaded at the start of the program.
+/// Included files are looked up relative to the current directory.
+pub fn assemble(source: &str) -> ResultChip8<Vec<u8>> {
+    let mut assembler = Assembler::new();
+    assembler.read_source(source, Rc::new("<source>".to_owned()), Path::new(""), 0)?;
+    assembler.finish()
+}
+
+/// Where something is in the source, for error messages
+#[derive(Clone, Debug)]
+pub(crate) struct Location {
+    pub(crate) file: Rc<String>,
+    pub(crate) line: usize,
+    pub(crate) column: usize,
+}
+
+impl Location {
+    pub(crate) fn error(&self, message: String) -> Error {
+        Error::new(format!("{}: {}", self, message))
+    }
+}
+
+impl Display for Location {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.file, self.line, self.column)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum TokenKind {
+    Name(String),
+    Number(i64),
+    Text(String),
+    /// A row of a sprite, like `..##..##`
+    Pixels(String),
+    Symbol(char),
+}
+
+#[derive(Clone, Debug)]
+struct Token {
+    kind: TokenKind,
+    location: Location,
+}
+
+/// A sum of numbers and symbols, which can refer to labels that come later
+#[derive(Clone, Debug)]
+struct Expression {
+    terms: Vec<(bool, Term)>,
+    location: Location,
+}
+
+#[derive(Clone, Debug)]
+enum Term {
+    Number(i64),
+    Symbol(String, Location),
+}
+
+#[derive(Clone, Debug)]
+enum Operand {
+    Register(u8),
+    Keyword(&'static str),
+    /// `[I]`, the memory I points to
+    Indirect,
+    Long(Expression),
+    Value(Expression),
+}
+
+#[derive(Clone, Debug)]
+enum Arg {
+    Register(u8),
+    Keyword(&'static str),
+    Indirect,
+    Long(i64, Location),
+    Value(i64, Location),
+}
+
+enum Item {
+    Instruction {
+        mnemonic: String,
+        operands: Vec<Operand>,
+        location: Location,
+    },
+    Bytes(Vec<Expression>),
+    Words(Vec<Expression>),
+    Raw(Vec<u8>),
+}
+
+enum Symbol {
+    Label(Address),
+    Constant(Expression),
+}
+
+struct Assembler {
+    items: Vec<Item>,
+    symbols: HashMap<String, (Symbol, Location)>,
+    address: usize,
+    /// Files being read, to report includes that include themselves
+    files: Vec<PathBuf>,
+}
+
+impl Assembler {
+    fn new() -> Assembler {
+        Assembler {
+            items: Vec::new(),
+            symbols: HashMap::new(),
+            address: PROGRAM_START.into(),
+            files: Vec::new(),
+        }
+    }
+
+    fn read_file(
+        &mut self,
+        path: &Path,
+        location: Option<&Location>,
+        depth: usize,
+    ) -> VoidResultChip8 {
+        let fail = |message: String| match location {
+            Some(x) => x.error(message),
+            None => Error::new(message),
+        };
+
+        let canonical = path.canonicalize().unwrap_or_else(|_| path.to_owned());
+        if depth > MAX_DEPTH || self.files.contains(&canonical) {
+            return Err(fail(format!("{} includes itself", path.display())));
+        }
+
+        let source = fs::read_to_string(path)
+            .map_err(|x| fail(format!("Unable to read {}: {}", path.display(), x)))?;
+        let directory = path.parent().unwrap_or_else(|| Path::new(""));
+
+        self.files.push(canonical);
+        let name = Rc::new(path.display().to_string());
+        self.read_source(&source, name, directory, depth)?;
+        self.files.pop();
+        Ok(())
+    }
+
+    fn read_source(
+        &mut self,
+        source: &str,
+        file: Rc<String>,
+        directory: &Path,
+        depth: usize,
+    ) -> VoidResultChip8 {
+        for (i, text) in source.lines().enumerate() {
+            let start = Location {
+                file: file.clone(),
+                line: i + 1,
+                column: 1,
+            };
+            if !self.read_pseudocode(text, &start)? {
+                let tokens = tokenize(text, &start)?;
+                self.read_line(tokens, &start, directory, depth)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads a line with an instruction written the way `Opcode` displays it, like `V0 += 01`,
+    /// where numbers are always hexadecimal. Returns false if the line isn't written that way.
+    fn read_pseudocode(&mut self, text: &str, start: &Location) -> ResultChip8<bool> {
+        let (label, code) = match text.split_once(':') {
+            Some((name, code)) if is_name(name.trim()) => (Some(name), code),
+            _ => (None, text),
+        };
+
+        // Drawing has a semicolon in it, so the comment can start at any of them
+        let ends = code
+            .match_indices(';')
+            .map(|(i, _)| i)
+            .chain(Some(code.len()));
+        let opcode = match ends
+            .filter_map(|end| code[..end].parse::<Opcode>().ok())
+            .next()
+        {
+            Some(x) => x,
+            None => return Ok(false),
+        };
+
+        if let Some(name) = label {
+            let location = Location {
+                column: name.len() - name.trim_start().len() + 1,
+                ..start.clone()
+            };
+            self.define(
+                name.trim(),
+                Symbol::Label(Address::from(self.address)),
+                &location,
+            )?;
+        }
+
+        let bytes = opcode
+            .encode_bytes()
+            .map_err(|x| start.error(x.to_string()))?;
+        let size = bytes.len();
+        self.push(Item::Raw(bytes), size, start)?;
+        Ok(true)
+    }
+
+    fn read_line(
+        &mut self,
+        tokens: Vec<Token>,
+        start: &Location,
+        directory: &Path,
+        depth: usize,
+    ) -> VoidResultChip8 {
+        let mut tokens = &tokens[..];
+
+        if let [Token {
+            kind: TokenKind::Name(name),
+            location,
+        }, Token {
+            kind: TokenKind::Symbol(':'),
+            ..
+        }, rest @ ..] = tokens
+        {
+            self.define(name, Symbol::Label(Address::from(self.address)), location)?;
+            tokens = rest;
+        }
+
+        let (first, rest) = match tokens.split_first() {
+            Some(x) => x,
+            None => return Ok(()),
+        };
+        let name = match &first.kind {
+            TokenKind::Name(x) => x,
+            _ => return Err(first.location.error("Expected an instruction".to_owned())),
+        };
+
+        match name.to_ascii_lowercase().as_str() {
+            "include" => match rest {
+                [Token {
+                    kind: TokenKind::Text(path),
+                    location,
+                }] => self.read_file(&directory.join(path), Some(location), depth + 1),
+                _ => Err(first
+                    .location
+                    .error("Expected a file name in quotes".to_owned())),
+            },
+
+            "const" => match rest {
+                [Token {
+                    kind: TokenKind::Name(name),
+                    location,
+                }, Token {
+                    kind: TokenKind::Symbol('='),
+                    ..
+                }, value @ ..] => {
+                    let value = parse_expression(value, location)?;
+                    self.define(name, Symbol::Constant(value), location)
+                }
+                _ => Err(first
+                    .location
+                    .error("Expected a constant like `const NAME = value`".to_owned())),
+            },
+
+            "db" => {
+                let mut values = Vec::new();
+                for operand in split_operands(rest, &first.location)? {
+                    match operand {
+                        [Token {
+                            kind: TokenKind::Text(text),
+                            ..
+                        }] => {
+                            let bytes = text.bytes().map(i64::from);
+                            values.extend(bytes.map(|x| Expression {
+                                terms: vec![(false, Term::Number(x))],
+                                location: operand[0].location.clone(),
+                            }));
+                        }
+                        _ => values.push(parse_expression(operand, &first.location)?),
+                    }
+                }
+                let size = values.len();
+                self.push(Item::Bytes(values), size, start)
+            }
+
+            "dw" => {
+                let values = split_operands(rest, &first.location)?
+                    .into_iter()
+                    .map(|x| parse_expression(x, &first.location))
+                    .collect::<ResultChip8<Vec<Expression>>>()?;
+                let size = values.len() * 2;
+                self.push(Item::Words(values), size, start)
+            }
+
+            "sprite" => {
+                if rest.is_empty() {
+                    return Err(first.location.error("Expected sprite rows".to_owned()));
+                }
+
+                let mut bytes = Vec::new();
+                for token in rest {
+                    match &token.kind {
+                        TokenKind::Pixels(row) if row.len() == 8 || row.len() == 16 => {
+                            let bits = row
+                                .chars()
+                                .fold(0u16, |acc, x| (acc << 1) | u16::from(x == '#'));
+                            if row.len() == 16 {
+                                bytes.extend_from_slice(&bits.to_be_bytes());
+                            } else {
+                                bytes.push(bits as u8);
+                            }
+                        }
+                        _ => {
+                            return Err(token.location.error(
+                                "Sprite rows must be 8 or 16 pixels made of `#` and `.`".to_owned(),
+                            ))
+                        }
+                    }
+                }
+                let size = bytes.len();
+                self.push(Item::Raw(bytes), size, start)
+            }
+
+            _ => {
+                let mnemonic = name.to_ascii_uppercase();
+                if !MNEMONICS.contains(&mnemonic.as_str()) {
+                    return Err(first
+                        .location
+                        .error(format!("Unknown instruction `{}`", name)));
+                }
+
+                let operands = split_operands(rest, &first.location)?
+                    .into_iter()
+                    .map(|x| parse_operand(x, &first.location))
+                    .collect::<ResultChip8<Vec<Operand>>>()?;
+
+                let size = match operands.as_slice() {
+                    [Operand::Keyword("I"), Operand::Long(_)] => 4,
+                    _ => 2,
+                };
+                let item = Item::Instruction {
+                    mnemonic,
+                    operands,
+                    location: first.location.clone(),
+                };
+                self.push(item, size, start)
+            }
+        }
+    }
+
+    fn define(&mut self, name: &str, symbol: Symbol, location: &Location) -> VoidResultChip8 {
+        if register(name).is_some() || keyword(name).is_some() {
+            return Err(location.error(format!("`{}` is a reserved name", name)));
+        }
+        if let Some((_, previous)) = self.symbols.get(name) {
+            return Err(location.error(format!("`{}` was already defined at {}", name, previous)));
+        }
+
+        self.symbols
+            .insert(name.to_owned(), (symbol, location.clone()));
+        Ok(())
+    }
+
+    fn push(&mut self, item: Item, size: usize, location: &Location) -> VoidResultChip8 {
+        self.address += size;
+        if self.address > 0x10000 {
+            return Err(location.error("The program doesn't fit in memory".to_owned()));
+        }
+
+        self.items.push(item);
+        Ok(())
+    }
+
+    fn finish(self) -> ResultChip8<Vec<u8>> {
+        let mut rom = Vec::new();
+
+        for item in self.items.iter() {
+            match item {
+                Item::Instruction {
+                    mnemonic,
+                    operands,
+                    location,
+                } => {
+                    let mut args = Vec::with_capacity(operands.len());
+                    for operand in operands {
+                        args.push(match operand {
+                            Operand::Register(x) => Arg::Register(*x),
+                            Operand::Keyword(x) => Arg::Keyword(x),
+                            Operand::Indirect => Arg::Indirect,
+                            Operand::Long(x) => Arg::Long(self.evaluate(x, 0)?, x.location.clone()),
+                            Operand::Value(x) => {
+                                Arg::Value(self.evaluate(x, 0)?, x.location.clone())
+                            }
+                        });
+                    }
+
+                    let opcode = instruction(mnemonic, &args, location)?;
+                    let bytes = opcode.encode_bytes();
+                    rom.extend(bytes.map_err(|x| location.error(x.to_string()))?);
+                }
+                Item::Bytes(values) => {
+                    for value in values {
+                        let x = self.evaluate(value, 0)?;
+                        rom.push(byte(x, &value.location)?.into());
+                    }
+                }
+                Item::Words(values) => {
+                    for value in values {
+                        let x = self.evaluate(value, 0)?;
+                        if !(-0x8000..=0xFFFF).contains(&x) {
+                            return Err(value
+                                .location
+                                .error(format!("{} doesn't fit in 16 bits", x)));
+                        }
+                        rom.extend_from_slice(&(x as u16).to_be_bytes());
+                    }
+                }
+                Item::Raw(bytes) => rom.extend_from_slice(bytes),
+            }
+        }
+
+        Ok(rom)
+    }
+
+    fn evaluate(&self, expression: &Expression, depth: usize) -> ResultChip8<i64> {
+        let mut result = 0i64;
+
+        for (negative, term) in expression.terms.iter() {
+            let value = match term {
+                Term::Number(x) => *x,
+                Term::Symbol(name, location) => match self.symbols.get(name) {
+                    Some((Symbol::Label(x), _)) => i64::from(u16::from(*x)),
+                    Some((Symbol::Constant(x), _)) if depth < MAX_DEPTH => {
+                        self.evaluate(x, depth + 1)?
+                    }
+                    Some((Symbol::Constant(_), _)) => {
+                        return Err(
+                            location.error(format!("`{}` is defined in terms of itself", name))
+                        )
+                    }
+                    None => return Err(location.error(format!("Unknown name `{}`", name))),
+                },
+            };
+
+            result = if *negative {
+                result.checked_sub(value)
+            } else {
+                result.checked_add(value)
+            }
+            .ok_or_else(|| expression.location.error("Value is too large".to_owned()))?;
+        }
+
+        Ok(result)
+    }
+}
+
+/// Turns the instruction `mnemonic` with `args` into the opcode it stands for
+fn instruction(mnemonic: &str, args: &[Arg], location: &Location) -> ResultChip8<Opcode> {
+    use Arg::{Indirect, Keyword, Long, Register, Value};
+
+    let cond = |x: &str| match x {
+        "SE" | "SKP" => Condition::Equal,
+        _ => Condition::NotEqual,
+    };
+    let op = |x: &str| match x {
+        "ADD" => Operation::Add,
+        "OR" => Operation::Or,
+        "AND" => Operation::And,
+        "XOR" => Operation::Xor,
+        "SUB" => Operation::Sub,
+        "SUBN" => Operation::ReverseSub,
+        _ => Operation::None,
+    };
+
+    Ok(match (mnemonic, args) {
+        ("CLS", []) => Opcode::ClearScreen,
+        ("RET", []) => Opcode::Return,
+        ("EXIT", []) => Opcode::Exit,
+        ("NOP", []) => Opcode::Nop,
+        ("SCD", [Value(n, at)]) => Opcode::ScrollDown(nibble(*n, at)?),
+        ("SCU", [Value(n, at)]) => Opcode::ScrollUp(nibble(*n, at)?),
+        ("SCR", []) => Opcode::ScrollRight,
+        ("SCL", []) => Opcode::ScrollLeft,
+        ("LOW", []) => Opcode::SetHighResolution(false),
+        ("HIGH", []) => Opcode::SetHighResolution(true),
+        ("SYS", [Value(a, at)]) => Opcode::CallNative(address(*a, at)?),
+        ("JP", [Value(a, at)]) => Opcode::Jump(address(*a, at)?),
+        ("JP", [Register(0), Value(a, at)]) => Opcode::OffsetJump(address(*a, at)?),
+        ("CALL", [Value(a, at)]) => Opcode::Call(address(*a, at)?),
+
+        ("LD", [Keyword("I"), Value(a, at)]) => Opcode::AssignAddress(address(*a, at)?),
+        ("LD", [Keyword("I"), Long(a, at)]) => {
+            if !(0..=0xFFFF).contains(a) {
+                return Err(at.error(format!("{} isn't a valid address", a)));
+            }
+            Opcode::AssignLongAddress(Address::new(*a as u16))
+        }
+        ("ADD", [Keyword("I"), Register(x)]) => Opcode::AddAddress(*x),
+        ("LD", [Keyword("F"), Register(x)]) => Opcode::GetCharacterAddress(*x),
+        ("LD", [Keyword("HF"), Register(x)]) => Opcode::GetLargeCharacterAddress(*x),
+        ("LD", [Keyword("B"), Register(x)]) => Opcode::WriteBCD(*x),
+
+        ("SE", [Register(x), Register(y)]) | ("SNE", [Register(x), Register(y)]) => {
+            Opcode::CondJump {
+                left: OpcodeParam::Register(*x),
+                right: OpcodeParam::Register(*y),
+                cond: cond(mnemonic),
+            }
+        }
+        ("SE", [Register(x), Value(n, at)]) | ("SNE", [Register(x), Value(n, at)]) => {
+            Opcode::CondJump {
+                left: OpcodeParam::Register(*x),
+                right: OpcodeParam::Immediate(byte(*n, at)?),
+                cond: cond(mnemonic),
+            }
+        }
+        ("SKP", [Register(x)]) | ("SKNP", [Register(x)]) => Opcode::CondKeyJump {
+            reg: *x,
+            cond: cond(mnemonic),
+        },
+        ("SAVE", [Register(x), Register(y)]) => Opcode::DumpValueRange { start: *x, end: *y },
+        ("LOAD", [Register(x), Register(y)]) => Opcode::LoadValueRange { start: *x, end: *y },
+
+        ("LD", [Register(x), Keyword("DT")]) => Opcode::GetDelayTimer(*x),
+        ("LD", [Register(x), Keyword("K")]) => Opcode::BlockOnKey(*x),
+        ("LD", [Keyword("DT"), Register(x)]) => Opcode::SetTimer {
+            reg: *x,
+            timer: Timer::Delay,
+        },
+        ("LD", [Keyword("ST"), Register(x)]) => Opcode::SetTimer {
+            reg: *x,
+            timer: Timer::Sound,
+        },
+        ("LD", [Indirect, Register(x)]) => Opcode::DumpValueRegisters(*x),
+        ("LD", [Register(x), Indirect]) => Opcode::LoadValueRegisters(*x),
+        ("LD", [Keyword("R"), Register(x)]) => Opcode::DumpFlagRegisters(*x),
+        ("LD", [Register(x), Keyword("R")]) => Opcode::LoadFlagRegisters(*x),
+
+        ("LD", [Register(x), Register(y)])
+        | ("ADD", [Register(x), Register(y)])
+        | ("OR", [Register(x), Register(y)])
+        | ("AND", [Register(x), Register(y)])
+        | ("XOR", [Register(x), Register(y)])
+        | ("SUB", [Register(x), Register(y)])
+        | ("SUBN", [Register(x), Register(y)]) => Opcode::Assign {
+            left_reg: *x,
+            right: OpcodeParam::Register(*y),
+            op: op(mnemonic),
+        },
+        ("LD", [Register(x), Value(n, at)]) | ("ADD", [Register(x), Value(n, at)]) => {
+            Opcode::Assign {
+                left_reg: *x,
+                right: OpcodeParam::Immediate(byte(*n, at)?),
+                op: op(mnemonic),
+            }
+        }
+        ("SHR", [Register(x)]) | ("SHL", [Register(x)]) => Opcode::Shift {
+            reg: *x,
+            source: *x,
+            right: mnemonic == "SHR",
+        },
+        ("SHR", [Register(x), Register(y)]) | ("SHL", [Register(x), Register(y)]) => {
+            Opcode::Shift {
+                reg: *x,
+                source: *y,
+                right: mnemonic == "SHR",
+            }
+        }
+        ("RND", [Register(x), Value(n, at)]) => Opcode::Random {
+            reg: *x,
+            mask: byte(*n, at)?,
+        },
+        ("DRW", [Register(x), Register(y), Value(n, at)]) => Opcode::Draw {
+            x: *x,
+            y: *y,
+            height: nibble(*n, at)?,
+        },
+
+        ("PLANE", [Value(n, at)]) => Opcode::SelectPlanes(nibble(*n, at)?),
+        ("AUDIO", []) => Opcode::LoadAudioPattern,
+        ("PITCH", [Register(x)]) => Opcode::SetPitch(*x),
+
+        _ => return Err(location.error(format!("Invalid operands for `{}`", mnemonic))),
+    })
+}
+
+fn byte(value: i64, location: &Location) -> ResultChip8<Word> {
+    // Negative values are allowed so that subtraction can be written as an addition
+    if !(-0x80..=0xFF).contains(&value) {
+        return Err(location.error(format!("{} doesn't fit in a byte", value)));
+    }
+    Ok(Word::new(value as u8))
+}
+
+fn nibble(value: i64, location: &Location) -> ResultChip8<u8> {
+    if !(0..=0xF).contains(&value) {
+        return Err(location.error(format!("{} must be between 0 and 15", value)));
+    }
+    Ok(value as u8)
+}
+
+fn address(value: i64, location: &Location) -> ResultChip8<Address> {
+    if !(0..=0xFFF).contains(&value) {
+        return Err(location.error(format!(
+            "{:#X} is out of reach, only addresses up to 0xFFF can be used here",
+            value
+        )));
+    }
+    Ok(Address::new(value as u16))
+}
+
+fn register(name: &str) -> Option<u8> {
+    let mut chars = name.chars();
+    match (chars.next(), chars.next(), chars.next()) {
+        (Some('V'), Some(x), None) | (Some('v'), Some(x), None) => x.to_digit(16).map(|x| x as u8),
+        _ => None,
+    }
+}
+
+fn keyword(name: &str) -> Option<&'static str> {
+    let name = name.to_ascii_uppercase();
+    KEYWORDS.iter().copied().find(|x| *x == name)
+}
+
+fn is_name(text: &str) -> bool {
+    let mut chars = text.chars();
+    chars
+        .next()
+        .is_some_and(|x| x.is_ascii_alphabetic() || x == '_')
+        && chars.all(|x| x.is_ascii_alphanumeric() || x == '_' || x == '.')
+}
+
+fn tokenize(text: &str, start: &Location) -> ResultChip8<Vec<Token>> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens = Vec::new();
+
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let location = Location {
+            column: i + 1,
+            ..start.clone()
+        };
+        let begin = i;
+        let mut take_while = |f: &dyn Fn(char) -> bool| {
+            while i < chars.len() && f(chars[i]) {
+                i += 1;
+            }
+            chars[begin..i].iter().collect::<String>()
+        };
+
+        let kind =
+            match c {
+                ';' => break,
+                _ if c.is_whitespace() => {
+                    i += 1;
+                    continue;
+                }
+                _ if c.is_ascii_alphabetic() || c == '_' => TokenKind::Name(take_while(&|x| {
+                    x.is_ascii_alphanumeric() || x == '_' || x == '.'
+                })),
+                _ if c.is_ascii_digit() => {
+                    let text = take_while(&|x| x.is_ascii_alphanumeric() || x == '_');
+                    TokenKind::Number(parse_number(&text).ok_or_else(|| {
+                        location.error(format!("`{}` isn't a valid number", text))
+                    })?)
+                }
+                '#' | '.' => TokenKind::Pixels(take_while(&|x| x == '#' || x == '.')),
+                '"' => {
+                    i += 1;
+                    let end = (i..chars.len())
+                        .find(|x| chars[*x] == '"')
+                        .ok_or_else(|| location.error("Missing the closing `\"`".to_owned()))?;
+                    let text = chars[i..end].iter().collect();
+                    i = end + 1;
+                    TokenKind::Text(text)
+                }
+                ',' | ':' | '[' | ']' | '+' | '-' | '=' => {
+                    i += 1;
+                    TokenKind::Symbol(c)
+                }
+                _ => return Err(location.error(format!("Unexpected `{}`", c))),
+            };
+
+        tokens.push(Token { kind, location });
+    }
+
+    Ok(tokens)
+}
+
+fn parse_number(text: &str) -> Option<i64> {
+    let text = text.replace('_', "");
+    let lower = text.to_ascii_lowercase();
+    if let Some(x) = lower.strip_prefix("0x") {
+        i64::from_str_radix(x, 16).ok()
+    } else if let Some(x) = lower.strip_prefix("0b") {
+        i64::from_str_radix(x, 2).ok()
+    } else {
+        text.parse().ok()
+    }
+}
+
+/// Splits the operands of an instruction on commas
+fn split_operands<'a>(tokens: &'a [Token], at: &Location) -> ResultChip8<Vec<&'a [Token]>> {
+    if tokens.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let operands: Vec<&[Token]> = tokens.split(|x| x.kind == TokenKind::Symbol(',')).collect();
+
+    for (i, operand) in operands.iter().enumerate() {
+        if operand.is_empty() {
+            // Point at the comma that's missing an operand
+            let comma = tokens
+                .iter()
+                .filter(|x| x.kind == TokenKind::Symbol(','))
+                .nth(i.saturating_sub(1))
+                .map_or(at, |x| &x.location);
+            return Err(comma.error("Missing an operand".to_owned()));
+        }
+    }
+
+    Ok(operands)
+}
+
+fn parse_operand(tokens: &[Token], at: &Location) -> ResultChip8<Operand> {
+    match tokens {
+        [Token {
+            kind: TokenKind::Name(name),
+            ..
+        }] if register(name).is_some() => Ok(Operand::Register(register(name).unwrap())),
+        [Token {
+            kind: TokenKind::Name(name),
+            ..
+        }, rest @ ..]
+            if name.eq_ignore_ascii_case("long") =>
+        {
+            Ok(Operand::Long(parse_expression(rest, &tokens[0].location)?))
+        }
+        [Token {
+            kind: TokenKind::Name(name),
+            ..
+        }] if keyword(name).is_some() => Ok(Operand::Keyword(keyword(name).unwrap())),
+        [Token {
+            kind: TokenKind::Symbol('['),
+            ..
+        }, Token {
+            kind: TokenKind::Name(name),
+            ..
+        }, Token {
+            kind: TokenKind::Symbol(']'),
+            ..
+        }] if name.eq_ignore_ascii_case("i") => Ok(Operand::Indirect),
+        _ => Ok(Operand::Value(parse_expression(tokens, at)?)),
+    }
+}
+
+fn parse_expression(tokens: &[Token], at: &Location) -> ResultChip8<Expression> {
+    let location = tokens.first().map_or(at, |x| &x.location).clone();
+    let mut terms = Vec::new();
+    let mut negative = false;
+    let mut expect_term = true;
+
+    for token in tokens {
+        match (&token.kind, expect_term) {
+            (TokenKind::Symbol('-'), true) => negative = !negative,
+            (TokenKind::Symbol('+'), true) => {}
+            (TokenKind::Symbol('-'), false) | (TokenKind::Symbol('+'), false) => {
+                negative = token.kind == TokenKind::Symbol('-');
+                expect_term = true;
+            }
+            (TokenKind::Number(x), true) => {
+                terms.push((negative, Term::Number(*x)));
+                expect_term = false;
+            }
+            (TokenKind::Name(x), true) if register(x).is_none() && keyword(x).is_none() => {
+                terms.push((negative, Term::Symbol(x.clone(), token.location.clone())));
+                expect_term = false;
+            }
+            _ => {
+                return Err(token
+                    .location
+                    .error("Expected a number or a name".to_owned()))
+            }
+        }
+        if !expect_term {
+            negative = false;
+        }
+    }
+
+    if expect_term {
+        let end = tokens.last().map_or(at, |x| &x.location);
+        return Err(end.error("Expected a value".to_owned()));
+    }
+
+    Ok(Expression { terms, location })
+}
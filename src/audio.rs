@@ -0,0 +1,208 @@
+use crate::core::{ResultChip8, VoidResultChip8, Word};
+use std::cell::RefCell;
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::rc::Rc;
+use std::time::Duration;
+
+/// Length in bytes of an XO-CHIP audio pattern
+pub const PATTERN_LEN: usize = 16;
+
+/// The XO-CHIP 1-bit audio pattern that loops while the sound timer is active
+#[derive(Eq, PartialEq, Clone, Copy, Debug)]
+pub struct AudioPattern {
+    pub bits: [Word; PATTERN_LEN],
+    pub pitch: Word,
+}
+
+impl Default for AudioPattern {
+    fn default() -> Self {
+        AudioPattern::new()
+    }
+}
+
+impl AudioPattern {
+    /// A square wave at the default pitch, for programs that never load a pattern
+    pub fn new() -> AudioPattern {
+        AudioPattern {
+            bits: [Word::new(0xF0u8); PATTERN_LEN],
+            pitch: Word::new(64u8),
+        }
+    }
+
+    /// How many bits of the pattern are played per second
+    pub fn sample_rate(&self) -> f64 {
+        let pitch: u8 = self.pitch.into();
+        4000.0 * 2f64.powf((f64::from(pitch) - 64.0) / 48.0)
+    }
+}
+
+/// Where the CPU sends its sound to
+pub trait AudioSink {
+    /// Called every tick with how much time passed since the last one, and whether the
+    /// sound timer is active
+    fn play(&mut self, elapsed: Duration, active: bool, pattern: &AudioPattern) -> VoidResultChip8;
+}
+
+/// Lets a sink be shared, to look at what it produced while the CPU still owns it
+impl<T: AudioSink> AudioSink for Rc<RefCell<T>> {
+    fn play(&mut self, elapsed: Duration, active: bool, pattern: &AudioPattern) -> VoidResultChip8 {
+        self.borrow_mut().play(elapsed, active, pattern)
+    }
+}
+
+/// Discards all sound
+pub struct NullAudio;
+
+impl AudioSink for NullAudio {
+    fn play(&mut self, _: Duration, _: bool, _: &AudioPattern) -> VoidResultChip8 {
+        Ok(())
+    }
+}
+
+/// Rings the terminal bell every time the sound timer becomes active
+pub struct BellAudio {
+    active: bool,
+}
+
+impl Default for BellAudio {
+    fn default() -> Self {
+        BellAudio::new()
+    }
+}
+
+impl BellAudio {
+    pub fn new() -> BellAudio {
+        BellAudio { active: false }
+    }
+}
+
+impl AudioSink for BellAudio {
+    fn play(&mut self, _: Duration, active: bool, _: &AudioPattern) -> VoidResultChip8 {
+        if active && !self.active {
+            io::stdout().write_all(b"\x07")?;
+            io::stdout().flush()?;
+        }
+
+        self.active = active;
+        Ok(())
+    }
+}
+
+/// Renders the sound into a mono, 8-bit PCM WAV file, playing the audio pattern while the
+/// sound timer is active and silence otherwise.
+///
+/// The header can only be completed once all the sound is known, which happens on `finish`,
+/// when the sink is dropped, and every second of sound in case the process is interrupted.
+pub struct WavAudio<W: Write + Seek> {
+    out: W,
+    samples: u32,
+    pending_samples: f64,
+    phase: f64,
+}
+
+impl WavAudio<BufWriter<File>> {
+    pub fn create_file(path: &str) -> ResultChip8<WavAudio<BufWriter<File>>> {
+        WavAudio::new(BufWriter::new(File::create(path)?))
+    }
+}
+
+impl<W: Write + Seek> WavAudio<W> {
+    pub const SAMPLE_RATE: u32 = 44100;
+    const SILENCE: u8 = 0x80;
+    const VOLUME: u8 = 0x40;
+
+    pub fn new(out: W) -> ResultChip8<WavAudio<W>> {
+        let mut wav = WavAudio {
+            out,
+            samples: 0,
+            pending_samples: 0.0,
+            phase: 0.0,
+        };
+
+        wav.write_header()?;
+        Ok(wav)
+    }
+
+    /// The destination of the sound, which is only a valid file after `finish`
+    pub fn get_ref(&self) -> &W {
+        &self.out
+    }
+
+    /// Completes the header, so that the file is valid up to this point
+    pub fn finish(&mut self) -> VoidResultChip8 {
+        let position = self.out.stream_position()?;
+        self.out.seek(SeekFrom::Start(0))?;
+        self.write_header()?;
+        self.out.seek(SeekFrom::Start(position))?;
+        self.out.flush()?;
+        Ok(())
+    }
+
+    fn write_header(&mut self) -> VoidResultChip8 {
+        let out = &mut self.out;
+        out.write_all(b"RIFF")?;
+        out.write_all(&(36 + self.samples).to_le_bytes())?;
+        out.write_all(b"WAVE")?;
+
+        out.write_all(b"fmt ")?;
+        out.write_all(&16u32.to_le_bytes())?; // Size of this chunk
+        out.write_all(&1u16.to_le_bytes())?; // PCM
+        out.write_all(&1u16.to_le_bytes())?; // Channels
+        out.write_all(&WavAudio::<W>::SAMPLE_RATE.to_le_bytes())?;
+        out.write_all(&WavAudio::<W>::SAMPLE_RATE.to_le_bytes())?; // Bytes per second
+        out.write_all(&1u16.to_le_bytes())?; // Bytes per sample
+        out.write_all(&8u16.to_le_bytes())?; // Bits per sample
+
+        out.write_all(b"data")?;
+        out.write_all(&self.samples.to_le_bytes())?;
+        Ok(())
+    }
+}
+
+impl<W: Write + Seek> AudioSink for WavAudio<W> {
+    fn play(&mut self, elapsed: Duration, active: bool, pattern: &AudioPattern) -> VoidResultChip8 {
+        self.pending_samples += elapsed.as_secs_f64() * f64::from(WavAudio::<W>::SAMPLE_RATE);
+        let count = self.pending_samples.floor();
+        self.pending_samples -= count;
+
+        let pattern_bits = (PATTERN_LEN * 8) as f64;
+        let step = pattern.sample_rate() / f64::from(WavAudio::<W>::SAMPLE_RATE);
+
+        let mut buffer = Vec::with_capacity(count as usize);
+        for _ in 0..count as usize {
+            if !active {
+                buffer.push(WavAudio::<W>::SILENCE);
+                continue;
+            }
+
+            let bit_index = self.phase as usize;
+            let byte: u8 = pattern.bits[bit_index / 8].into();
+            let bit = (byte >> (7 - bit_index % 8)) & 1;
+
+            buffer.push(if bit == 1 {
+                WavAudio::<W>::SILENCE + WavAudio::<W>::VOLUME
+            } else {
+                WavAudio::<W>::SILENCE - WavAudio::<W>::VOLUME
+            });
+            self.phase = (self.phase + step) % pattern_bits;
+        }
+
+        self.out.write_all(&buffer)?;
+
+        let previous_seconds = self.samples / WavAudio::<W>::SAMPLE_RATE;
+        self.samples += buffer.len() as u32;
+        if self.samples / WavAudio::<W>::SAMPLE_RATE != previous_seconds {
+            self.finish()?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<W: Write + Seek> Drop for WavAudio<W> {
+    fn drop(&mut self) {
+        // Errors can't be reported from here, `finish` should be called to handle them
+        let _ = self.finish();
+    }
+}
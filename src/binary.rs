@@ -0,0 +1,78 @@
+//! Big-endian encoding helpers for the emulator's binary file formats
+
+use crate::core::{Error, ResultChip8, VoidResultChip8};
+use std::io::{self, Read, Write};
+
+pub(crate) fn read_u8(input: &mut impl Read) -> ResultChip8<u8> {
+    let mut buf = [0u8; 1];
+    input.read_exact(&mut buf)?;
+    Ok(buf[0])
+}
+
+pub(crate) fn read_u16(input: &mut impl Read) -> ResultChip8<u16> {
+    let mut buf = [0u8; 2];
+    input.read_exact(&mut buf)?;
+    Ok(u16::from_be_bytes(buf))
+}
+
+pub(crate) fn read_u32(input: &mut impl Read) -> ResultChip8<u32> {
+    let mut buf = [0u8; 4];
+    input.read_exact(&mut buf)?;
+    Ok(u32::from_be_bytes(buf))
+}
+
+pub(crate) fn read_u64(input: &mut impl Read) -> ResultChip8<u64> {
+    let mut buf = [0u8; 8];
+    input.read_exact(&mut buf)?;
+    Ok(u64::from_be_bytes(buf))
+}
+
+/// Reads a u64, or returns `None` if the input ended first, for formats whose records go until
+/// the end of the file
+pub(crate) fn read_u64_or_end(input: &mut impl Read) -> ResultChip8<Option<u64>> {
+    let mut buf = [0u8; 8];
+    match input.read_exact(&mut buf) {
+        Ok(()) => Ok(Some(u64::from_be_bytes(buf))),
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Reads a byte array prefixed by its length
+pub(crate) fn read_bytes(input: &mut impl Read) -> ResultChip8<Vec<u8>> {
+    let len = read_u32(input)?;
+    let mut buf = Vec::new();
+    input.by_ref().take(len.into()).read_to_end(&mut buf)?;
+
+    if buf.len() != len as usize {
+        return Err(Error::new_str("File ended unexpectedly"));
+    }
+    Ok(buf)
+}
+
+pub(crate) fn write_u8(out: &mut impl Write, value: u8) -> VoidResultChip8 {
+    out.write_all(&[value])?;
+    Ok(())
+}
+
+pub(crate) fn write_u16(out: &mut impl Write, value: u16) -> VoidResultChip8 {
+    out.write_all(&value.to_be_bytes())?;
+    Ok(())
+}
+
+pub(crate) fn write_u32(out: &mut impl Write, value: u32) -> VoidResultChip8 {
+    out.write_all(&value.to_be_bytes())?;
+    Ok(())
+}
+
+pub(crate) fn write_u64(out: &mut impl Write, value: u64) -> VoidResultChip8 {
+    out.write_all(&value.to_be_bytes())?;
+    Ok(())
+}
+
+/// Writes a byte array prefixed by its length
+pub(crate) fn write_bytes(out: &mut impl Write, value: &[u8]) -> VoidResultChip8 {
+    write_u32(out, value.len() as u32)?;
+    out.write_all(value)?;
+    Ok(())
+}
@@ -2,12 +2,10 @@ use std::fmt::{self, Display, Debug, Formatter};
 use std::io;
 use std::ops::{
     Add, Sub, Mul, Div, Rem, Shr, Shl, BitOr, BitAnd, BitXor, 
-    AddAssign, SubAssign, MulAssign, DivAssign, RemAssign, ShrAssign, ShlAssign,
-    Index, IndexMut
+    AddAssign, SubAssign, MulAssign, DivAssign, RemAssign, ShrAssign, ShlAssign
 };
 use std::num::Wrapping;
 use std::borrow::Cow;
-use ctrlc;
 
 pub type ResultChip8<T> = Result<T, Error>;
 pub type VoidResultChip8 = ResultChip8<()>;
@@ -104,7 +102,7 @@ impl Debug for Word {
 
 impl From<Word> for u8 {
     fn from(x: Word) -> Self {
-        Self::from(x.0.0)
+        x.0.0
     }
 }
 
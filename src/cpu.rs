@@ -1,36 +1,59 @@
-use crate::core::{Address, Error, VoidResultChip8, Word};
+use crate::audio::{AudioPattern, AudioSink, NullAudio, PATTERN_LEN};
+use crate::core::{Address, Error, ResultChip8, VoidResultChip8, Word};
 use crate::display::VideoMemory;
-use crate::input::{InputManager, KEY_NUM};
+use crate::input::{InputSource, KEY_NUM};
 use crate::memory::{ByteArrayMemory, MemoryMapper, MemoryRange, ReadMemory, WriteMemory};
-use crate::opcodes::{Condition, Opcode, OpcodeParam, Timer};
+use crate::opcodes::{Opcode, OpcodeParam, Operation, Timer, ValueRegisterIndex};
+use crate::quirks::{AddressIncrement, Quirks};
+use crate::random::{RandomSource, ThreadRandom};
 use crate::registers::Registers;
-use crate::timers::Timers;
-use rand::random;
-use std::thread;
-use std::time::{Duration, Instant};
+use crate::timers::{Timers, TIMER_PERIOD};
+use crate::trace::{TraceEntry, TraceSink};
+
+pub const PROGRAM_START: u16 = 0x200;
+const MEMORY_END: u16 = 0xFFF;
+const EXTENDED_MEMORY_END: u16 = 0xFFFF;
 
 const DIGITS_ROM_DATA: &[u8; 0x50] = include_bytes!["digits.bin"];
-const MIN_TICK_DURATION: Duration = Duration::from_millis(1);
-const SLEEP_THRESHOLD: Duration = Duration::from_millis(50);
+const LARGE_DIGITS_ROM_DATA: &[u8; 0xA0] = include_bytes!["large_digits.bin"];
+const LARGE_DIGITS_START: u16 = 0x050;
 
+#[allow(clippy::upper_case_acronyms)]
 pub struct CPU {
     pub registers: Registers,
     pub timers: Timers,
     pub memory: MemoryMapper,
     pub stack: Vec<Address>,
     pub vram: VideoMemory,
-    pub input: InputManager,
+    pub input: Box<dyn InputSource>,
+    pub random: Box<dyn RandomSource>,
+    pub quirks: Quirks,
+    pub audio: AudioPattern,
+    pub audio_sink: Box<dyn AudioSink>,
+    /// Set when the program exits, after which ticks do nothing
+    pub halted: bool,
+    /// How many instructions ran since power on
+    pub cycles: u64,
+    /// Everything that receives the instructions that run, like trace files and profilers
+    pub tracers: Vec<Box<dyn TraceSink>>,
 }
 
 impl CPU {
-    pub fn new() -> CPU {
+    pub fn new(input: impl InputSource + 'static) -> CPU {
         let mut cpu = CPU {
             registers: Registers::new(),
             timers: Timers::new(),
             memory: MemoryMapper::new(),
             stack: Vec::new(),
             vram: VideoMemory::new(),
-            input: InputManager::new(),
+            input: Box::new(input),
+            random: Box::new(ThreadRandom),
+            quirks: Quirks::default(),
+            audio: AudioPattern::new(),
+            audio_sink: Box::new(NullAudio),
+            halted: false,
+            cycles: 0,
+            tracers: Vec::new(),
         };
 
         let digits_rom = ByteArrayMemory::new(DIGITS_ROM_DATA);
@@ -42,40 +65,178 @@ impl CPU {
             )
             .expect("Unable to map digits ROM");
 
+        let large_digits_rom = ByteArrayMemory::new(LARGE_DIGITS_ROM_DATA);
+        cpu.memory
+            .add_read(
+                large_digits_rom,
+                MemoryRange::new_len(LARGE_DIGITS_START, LARGE_DIGITS_ROM_DATA.len() as u16 - 1),
+                "Large Digits ROM",
+            )
+            .expect("Unable to map large digits ROM");
+
         cpu
     }
 
-    pub fn tick_loop(&mut self) -> VoidResultChip8 {
-        let mut sleep_acc = Duration::from_millis(0);
+    /// Maps the main memory with `rom` loaded into it, its size depends on `quirks`
+    pub fn load_rom(&mut self, rom: &[u8]) -> VoidResultChip8 {
+        let end = if self.quirks.extended_memory {
+            EXTENDED_MEMORY_END
+        } else {
+            MEMORY_END
+        };
 
-        loop {
-            let start = Instant::now();
-            self.tick()?;
+        let len = usize::from(end - PROGRAM_START) + 1;
+        if rom.len() > len {
+            return Err(Error::new(format!(
+                "ROM is {} bytes long, but only {} bytes fit in memory",
+                rom.len(),
+                len
+            )));
+        }
 
-            let tick_duration = start.elapsed();
+        let words: Vec<Word> = rom.iter().map(|x| Word::new(*x)).collect();
+        let mut main_memory = ByteArrayMemory::zero(len);
+        main_memory.set_range(Address::ZERO, &words)?;
 
-            if tick_duration < MIN_TICK_DURATION {
-                sleep_acc += MIN_TICK_DURATION - tick_duration;
-            }
+        self.memory.add(
+            main_memory,
+            MemoryRange::new(PROGRAM_START, end),
+            "Main Memory",
+        )
+    }
 
-            if sleep_acc > SLEEP_THRESHOLD {
-                thread::sleep(sleep_acc);
-                sleep_acc = Duration::from_millis(0);
-            }
+    /// Runs a whole 60Hz frame: `instructions` instructions, then one timer period and the
+    /// display update
+    pub fn frame(&mut self, instructions: u32) -> VoidResultChip8 {
+        for _ in 0..instructions {
+            self.step()?;
         }
+
+        self.end_frame()
     }
 
-    pub fn tick(&mut self) -> VoidResultChip8 {
-        self.timers.tick();
+    /// Advances the timers by one period and updates the display, as happens after every frame
+    pub fn end_frame(&mut self) -> VoidResultChip8 {
+        self.timers.tick(TIMER_PERIOD);
+        self.audio_sink
+            .play(TIMER_PERIOD, self.timers.is_sound_active(), &self.audio)?;
+        for tracer in self.tracers.iter_mut() {
+            tracer.end_frame()?;
+        }
+        self.vram.present()
+    }
+
+    /// Runs a single instruction without touching the timers
+    pub fn step(&mut self) -> VoidResultChip8 {
+        if self.halted {
+            return Ok(());
+        }
+
         self.input.tick()?;
 
-        let opcode_bytes = self
-            .memory
-            .get_range(MemoryRange::new_len(self.registers.program_counter, 2))?;
+        let pc = self.registers.program_counter;
+        self.memory.begin_instruction(pc);
+        let opcode = self.decode_with(pc, |x| self.memory.fetch(x))?;
+
+        // Read before running the instruction, since it might overwrite itself
+        let entry = if self.tracers.is_empty() {
+            None
+        } else {
+            let entry = self.trace_entry(pc, opcode)?;
+            self.memory.start_write_log();
+            Some(entry)
+        };
+
+        let result = self.interpret(opcode);
+        // Taken even if the instruction failed, so later writes aren't kept
+        let writes = self.memory.take_write_log();
+        result?;
+
+        if let Some(mut entry) = entry {
+            entry.values = self.registers.values;
+            entry.address = self.registers.address;
+            entry.stack_depth = self.stack.len();
+            entry.writes = writes;
+            for tracer in self.tracers.iter_mut() {
+                tracer.trace(&entry)?;
+            }
+        }
+        self.cycles += 1;
+
+        Ok(())
+    }
+
+    /// Decodes the instruction stored at `addr` into what it does with the current quirks,
+    /// without running it
+    pub fn decode_at(&self, addr: Address) -> ResultChip8<Opcode> {
+        self.decode_with(addr, |x| self.memory.peek(x))
+    }
+
+    fn decode_with(
+        &self,
+        addr: Address,
+        read: impl Fn(Address) -> ResultChip8<Word>,
+    ) -> ResultChip8<Opcode> {
+        let read_u16 = |addr: Address| -> ResultChip8<u16> {
+            Ok(u16::from_be_bytes([
+                read(addr)?.into(),
+                read(addr + 1u16)?.into(),
+            ]))
+        };
+
+        let value = read_u16(addr)?;
+        let opcode = if Opcode::has_operand(value) {
+            Opcode::decode_with_operand(value, read_u16(addr + 2u16)?)
+        } else {
+            Opcode::decode(value)
+        }?;
+        Ok(self.quirks.apply(opcode))
+    }
+
+    /// Moves I after FX55 and FX65 accessed the registers up to V`end`
+    fn increment_address(&mut self, end: u8) {
+        match self.quirks.load_store_increment {
+            AddressIncrement::None => {}
+            AddressIncrement::X => self.registers.address += end,
+            AddressIncrement::XPlusOne => self.registers.address += end + 1,
+        }
+    }
 
-        let opcode = Opcode::decode_bytes(&[opcode_bytes[0], opcode_bytes[1]])?;
-        self.interpret(opcode)?;
+    /// Reads a word without triggering watchpoints, since it's only looked at
+    fn read_u16(&self, addr: Address) -> ResultChip8<u16> {
+        Ok(u16::from_be_bytes([
+            self.memory.peek(addr)?.into(),
+            self.memory.peek(addr + 1u16)?.into(),
+        ]))
+    }
 
+    fn trace_entry(&self, pc: Address, opcode: Opcode) -> ResultChip8<TraceEntry> {
+        Ok(TraceEntry {
+            cycle: self.cycles,
+            program_counter: pc,
+            raw: self.read_u16(pc)?,
+            operand: match opcode.size() {
+                4 => Some(self.read_u16(pc + 2u16)?),
+                _ => None,
+            },
+            opcode,
+            values: self.registers.values,
+            address: self.registers.address,
+            stack_depth: self.stack.len(),
+            writes: Vec::new(),
+        })
+    }
+
+    /// Skips the instruction after the current one, which might take more than 2 bytes
+    fn skip_next(&mut self) -> VoidResultChip8 {
+        let next = self.registers.program_counter + 2u16;
+        let size = if Opcode::has_operand(self.read_u16(next)?) {
+            4u16
+        } else {
+            2u16
+        };
+
+        self.registers.program_counter += size;
         Ok(())
     }
 
@@ -96,33 +257,37 @@ impl CPU {
 
                 self.registers.values[left_reg as usize] = result;
 
-                match (carry, right) {
-                    (Some(c), OpcodeParam::Register(_)) => {
-                        self.registers.values[0xF] = Word::new(if c { 1 } else { 0 })
-                    }
-                    _ => {}
+                if let (Some(c), OpcodeParam::Register(_)) = (carry, right) {
+                    self.registers.values[0xF] = Word::new(if c { 1 } else { 0 })
                 };
-                Ok(())
-            }
 
-            Opcode::Shift { reg, right: true } => {
-                let value = self.registers.values[reg as usize];
-
-                self.registers.values[0xF] = value & 1;
-                self.registers.values[reg as usize] = value >> 1;
+                let logic = matches!(op, Operation::Or | Operation::And | Operation::Xor);
+                if self.quirks.logic_resets_flag && logic {
+                    self.registers.values[0xF] = Word::ZERO;
+                }
                 Ok(())
             }
 
-            Opcode::Shift { reg, right: false } => {
-                let value = self.registers.values[reg as usize];
+            Opcode::Shift { reg, source, right } => {
+                let value = if self.quirks.shift_uses_vy {
+                    self.registers.values[source as usize]
+                } else {
+                    self.registers.values[reg as usize]
+                };
 
-                self.registers.values[0xF] = (value & 0b1000_0000) >> 7;
-                self.registers.values[reg as usize] = value << 1;
+                let (result, flag) = if right {
+                    (value >> 1, value & 1)
+                } else {
+                    (value << 1, (value & 0b1000_0000) >> 7)
+                };
+
+                self.registers.values[reg as usize] = result;
+                self.registers.values[0xF] = flag;
                 Ok(())
             }
 
             Opcode::Random { reg, mask } => {
-                self.registers.values[reg as usize] = Word::new(random::<u8>()) & mask;
+                self.registers.values[reg as usize] = self.random.next_word() & mask;
                 Ok(())
             }
 
@@ -132,6 +297,11 @@ impl CPU {
                 Ok(())
             }
 
+            Opcode::AssignLongAddress(addr) => {
+                self.registers.address = addr;
+                Ok(())
+            }
+
             Opcode::AddAddress(reg) => {
                 let value = self.registers.values[reg as usize];
                 self.registers.address += value;
@@ -144,6 +314,13 @@ impl CPU {
                 Ok(())
             }
 
+            Opcode::GetLargeCharacterAddress(reg) => {
+                let value = self.registers.values[reg as usize];
+                self.registers.address =
+                    Address::new(LARGE_DIGITS_START) + Address::from(value) * 10u16;
+                Ok(())
+            }
+
             // Flow Control
             Opcode::Return => {
                 let addr = self
@@ -154,6 +331,12 @@ impl CPU {
                 Ok(())
             }
 
+            Opcode::Exit => {
+                increment_pc = false;
+                self.halted = true;
+                Ok(())
+            }
+
             Opcode::Jump(addr) => {
                 increment_pc = false;
                 self.registers.program_counter = addr;
@@ -162,7 +345,12 @@ impl CPU {
 
             Opcode::OffsetJump(addr) => {
                 increment_pc = false;
-                self.registers.program_counter = addr + self.registers.values[0];
+                let reg = if self.quirks.jump_uses_vx {
+                    (u16::from(addr) >> 8) as usize
+                } else {
+                    0
+                };
+                self.registers.program_counter = addr + self.registers.values[reg];
                 Ok(())
             }
 
@@ -175,39 +363,66 @@ impl CPU {
 
             Opcode::CondJump { left, right, cond } => {
                 if cond.evaluate(self.get_value(left), self.get_value(right)) {
-                    self.registers.program_counter += 2;
+                    self.skip_next()?;
                 }
                 Ok(())
             }
 
             // Graphics
-            Opcode::ClearScreen => self.vram.clear(),
+            Opcode::ClearScreen => self.vram.clear_selected(),
 
             Opcode::Draw {
                 x: x_reg,
                 y: y_reg,
-                height,
+                height: sprite_height,
             } => {
-                let x: usize = self.registers.values[x_reg as usize].into();
-                let y: usize = self.registers.values[y_reg as usize].into();
+                let (width, height) = (self.vram.width(), self.vram.height());
+
+                // The starting position always wraps, only the pixels drawn past the edges can be clipped
+                let x = usize::from(self.registers.values[x_reg as usize]) % width;
+                let y = usize::from(self.registers.values[y_reg as usize]) % height;
+
+                // A height of 0 draws a 16x16 sprite, stored as two bytes per row
+                let (sprite_width, sprite_height) = match sprite_height {
+                    0 => (16, 16),
+                    rows => (8, usize::from(rows)),
+                };
+                let bytes_per_row = sprite_width / 8;
 
-                let sprite = self
-                    .memory
-                    .get_range(MemoryRange::new_len(self.registers.address, height))?;
+                let sprite_len = sprite_height * bytes_per_row;
 
                 self.registers.values[0xF] = 0.into();
 
-                for dy in 0..height {
-                    let byte = sprite[dy as usize];
-                    for dx in 0..8 {
-                        let bit = ((byte >> (7 - dx)) & 1) == 1.into();
-                        if !bit {
-                            continue;
-                        }
+                // Every selected plane gets its own sprite, stored one after the other
+                let mut sprite_addr = self.registers.address;
+                for plane in 0..VideoMemory::PLANE_NUM {
+                    let plane_mask = 1 << plane;
+                    if self.vram.selected_planes() & plane_mask == 0 {
+                        continue;
+                    }
 
-                        let new_pixel = self.vram.flip(x + dx, y + (dy as usize))?;
-                        if !new_pixel {
-                            self.registers.values[0xF] = 1.into();
+                    let sprite = self
+                        .memory
+                        .get_range(MemoryRange::new_len(sprite_addr, sprite_len as u16 - 1))?;
+                    sprite_addr += sprite_len as u16;
+
+                    for dy in 0..sprite_height {
+                        for dx in 0..sprite_width {
+                            let byte = sprite[dy * bytes_per_row + dx / 8];
+                            let bit = ((byte >> (7 - dx % 8)) & 1) == 1.into();
+                            if !bit {
+                                continue;
+                            }
+
+                            let (pixel_x, pixel_y) = (x + dx, y + dy);
+                            if self.quirks.clip_sprites && (pixel_x >= width || pixel_y >= height) {
+                                continue;
+                            }
+
+                            let color = self.vram.flip(pixel_x, pixel_y, plane_mask)?;
+                            if color & plane_mask == 0 {
+                                self.registers.values[0xF] = 1.into();
+                            }
                         }
                     }
                 }
@@ -215,6 +430,13 @@ impl CPU {
                 Ok(())
             }
 
+            Opcode::ScrollDown(amount) => self.vram.scroll(0, amount.into()),
+            Opcode::ScrollUp(amount) => self.vram.scroll(0, -isize::from(amount)),
+            Opcode::ScrollRight => self.vram.scroll(4, 0),
+            Opcode::ScrollLeft => self.vram.scroll(-4, 0),
+            Opcode::SetHighResolution(high) => self.vram.set_high_resolution(high),
+            Opcode::SelectPlanes(planes) => self.vram.select_planes(planes),
+
             // IO
             Opcode::BlockOnKey(reg) => {
                 increment_pc = false;
@@ -231,10 +453,10 @@ impl CPU {
 
             Opcode::CondKeyJump { reg, cond } => {
                 let key = self.registers.values[reg as usize];
-                let down = self.input.is_down(key)?;
+                let down = self.input.is_down(key.into())?;
 
                 if cond.evaluate(down, true) {
-                    self.registers.program_counter += 2;
+                    self.skip_next()?;
                 }
 
                 Ok(())
@@ -255,6 +477,21 @@ impl CPU {
                 Ok(())
             }
 
+            // Sound
+            Opcode::LoadAudioPattern => {
+                let pattern = self.memory.get_range(MemoryRange::new_len(
+                    self.registers.address,
+                    PATTERN_LEN as u16 - 1,
+                ))?;
+                self.audio.bits.copy_from_slice(&pattern);
+                Ok(())
+            }
+
+            Opcode::SetPitch(reg) => {
+                self.audio.pitch = self.registers.values[reg as usize];
+                Ok(())
+            }
+
             // Misc
             Opcode::Nop => Ok(()),
 
@@ -276,6 +513,36 @@ impl CPU {
                     let addr = self.registers.address + i;
                     self.memory.set(addr, self.registers.values[i as usize])?;
                 }
+
+                self.increment_address(end);
+                Ok(())
+            }
+
+            Opcode::DumpFlagRegisters(end) => {
+                let end = usize::from(end);
+                self.registers.rpl_flags[..=end].copy_from_slice(&self.registers.values[..=end]);
+                Ok(())
+            }
+
+            Opcode::LoadFlagRegisters(end) => {
+                let end = usize::from(end);
+                self.registers.values[..=end].copy_from_slice(&self.registers.rpl_flags[..=end]);
+                Ok(())
+            }
+
+            Opcode::DumpValueRange { start, end } => {
+                for (i, reg) in register_range(start, end).enumerate() {
+                    let addr = self.registers.address + i as u16;
+                    self.memory.set(addr, self.registers.values[reg])?;
+                }
+                Ok(())
+            }
+
+            Opcode::LoadValueRange { start, end } => {
+                for (i, reg) in register_range(start, end).enumerate() {
+                    let addr = self.registers.address + i as u16;
+                    self.registers.values[reg] = self.memory.get(addr)?;
+                }
                 Ok(())
             }
 
@@ -284,6 +551,8 @@ impl CPU {
                     let addr = self.registers.address + i;
                     self.registers.values[i as usize] = self.memory.get(addr)?;
                 }
+
+                self.increment_address(end);
                 Ok(())
             }
 
@@ -291,7 +560,7 @@ impl CPU {
         }?;
 
         if increment_pc {
-            self.registers.program_counter += 2u16;
+            self.registers.program_counter += opcode.size();
         }
 
         Ok(())
@@ -304,3 +573,16 @@ impl CPU {
         }
     }
 }
+
+/// The registers from `start` to `end`, both inclusive, going backwards if `start` is greater
+fn register_range(
+    start: ValueRegisterIndex,
+    end: ValueRegisterIndex,
+) -> Box<dyn Iterator<Item = usize>> {
+    let (start, end) = (usize::from(start), usize::from(end));
+    if start <= end {
+        Box::new(start..=end)
+    } else {
+        Box::new((end..=start).rev())
+    }
+}
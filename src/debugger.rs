@@ -0,0 +1,614 @@
+use crate::core::{Address, Error, ResultChip8, VoidResultChip8, Word};
+use crate::cpu::CPU;
+use crate::input::{ScriptedInput, KEY_NUM};
+use crate::memory::{MemoryAccess, MemoryRange, WatchKind, Watchpoint, WriteMemory};
+use crate::opcodes::Opcode;
+use std::convert::TryFrom;
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+const HEXDUMP_ROW_LEN: u16 = 0x10;
+const DEFAULT_HEXDUMP_LEN: u16 = 0x40;
+const DEFAULT_DISASSEMBLY_LEN: usize = 10;
+// How many bytes before the PC the disassembly starts at by default
+const DISASSEMBLY_LOOKBEHIND: u16 = 6;
+
+const HELP: &str = "\
+All numbers are hexadecimal.
+  s, step [n]          Run n instructions, 1 by default
+  n, next              Run one instruction, running whole subroutines on calls
+  finish               Run until the current subroutine returns
+  c, continue          Run until a breakpoint is hit or the program exits
+  b, break <addr>      Stop before the instruction at <addr> runs
+  bo, break-op <kind>  Stop before any instruction of a kind runs, like Draw or Call
+  d, delete <bp>       Delete the breakpoint on an address or opcode kind
+  w, watch <r|w|rw|x> <start> [end] [= value]
+                       Stop after an instruction accesses memory in a range, optionally
+                       only when a specific value is read or written
+  dw, delete-watch <id>
+                       Delete a watchpoint
+  bl, breakpoints      List all breakpoints and watchpoints
+  r, regs              Show the registers
+  stack                Show the call stack
+  timers               Show the delay and sound timers
+  x <addr> [len]       Show a hexdump of memory
+  dis [addr] [count]   Disassemble instructions, around the PC by default
+  screen               Show the screen
+  set <reg> <value>    Change V0-VF, I, PC, DT or ST
+  poke <addr> <bytes>  Write bytes to memory
+  a, asm <addr> <instruction>
+                       Write an instruction to memory, typed the way dis shows it
+  key <key> <up|down>  Release or hold one of the machine's keys
+  h, help              Show this text
+  q, quit              Exit the debugger";
+
+/// Why the debugger gave back control after running the CPU
+#[derive(Debug)]
+pub enum StopReason {
+    Finished,
+    Breakpoint(Address),
+    OpcodeBreakpoint(&'static str),
+    Watchpoint(MemoryAccess),
+    Halted,
+    Interrupted,
+    Error(Error),
+}
+
+/// Runs a CPU under the control of text commands, like a REPL.
+///
+/// Keys are controlled with commands too, since the terminal is busy reading them.
+pub struct Debugger {
+    pub cpu: CPU,
+    pub instructions_per_frame: u32,
+    /// Can be set from another thread, like a Ctrl+C handler, to stop the CPU while it runs
+    pub interrupt: Arc<AtomicBool>,
+    breakpoints: Vec<Address>,
+    opcode_breakpoints: Vec<&'static str>,
+    keys: [bool; KEY_NUM],
+    frame_instructions: u32,
+}
+
+impl Debugger {
+    pub fn new(mut cpu: CPU, instructions_per_frame: u32) -> Debugger {
+        cpu.input = Box::new(ScriptedInput::new());
+        Debugger {
+            cpu,
+            instructions_per_frame: instructions_per_frame.max(1),
+            interrupt: Arc::new(AtomicBool::new(false)),
+            breakpoints: Vec::new(),
+            opcode_breakpoints: Vec::new(),
+            keys: [false; KEY_NUM],
+            frame_instructions: 0,
+        }
+    }
+
+    /// Runs a single command, returning `false` if the debugger should exit
+    pub fn execute(&mut self, line: &str, out: &mut impl Write) -> ResultChip8<bool> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let (command, args) = match words.split_first() {
+            None => return Ok(true),
+            Some(x) => x,
+        };
+
+        match *command {
+            "s" | "step" => {
+                let mut remaining: u64 = match args.first() {
+                    None => 1,
+                    Some(x) => parse_hex(x)?,
+                };
+                if remaining == 0 {
+                    return Ok(true);
+                }
+
+                let reason = self.run_until(|_| {
+                    remaining -= 1;
+                    remaining == 0
+                });
+                self.print_stop(reason, out)?;
+            }
+
+            "n" | "next" => {
+                let reason = match self.cpu.decode_at(self.cpu.registers.program_counter) {
+                    Ok(opcode @ Opcode::Call(_)) => {
+                        let depth = self.cpu.stack.len();
+                        let return_addr = self.cpu.registers.program_counter + opcode.size();
+                        self.run_until(|cpu| {
+                            cpu.stack.len() == depth && cpu.registers.program_counter == return_addr
+                        })
+                    }
+                    _ => self.run_until(|_| true),
+                };
+                self.print_stop(reason, out)?;
+            }
+
+            "finish" => {
+                let depth = self.cpu.stack.len();
+                if depth == 0 {
+                    return Err(Error::new_str("Not inside a subroutine"));
+                }
+
+                let reason = self.run_until(|cpu| cpu.stack.len() < depth);
+                self.print_stop(reason, out)?;
+            }
+
+            "c" | "continue" => {
+                let reason = self.run_until(|_| false);
+                self.print_stop(reason, out)?;
+            }
+
+            "b" | "break" => {
+                let addr = Address::new(parse_hex::<u16>(arg(args, 0, "address")?)?);
+                self.add_breakpoint(addr);
+                writeln!(out, "Breakpoint at {}", addr)?;
+            }
+
+            "bo" | "break-op" => {
+                let name = arg(args, 0, "opcode kind")?;
+                let kinds = opcode_kinds();
+                let kind = match kinds.iter().find(|x| **x == name) {
+                    Some(x) => *x,
+                    None => {
+                        let mut message = format!("Unknown opcode kind {}", name);
+                        if let Some(x) = kinds.iter().find(|x| x.eq_ignore_ascii_case(name)) {
+                            message += &format!(", did you mean {}?", x);
+                        }
+                        return Err(Error::new(message));
+                    }
+                };
+
+                if !self.opcode_breakpoints.contains(&kind) {
+                    self.opcode_breakpoints.push(kind);
+                }
+                writeln!(out, "Breakpoint on {}", kind)?;
+            }
+
+            "d" | "delete" => {
+                let target = arg(args, 0, "breakpoint")?;
+                let before = self.breakpoints.len() + self.opcode_breakpoints.len();
+
+                self.opcode_breakpoints.retain(|x| *x != target);
+                if let Ok(addr) = parse_hex::<u16>(target) {
+                    self.remove_breakpoint(Address::new(addr));
+                }
+
+                if before == self.breakpoints.len() + self.opcode_breakpoints.len() {
+                    return Err(Error::new(format!("No breakpoint on {}", target)));
+                }
+            }
+
+            "w" | "watch" => {
+                let kind = match arg(args, 0, "access kind")? {
+                    "r" => WatchKind::Read,
+                    "w" => WatchKind::Write,
+                    "rw" => WatchKind::ReadWrite,
+                    "x" => WatchKind::Execute,
+                    x => return Err(Error::new(format!("Expected r, w, rw or x, got {}", x))),
+                };
+
+                let start = parse_hex::<u16>(arg(args, 1, "address")?)?;
+                let mut rest = &args[2..];
+                let end = match rest.first() {
+                    Some(x) if *x != "=" => {
+                        rest = &rest[1..];
+                        parse_hex::<u16>(x)?
+                    }
+                    _ => start,
+                };
+                if end < start {
+                    return Err(Error::new_str("The end of the range is before its start"));
+                }
+
+                let mut watchpoint = Watchpoint::new(kind, MemoryRange::new(start, end));
+                match rest {
+                    [] => {}
+                    ["=", value] => watchpoint.value = Some(Word::new(parse_hex::<u8>(value)?)),
+                    _ => return Err(Error::new_str("Expected = followed by a value")),
+                };
+
+                let id = self.cpu.memory.add_watchpoint(watchpoint);
+                writeln!(out, "Watchpoint #{:X}", id)?;
+            }
+
+            "dw" | "delete-watch" => {
+                let id = parse_hex::<usize>(arg(args, 0, "watchpoint")?)?;
+                if !self.cpu.memory.remove_watchpoint(id) {
+                    return Err(Error::new(format!("No watchpoint #{:X}", id)));
+                }
+            }
+
+            "bl" | "breakpoints" => {
+                for addr in self.breakpoints.iter() {
+                    writeln!(out, "{}", addr)?;
+                }
+                for kind in self.opcode_breakpoints.iter() {
+                    writeln!(out, "{}", kind)?;
+                }
+                for (id, watchpoint) in self.cpu.memory.watchpoints() {
+                    write!(out, "#{:X} {} {}", id, watchpoint.kind, watchpoint.range)?;
+                    if let Some(value) = watchpoint.value {
+                        write!(out, " = {}", value)?;
+                    }
+                    writeln!(out)?;
+                }
+            }
+
+            "r" | "regs" => self.print_registers(out)?,
+
+            "stack" => {
+                if self.cpu.stack.is_empty() {
+                    writeln!(out, "Empty")?;
+                }
+                for (i, addr) in self.cpu.stack.iter().enumerate().rev() {
+                    writeln!(out, "#{:X} {}", i, addr)?;
+                }
+            }
+
+            "timers" => writeln!(
+                out,
+                "DT = {}  ST = {}",
+                self.cpu.timers.delay_timer, self.cpu.timers.sound_timer
+            )?,
+
+            "x" => {
+                let start = parse_hex::<u16>(arg(args, 0, "address")?)?;
+                let len = match args.get(1) {
+                    None => DEFAULT_HEXDUMP_LEN,
+                    Some(x) => parse_hex(x)?,
+                };
+                self.print_hexdump(Address::new(start), len, out)?;
+            }
+
+            "dis" => {
+                let start = match args.first() {
+                    Some(x) => Address::new(parse_hex::<u16>(x)?),
+                    None => {
+                        let pc: u16 = self.cpu.registers.program_counter.into();
+                        Address::new(pc.saturating_sub(DISASSEMBLY_LOOKBEHIND))
+                    }
+                };
+                let count = match args.get(1) {
+                    None => DEFAULT_DISASSEMBLY_LEN,
+                    Some(x) => parse_hex(x)?,
+                };
+                self.print_disassembly(start, count, out)?;
+            }
+
+            "screen" => self.print_screen(out)?,
+
+            "set" => {
+                let reg = arg(args, 0, "register")?.to_uppercase();
+                let value = parse_hex::<u16>(arg(args, 1, "value")?)?;
+                self.set_register(&reg, value)?;
+            }
+
+            "poke" => {
+                let start = Address::new(parse_hex::<u16>(arg(args, 0, "address")?)?);
+                if args.len() < 2 {
+                    return Err(Error::new_str("Missing bytes to write"));
+                }
+
+                for (i, x) in args[1..].iter().enumerate() {
+                    let value = Word::new(parse_hex::<u8>(x)?);
+                    self.cpu.memory.set(start + i as u16, value)?;
+                }
+            }
+
+            "a" | "asm" => {
+                let start = Address::new(parse_hex::<u16>(arg(args, 0, "address")?)?);
+                if args.len() < 2 {
+                    return Err(Error::new_str("Missing an instruction to write"));
+                }
+
+                let opcode: Opcode = args[1..].join(" ").parse()?;
+                for (i, x) in opcode.encode_bytes()?.into_iter().enumerate() {
+                    self.cpu.memory.set(start + i as u16, Word::new(x))?;
+                }
+                writeln!(out, "{}: {}", start, opcode)?;
+            }
+
+            "key" => {
+                let key = parse_hex::<usize>(arg(args, 0, "key")?)?;
+                if key >= KEY_NUM {
+                    return Err(Error::new(format!("Invalid key {:X}", key)));
+                }
+
+                self.keys[key] = match arg(args, 1, "up or down")? {
+                    "down" => true,
+                    "up" => false,
+                    x => return Err(Error::new(format!("Expected up or down, got {}", x))),
+                };
+                self.update_keys()?;
+            }
+
+            "h" | "help" => writeln!(out, "{}", HELP)?,
+
+            "q" | "quit" => return Ok(false),
+
+            x => return Err(Error::new(format!("Unknown command {}, try help", x))),
+        };
+
+        Ok(true)
+    }
+
+    /// Stops the CPU before the instruction at `addr` runs
+    pub fn add_breakpoint(&mut self, addr: Address) {
+        if !self.breakpoints.contains(&addr) {
+            self.breakpoints.push(addr);
+        }
+    }
+
+    /// Returns whether there was a breakpoint at `addr`
+    pub fn remove_breakpoint(&mut self, addr: Address) -> bool {
+        let before = self.breakpoints.len();
+        self.breakpoints.retain(|x| *x != addr);
+        before != self.breakpoints.len()
+    }
+
+    /// Runs instructions until `done` returns true after one of them, or something else stops
+    /// the CPU. Breakpoints are only checked after the first instruction, so that it's possible
+    /// to continue from one.
+    pub fn run_until(&mut self, mut done: impl FnMut(&CPU) -> bool) -> StopReason {
+        loop {
+            if self.cpu.halted {
+                return StopReason::Halted;
+            }
+
+            if let Err(err) = self.step() {
+                return StopReason::Error(err);
+            }
+
+            if let Some(access) = self.cpu.memory.take_watch_hit() {
+                return StopReason::Watchpoint(access);
+            }
+
+            if done(&self.cpu) {
+                return StopReason::Finished;
+            }
+
+            let pc = self.cpu.registers.program_counter;
+            if self.breakpoints.contains(&pc) {
+                return StopReason::Breakpoint(pc);
+            }
+
+            if let Ok(opcode) = self.cpu.decode_at(pc) {
+                let kind = opcode_kind(&opcode);
+                if self.opcode_breakpoints.contains(&kind) {
+                    return StopReason::OpcodeBreakpoint(kind);
+                }
+            }
+
+            if self.interrupt.swap(false, Ordering::SeqCst) {
+                return StopReason::Interrupted;
+            }
+        }
+    }
+
+    /// Runs one instruction, ending the frame once enough instructions ran
+    fn step(&mut self) -> VoidResultChip8 {
+        self.cpu.step()?;
+
+        self.frame_instructions += 1;
+        if self.frame_instructions >= self.instructions_per_frame {
+            self.frame_instructions = 0;
+            self.cpu.end_frame()?;
+        }
+
+        Ok(())
+    }
+
+    fn update_keys(&mut self) -> VoidResultChip8 {
+        let mut input = ScriptedInput::new();
+        for (key, down) in self.keys.iter().enumerate() {
+            if *down {
+                input.hold(key)?;
+            }
+        }
+
+        self.cpu.input = Box::new(input);
+        Ok(())
+    }
+
+    fn set_register(&mut self, reg: &str, value: u16) -> VoidResultChip8 {
+        let word = || {
+            u8::try_from(value)
+                .map(Word::new)
+                .map_err(|_| Error::new(format!("{:X} doesn't fit in {}", value, reg)))
+        };
+
+        match reg {
+            "I" => self.cpu.registers.address = Address::new(value),
+            "PC" => self.cpu.registers.program_counter = Address::new(value),
+            "DT" => self.cpu.timers.delay_timer = word()?,
+            "ST" => self.cpu.timers.sound_timer = word()?,
+            x if x.len() == 2 && x.starts_with('V') => {
+                let index = usize::from_str_radix(&x[1..], 16)
+                    .map_err(|_| Error::new(format!("Unknown register {}", x)))?;
+                self.cpu.registers.values[index] = word()?;
+            }
+            x => return Err(Error::new(format!("Unknown register {}", x))),
+        };
+
+        Ok(())
+    }
+
+    fn print_stop(&self, reason: StopReason, out: &mut impl Write) -> VoidResultChip8 {
+        match reason {
+            StopReason::Finished => {}
+            StopReason::Breakpoint(addr) => writeln!(out, "Breakpoint at {}", addr)?,
+            StopReason::OpcodeBreakpoint(kind) => writeln!(out, "Breakpoint on {}", kind)?,
+            StopReason::Watchpoint(access) => writeln!(
+                out,
+                "Watchpoint #{:X}: {:?} of {} at {} by the instruction at {}",
+                access.watchpoint, access.kind, access.value, access.addr, access.program_counter
+            )?,
+            StopReason::Halted => writeln!(out, "The program exited")?,
+            StopReason::Interrupted => writeln!(out, "Interrupted")?,
+            StopReason::Error(err) => writeln!(out, "Error: {}", err)?,
+        };
+
+        self.print_disassembly(self.cpu.registers.program_counter, 1, out)
+    }
+
+    fn print_registers(&self, out: &mut impl Write) -> VoidResultChip8 {
+        let registers = &self.cpu.registers;
+        for (row, values) in registers.values.chunks(8).enumerate() {
+            for (i, value) in values.iter().enumerate() {
+                write!(out, "V{:X} = {}  ", row * 8 + i, value)?;
+            }
+            writeln!(out)?;
+        }
+
+        writeln!(
+            out,
+            "I = {}  PC = {}  SP = {:X}",
+            registers.address,
+            registers.program_counter,
+            self.cpu.stack.len()
+        )?;
+        Ok(())
+    }
+
+    fn print_hexdump(&self, start: Address, len: u16, out: &mut impl Write) -> VoidResultChip8 {
+        for row_start in (0..len).step_by(HEXDUMP_ROW_LEN.into()) {
+            let row_addr = start + row_start;
+            write!(out, "{}:", row_addr)?;
+
+            for i in row_start..len.min(row_start + HEXDUMP_ROW_LEN) {
+                match self.cpu.memory.peek(start + i) {
+                    Ok(x) => write!(out, " {}", x)?,
+                    Err(_) => write!(out, " ??")?,
+                };
+            }
+            writeln!(out)?;
+        }
+
+        Ok(())
+    }
+
+    fn print_disassembly(
+        &self,
+        start: Address,
+        count: usize,
+        out: &mut impl Write,
+    ) -> VoidResultChip8 {
+        let mut addr = start;
+        for _ in 0..count {
+            let marker = if addr == self.cpu.registers.program_counter {
+                "=>"
+            } else if self.breakpoints.contains(&addr) {
+                " *"
+            } else {
+                "  "
+            };
+
+            match self.cpu.decode_at(addr) {
+                Ok(opcode) => {
+                    writeln!(out, "{} {}: {}", marker, addr, opcode)?;
+                    addr += opcode.size();
+                }
+                Err(err) => {
+                    writeln!(out, "{} {}: ??? ({})", marker, addr, err)?;
+                    addr += 2u16;
+                }
+            };
+        }
+
+        Ok(())
+    }
+
+    fn print_screen(&self, out: &mut impl Write) -> VoidResultChip8 {
+        let vram = &self.cpu.vram;
+        for y in 0..vram.height() {
+            let mut line = String::with_capacity(vram.width());
+            for x in 0..vram.width() {
+                line.push(match vram.get(x, y)? {
+                    0 => '.',
+                    1 => '#',
+                    2 => '+',
+                    _ => '@',
+                });
+            }
+            writeln!(out, "{}", line)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The name of the opcode's variant, like `Draw` or `Call`
+pub fn opcode_kind(opcode: &Opcode) -> &'static str {
+    match opcode {
+        Opcode::Assign { .. } => "Assign",
+        Opcode::Shift { .. } => "Shift",
+        Opcode::Random { .. } => "Random",
+        Opcode::AssignAddress(_) => "AssignAddress",
+        Opcode::AssignLongAddress(_) => "AssignLongAddress",
+        Opcode::AddAddress(_) => "AddAddress",
+        Opcode::GetCharacterAddress(_) => "GetCharacterAddress",
+        Opcode::GetLargeCharacterAddress(_) => "GetLargeCharacterAddress",
+        Opcode::Return => "Return",
+        Opcode::Exit => "Exit",
+        Opcode::Jump(_) => "Jump",
+        Opcode::OffsetJump(_) => "OffsetJump",
+        Opcode::Call(_) => "Call",
+        Opcode::CallNative(_) => "CallNative",
+        Opcode::CondJump { .. } => "CondJump",
+        Opcode::ClearScreen => "ClearScreen",
+        Opcode::Draw { .. } => "Draw",
+        Opcode::ScrollDown(_) => "ScrollDown",
+        Opcode::ScrollUp(_) => "ScrollUp",
+        Opcode::ScrollRight => "ScrollRight",
+        Opcode::ScrollLeft => "ScrollLeft",
+        Opcode::SetHighResolution(_) => "SetHighResolution",
+        Opcode::SelectPlanes(_) => "SelectPlanes",
+        Opcode::BlockOnKey(_) => "BlockOnKey",
+        Opcode::CondKeyJump { .. } => "CondKeyJump",
+        Opcode::GetDelayTimer(_) => "GetDelayTimer",
+        Opcode::SetTimer { .. } => "SetTimer",
+        Opcode::LoadAudioPattern => "LoadAudioPattern",
+        Opcode::SetPitch(_) => "SetPitch",
+        Opcode::Nop => "Nop",
+        Opcode::WriteBCD(_) => "WriteBCD",
+        Opcode::DumpValueRegisters(_) => "DumpValueRegisters",
+        Opcode::LoadValueRegisters(_) => "LoadValueRegisters",
+        Opcode::DumpFlagRegisters(_) => "DumpFlagRegisters",
+        Opcode::LoadFlagRegisters(_) => "LoadFlagRegisters",
+        Opcode::DumpValueRange { .. } => "DumpValueRange",
+        Opcode::LoadValueRange { .. } => "LoadValueRange",
+    }
+}
+
+/// Every name `opcode_kind` returns for the instructions that decode, in encoding order
+pub fn opcode_kinds() -> Vec<&'static str> {
+    let mut kinds = Vec::new();
+    for value in 0..=0xFFFFu16 {
+        let opcode = if Opcode::has_operand(value) {
+            Opcode::decode_with_operand(value, 0)
+        } else {
+            Opcode::decode(value)
+        };
+
+        if let Ok(opcode) = opcode {
+            let kind = opcode_kind(&opcode);
+            if !kinds.contains(&kind) {
+                kinds.push(kind);
+            }
+        }
+    }
+    kinds
+}
+
+fn arg<'a>(args: &[&'a str], index: usize, name: &str) -> ResultChip8<&'a str> {
+    args.get(index)
+        .copied()
+        .ok_or_else(|| Error::new(format!("Missing {}", name)))
+}
+
+fn parse_hex<T: TryFrom<u64>>(text: &str) -> ResultChip8<T> {
+    let digits = text.trim_start_matches("0x");
+    u64::from_str_radix(digits, 16)
+        .ok()
+        .and_then(|x| T::try_from(x).ok())
+        .ok_or_else(|| Error::new(format!("Invalid hexadecimal number {}", text)))
+}
@@ -0,0 +1,313 @@
+use crate::core::{Address, Error, VoidResultChip8};
+use crate::opcodes::{Condition, Opcode, OpcodeParam, Operation, Timer};
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+
+pub struct Line {
+    pub address: Address,
+    pub content: LineContent,
+}
+
+pub enum LineContent {
+    Opcode(u16, Opcode),
+    Invalid(u16, Error),
+    LoneByte(u8),
+    /// A byte that no path through the code reaches, like sprites or other data
+    Data(u8),
+}
+
+/// Decodes every instruction of `rom` in sequence, assuming it's loaded at `start`.
+/// If `offset` is set, the first byte is skipped so that misaligned code can be read.
+pub fn disassemble(rom: &[u8], start: Address, offset: bool) -> Vec<Line> {
+    let mut lines = Vec::with_capacity(rom.len() / 2 + 1);
+
+    let mut i = 0;
+    while i < rom.len() {
+        let address = start + i;
+
+        let content = if i == 0 && offset {
+            LineContent::LoneByte(rom[i])
+        } else {
+            decode(rom, i)
+        };
+
+        i += size(&content);
+        lines.push(Line { address, content });
+    }
+
+    lines
+}
+
+/// Decodes only the instructions that can run, by following every path through the code from
+/// `start`, where `rom` is loaded. Everything else is returned as data, one byte at a time.
+///
+/// Since jumps are followed wherever they go, code that starts on an odd address is found too.
+/// The targets of `OffsetJump` depend on a register, so they're guessed: a table of jumps at
+/// the base address is assumed, and every jump in a row from there is followed.
+pub fn disassemble_flow(rom: &[u8], start: Address) -> Vec<Line> {
+    let mut code: Vec<Option<LineContent>> = (0..rom.len()).map(|_| None).collect();
+    let mut covered = vec![false; rom.len()];
+
+    let index = |addr: Address| -> Option<usize> {
+        let addr = usize::from(u16::from(addr));
+        let start = usize::from(u16::from(start));
+        addr.checked_sub(start).filter(|x| *x < rom.len())
+    };
+
+    let mut pending = vec![0usize];
+    while let Some(mut i) = pending.pop() {
+        while i < rom.len() && code[i].is_none() {
+            let content = decode(rom, i);
+            let len = size(&content);
+            for x in covered.iter_mut().skip(i).take(len) {
+                *x = true;
+            }
+
+            let opcode = match &content {
+                LineContent::Opcode(_, x) => *x,
+                _ => {
+                    code[i] = Some(content);
+                    break;
+                }
+            };
+            code[i] = Some(content);
+
+            let next = i + len;
+            let mut push = |addr: Address| {
+                if let Some(x) = index(addr) {
+                    pending.push(x);
+                }
+            };
+
+            match opcode {
+                Opcode::Jump(addr) => {
+                    push(addr);
+                    break;
+                }
+                Opcode::Return | Opcode::Exit => break,
+                Opcode::Call(addr) => push(addr),
+                Opcode::OffsetJump(addr) => {
+                    let mut entry = addr;
+                    while let Some(x) = index(entry) {
+                        match decode(rom, x) {
+                            LineContent::Opcode(_, Opcode::Jump(_)) => pending.push(x),
+                            _ => break,
+                        };
+                        entry += 2u16;
+                    }
+                    break;
+                }
+                // Both the next instruction and the one after it can run
+                Opcode::CondJump { .. } | Opcode::CondKeyJump { .. } if next < rom.len() => {
+                    pending.push(next + size(&decode(rom, next)));
+                }
+                _ => {}
+            };
+
+            i = next;
+        }
+    }
+
+    let mut lines = Vec::with_capacity(rom.len());
+    for (i, content) in code.into_iter().enumerate() {
+        let content = match content {
+            Some(x) => x,
+            None if !covered[i] => LineContent::Data(rom[i]),
+            None => continue,
+        };
+
+        lines.push(Line {
+            address: start + i,
+            content,
+        });
+    }
+
+    lines
+}
+
+/// Writes the code found by `disassemble_flow` as source for `chip8 asm`, so that assembling
+/// it gives back the same ROM. Every address that is jumped to, called or loaded into I gets
+/// a label, so that the code can be changed without breaking them.
+pub fn write_source(rom: &[u8], start: Address, out: &mut impl Write) -> VoidResultChip8 {
+    // Instructions that overlap with an earlier one can't be written separately,
+    // so whatever part of them the earlier one doesn't cover is kept as bytes
+    let mut lines = Vec::new();
+    let mut end = 0;
+    for line in disassemble_flow(rom, start) {
+        let offset = usize::from(u16::from(line.address) - u16::from(start));
+        let line_end = offset + size(&line.content);
+        if offset >= end {
+            lines.push(line);
+        } else {
+            lines.extend((end..line_end).map(|i| Line {
+                address: start + i,
+                content: LineContent::LoneByte(rom[i]),
+            }));
+        }
+        end = end.max(line_end);
+    }
+
+    let starts: HashSet<Address> = lines.iter().map(|x| x.address).collect();
+    let mut labels: HashMap<Address, (usize, String)> = HashMap::new();
+    for line in lines.iter() {
+        let opcode = match &line.content {
+            LineContent::Opcode(_, x) => x,
+            _ => continue,
+        };
+
+        let (addr, priority, prefix) = match opcode {
+            Opcode::Call(x) => (*x, 0, "sub"),
+            Opcode::OffsetJump(x) => (*x, 1, "table"),
+            Opcode::Jump(x) => (*x, 2, "label"),
+            Opcode::AssignAddress(x) | Opcode::AssignLongAddress(x) => (*x, 3, "data"),
+            _ => continue,
+        };
+
+        if !starts.contains(&addr) {
+            continue;
+        }
+        if labels.get(&addr).is_none_or(|(x, _)| priority < *x) {
+            labels.insert(addr, (priority, format!("{}_{}", prefix, addr)));
+        }
+    }
+
+    let label = |addr: Address| match labels.get(&addr) {
+        Some((_, x)) => x.clone(),
+        None => format!("0x{}", addr),
+    };
+
+    for line in lines.iter() {
+        if let Some((_, name)) = labels.get(&line.address) {
+            writeln!(out, "{}:", name)?;
+        }
+
+        match &line.content {
+            LineContent::Opcode(_, x) => writeln!(out, "    {}", mnemonic(x, label))?,
+            LineContent::Invalid(value, _) => {
+                let [high, low] = value.to_be_bytes();
+                writeln!(out, "    db 0x{:02X}, 0x{:02X}", high, low)?
+            }
+            LineContent::LoneByte(x) => writeln!(out, "    db 0x{:02X}", x)?,
+            LineContent::Data(x) => {
+                let bits: String = (0..8)
+                    .map(|i| if x & (0x80 >> i) != 0 { '#' } else { '.' })
+                    .collect();
+                writeln!(out, "    db 0x{:02X} ; {}", x, bits)?
+            }
+        };
+    }
+
+    Ok(())
+}
+
+/// Writes an instruction in the assembler's syntax, using `label` to name addresses
+fn mnemonic(opcode: &Opcode, label: impl Fn(Address) -> String) -> String {
+    let param = |x: &OpcodeParam| match x {
+        OpcodeParam::Register(x) => format!("V{:X}", x),
+        OpcodeParam::Immediate(x) => format!("0x{}", x),
+    };
+
+    match opcode {
+        Opcode::Assign {
+            left_reg,
+            right,
+            op,
+        } => {
+            let name = match op {
+                Operation::None => "LD",
+                Operation::Add => "ADD",
+                Operation::Sub => "SUB",
+                Operation::ReverseSub => "SUBN",
+                Operation::Or => "OR",
+                Operation::And => "AND",
+                Operation::Xor => "XOR",
+            };
+            format!("{} V{:X}, {}", name, left_reg, param(right))
+        }
+        Opcode::Shift { reg, source, right } => {
+            let name = if *right { "SHR" } else { "SHL" };
+            format!("{} V{:X}, V{:X}", name, reg, source)
+        }
+        Opcode::Random { reg, mask } => format!("RND V{:X}, 0x{}", reg, mask),
+
+        Opcode::AssignAddress(x) => format!("LD I, {}", label(*x)),
+        Opcode::AssignLongAddress(x) => format!("LD I, LONG {}", label(*x)),
+        Opcode::AddAddress(x) => format!("ADD I, V{:X}", x),
+        Opcode::GetCharacterAddress(x) => format!("LD F, V{:X}", x),
+        Opcode::GetLargeCharacterAddress(x) => format!("LD HF, V{:X}", x),
+
+        Opcode::Return => "RET".to_owned(),
+        Opcode::Exit => "EXIT".to_owned(),
+        Opcode::Jump(x) => format!("JP {}", label(*x)),
+        Opcode::OffsetJump(x) => format!("JP V0, {}", label(*x)),
+        Opcode::Call(x) => format!("CALL {}", label(*x)),
+        Opcode::CallNative(x) => format!("SYS {}", label(*x)),
+        Opcode::CondJump { left, right, cond } => {
+            let name = match cond {
+                Condition::Equal => "SE",
+                Condition::NotEqual => "SNE",
+            };
+            format!("{} {}, {}", name, param(left), param(right))
+        }
+
+        Opcode::ClearScreen => "CLS".to_owned(),
+        Opcode::Draw { x, y, height } => format!("DRW V{:X}, V{:X}, {}", x, y, height),
+        Opcode::ScrollDown(x) => format!("SCD {}", x),
+        Opcode::ScrollUp(x) => format!("SCU {}", x),
+        Opcode::ScrollRight => "SCR".to_owned(),
+        Opcode::ScrollLeft => "SCL".to_owned(),
+        Opcode::SetHighResolution(true) => "HIGH".to_owned(),
+        Opcode::SetHighResolution(false) => "LOW".to_owned(),
+        Opcode::SelectPlanes(x) => format!("PLANE {}", x),
+
+        Opcode::BlockOnKey(x) => format!("LD V{:X}, K", x),
+        Opcode::CondKeyJump { reg, cond } => match cond {
+            Condition::Equal => format!("SKP V{:X}", reg),
+            Condition::NotEqual => format!("SKNP V{:X}", reg),
+        },
+
+        Opcode::GetDelayTimer(x) => format!("LD V{:X}, DT", x),
+        Opcode::SetTimer { reg, timer } => match timer {
+            Timer::Delay => format!("LD DT, V{:X}", reg),
+            Timer::Sound => format!("LD ST, V{:X}", reg),
+        },
+
+        Opcode::LoadAudioPattern => "AUDIO".to_owned(),
+        Opcode::SetPitch(x) => format!("PITCH V{:X}", x),
+
+        Opcode::Nop => "NOP".to_owned(),
+        Opcode::WriteBCD(x) => format!("LD B, V{:X}", x),
+        Opcode::DumpValueRegisters(x) => format!("LD [I], V{:X}", x),
+        Opcode::LoadValueRegisters(x) => format!("LD V{:X}, [I]", x),
+        Opcode::DumpFlagRegisters(x) => format!("LD R, V{:X}", x),
+        Opcode::LoadFlagRegisters(x) => format!("LD V{:X}, R", x),
+        Opcode::DumpValueRange { start, end } => format!("SAVE V{:X}, V{:X}", start, end),
+        Opcode::LoadValueRange { start, end } => format!("LOAD V{:X}, V{:X}", start, end),
+    }
+}
+
+fn decode(rom: &[u8], i: usize) -> LineContent {
+    if i + 1 >= rom.len() {
+        return LineContent::LoneByte(rom[i]);
+    }
+
+    let value = u16::from_be_bytes([rom[i], rom[i + 1]]);
+    let decoded = if Opcode::has_operand(value) && i + 3 < rom.len() {
+        Opcode::decode_with_operand(value, u16::from_be_bytes([rom[i + 2], rom[i + 3]]))
+    } else {
+        Opcode::decode(value)
+    };
+
+    match decoded {
+        Ok(x) => LineContent::Opcode(value, x),
+        Err(x) => LineContent::Invalid(value, x),
+    }
+}
+
+fn size(content: &LineContent) -> usize {
+    match content {
+        LineContent::LoneByte(_) | LineContent::Data(_) => 1,
+        LineContent::Opcode(_, x) => usize::from(x.size()),
+        LineContent::Invalid(_, _) => 2,
+    }
+}
@@ -7,102 +7,313 @@ pub trait VideoListener {
         Ok(())
     }
 
-    fn on_change(&mut self, _x: usize, _y: usize, _value: bool) -> VoidResultChip8 {
+    /// `color` has one bit per plane, set if the pixel is on in that plane
+    fn on_change(&mut self, _x: usize, _y: usize, _color: u8) -> VoidResultChip8 {
         Ok(())
     }
 
     fn on_clear(&mut self) -> VoidResultChip8 {
         Ok(())
     }
+
+    /// Called at the end of every frame, changes can be shown all at once instead of one by one
+    fn on_frame(&mut self) -> VoidResultChip8 {
+        Ok(())
+    }
+
+    /// Called when the resolution changes, the screen is cleared right after
+    fn on_resize(&mut self, _width: usize, _height: usize) -> VoidResultChip8 {
+        Ok(())
+    }
+
     fn on_detach(&mut self, _memory: &mut VideoMemory) -> VoidResultChip8 {
         Ok(())
     }
 }
 
+/// The screen, made of XO-CHIP bitplanes that are drawn to independently.
+///
+/// Pixels are represented as colors with one bit per plane. Programs that don't know about
+/// planes only ever draw to the first one.
 pub struct VideoMemory {
-    data: [u8; VideoMemory::VRAM_LEN],
+    data: Vec<u8>,
+    high_resolution: bool,
+    selected_planes: u8,
     listeners: HashMap<u8, Box<dyn VideoListener>>,
     next_listener_id: u8,
 }
 
+impl Default for VideoMemory {
+    fn default() -> Self {
+        VideoMemory::new()
+    }
+}
+
 impl VideoMemory {
-    pub const BIT_WIDTH: usize = 64;
-    pub const BIT_HEIGHT: usize = 32;
-    const VRAM_LEN: usize = (VideoMemory::BIT_WIDTH * VideoMemory::BIT_HEIGHT) / 8;
+    pub const LOW_RES_WIDTH: usize = 64;
+    pub const LOW_RES_HEIGHT: usize = 32;
+    pub const HIGH_RES_WIDTH: usize = 128;
+    pub const HIGH_RES_HEIGHT: usize = 64;
+    pub const PLANE_NUM: usize = 2;
+    pub const ALL_PLANES: u8 = (1 << VideoMemory::PLANE_NUM) - 1;
 
     pub fn new() -> VideoMemory {
         VideoMemory {
-            data: [0; VideoMemory::VRAM_LEN],
+            data: vec![0; VideoMemory::plane_len(false) * VideoMemory::PLANE_NUM],
+            high_resolution: false,
+            selected_planes: 1,
             listeners: HashMap::new(),
             next_listener_id: 0,
         }
     }
 
-    pub fn get(&self, x: impl Into<usize>, y: impl Into<usize>) -> ResultChip8<bool> {
+    pub fn width(&self) -> usize {
+        if self.high_resolution {
+            VideoMemory::HIGH_RES_WIDTH
+        } else {
+            VideoMemory::LOW_RES_WIDTH
+        }
+    }
+
+    pub fn height(&self) -> usize {
+        if self.high_resolution {
+            VideoMemory::HIGH_RES_HEIGHT
+        } else {
+            VideoMemory::LOW_RES_HEIGHT
+        }
+    }
+
+    pub fn is_high_resolution(&self) -> bool {
+        self.high_resolution
+    }
+
+    /// Switches between the 64x32 and the 128x64 (SUPER-CHIP) modes, clearing the screen
+    pub fn set_high_resolution(&mut self, high: bool) -> VoidResultChip8 {
+        self.high_resolution = high;
+        self.data = vec![0; VideoMemory::plane_len(high) * VideoMemory::PLANE_NUM];
+
+        let (width, height) = (self.width(), self.height());
+        for listener in self.listeners.values_mut() {
+            listener.on_resize(width, height)?;
+        }
+
+        self.clear()
+    }
+
+    /// The planes affected by `clear_selected` and `scroll`, one bit per plane
+    pub fn selected_planes(&self) -> u8 {
+        self.selected_planes
+    }
+
+    pub fn select_planes(&mut self, planes: u8) -> VoidResultChip8 {
+        if planes > VideoMemory::ALL_PLANES {
+            return Err(Error::new(format!("Invalid plane selection {:X}", planes)));
+        }
+
+        self.selected_planes = planes;
+        Ok(())
+    }
+
+    pub fn get(&self, x: impl Into<usize>, y: impl Into<usize>) -> ResultChip8<u8> {
         let (byte_index, bit_offset) = self.get_index_offset(x.into(), y.into())?;
 
-        let bit = (self.data[byte_index] >> bit_offset) & 1;
-        Ok(bit == 1)
+        let mut color = 0;
+        for plane in 0..VideoMemory::PLANE_NUM {
+            let bit = (self.data[self.plane_offset(plane) + byte_index] >> bit_offset) & 1;
+            color |= bit << plane;
+        }
+
+        Ok(color)
     }
 
+    /// Returns the old color of the pixel
     pub fn set(
         &mut self,
         x_into: impl Into<usize>,
         y_into: impl Into<usize>,
-        value: bool,
-    ) -> ResultChip8<bool> {
+        color: u8,
+    ) -> ResultChip8<u8> {
         let (x, y) = (x_into.into(), y_into.into());
 
+        let old_color = self.get(x, y)?;
         let (byte_index, bit_offset) = self.get_index_offset(x, y)?;
-        let old_bit = (self.data[byte_index] >> bit_offset) & 1;
 
         let mask = 1 << bit_offset;
-        if value {
-            self.data[byte_index] |= mask;
-        } else {
-            self.data[byte_index] &= !mask;
+        for plane in 0..VideoMemory::PLANE_NUM {
+            let index = self.plane_offset(plane) + byte_index;
+            if (color >> plane) & 1 == 1 {
+                self.data[index] |= mask;
+            } else {
+                self.data[index] &= !mask;
+            }
         }
 
         for listener in self.listeners.values_mut() {
-            listener.on_change(x, y, value)?;
+            listener.on_change(x, y, color)?;
         }
 
-        Ok(old_bit == 1)
+        Ok(old_color)
     }
 
+    /// Inverts the pixel in every plane in `planes`, returning its new color
     pub fn flip(
         &mut self,
         x_into: impl Into<usize>,
         y_into: impl Into<usize>,
-    ) -> ResultChip8<bool> {
+        planes: u8,
+    ) -> ResultChip8<u8> {
         let (x, y) = (x_into.into(), y_into.into());
-        let (byte_index, bit_offset) = self.get_index_offset(x, y)?;
+        let color = self.get(x, y)? ^ planes;
+        self.set(x, y, color)?;
+        Ok(color)
+    }
 
-        self.data[byte_index] ^= 1 << bit_offset;
-        let bit = (self.data[byte_index] >> bit_offset) & 1;
+    pub fn clear(&mut self) -> VoidResultChip8 {
+        self.data.iter_mut().for_each(|x| *x = 0);
 
         for listener in self.listeners.values_mut() {
-            listener.on_change(x, y, bit == 1)?;
+            listener.on_clear()?;
         }
 
-        Ok(bit == 1)
+        Ok(())
     }
 
-    pub fn clear(&mut self) -> VoidResultChip8 {
-        self.data = [0; VideoMemory::VRAM_LEN];
-
+    /// Tells the listeners that a frame ended
+    pub fn present(&mut self) -> VoidResultChip8 {
         for listener in self.listeners.values_mut() {
-            listener.on_clear()?;
+            listener.on_frame()?;
         }
 
         Ok(())
     }
 
+    /// Clears only the selected planes
+    pub fn clear_selected(&mut self) -> VoidResultChip8 {
+        if self.selected_planes == VideoMemory::ALL_PLANES {
+            return self.clear();
+        }
+
+        for y in 0..self.height() {
+            for x in 0..self.width() {
+                let color = self.get(x, y)?;
+                if color & self.selected_planes != 0 {
+                    self.set(x, y, color & !self.selected_planes)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Moves the selected planes by the given amount of pixels, what scrolls off the edges is lost
+    pub fn scroll(&mut self, dx: isize, dy: isize) -> VoidResultChip8 {
+        let (width, height) = (self.width() as isize, self.height() as isize);
+        let planes = self.selected_planes;
+
+        let mut changes = Vec::new();
+        for y in 0..height {
+            for x in 0..width {
+                let (old_x, old_y) = (x - dx, y - dy);
+                let moved = if (0..width).contains(&old_x) && (0..height).contains(&old_y) {
+                    self.get(old_x as usize, old_y as usize)?
+                } else {
+                    0
+                };
+
+                let current = self.get(x as usize, y as usize)?;
+                let color = (current & !planes) | (moved & planes);
+                if color != current {
+                    changes.push((x as usize, y as usize, color));
+                }
+            }
+        }
+
+        for (x, y, color) in changes {
+            self.set(x, y, color)?;
+        }
+
+        Ok(())
+    }
+
+    /// The raw contents of the screen, one bit per pixel, row by row, one plane after the other
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Replaces the contents of the screen with data previously returned by `data`,
+    /// switching to the resolution it was taken in.
+    /// Data with a single plane, from before planes were supported, is also accepted.
+    pub fn restore(&mut self, data: &[u8]) -> VoidResultChip8 {
+        let (high, plane_num) = VideoMemory::restore_layout(data)?;
+
+        if high != self.high_resolution {
+            self.set_high_resolution(high)?;
+        } else {
+            self.clear()?;
+        }
+
+        let plane_len = VideoMemory::plane_len(high);
+        for y in 0..self.height() {
+            for x in 0..self.width() {
+                let (byte_index, bit_offset) = self.get_index_offset(x, y)?;
+
+                let mut color = 0;
+                for plane in 0..plane_num {
+                    color |= ((data[plane * plane_len + byte_index] >> bit_offset) & 1) << plane;
+                }
+
+                if color != 0 {
+                    self.set(x, y, color)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns an error if `restore` would fail for `data`, without changing anything
+    pub fn check_restore(data: &[u8]) -> VoidResultChip8 {
+        VideoMemory::restore_layout(data).map(|_| ())
+    }
+
+    /// The resolution and number of planes that screen contents from `data` were taken with
+    fn restore_layout(data: &[u8]) -> ResultChip8<(bool, usize)> {
+        let layouts = [
+            (false, VideoMemory::PLANE_NUM),
+            (true, VideoMemory::PLANE_NUM),
+            (false, 1),
+            (true, 1),
+        ];
+
+        layouts
+            .iter()
+            .copied()
+            .find(|(high, plane_num)| VideoMemory::plane_len(*high) * plane_num == data.len())
+            .ok_or_else(|| {
+                Error::new(format!(
+                    "{} bytes of video memory don't match any known resolution",
+                    data.len()
+                ))
+            })
+    }
+
+    fn plane_len(high_resolution: bool) -> usize {
+        if high_resolution {
+            (VideoMemory::HIGH_RES_WIDTH * VideoMemory::HIGH_RES_HEIGHT) / 8
+        } else {
+            (VideoMemory::LOW_RES_WIDTH * VideoMemory::LOW_RES_HEIGHT) / 8
+        }
+    }
+
+    fn plane_offset(&self, plane: usize) -> usize {
+        plane * VideoMemory::plane_len(self.high_resolution)
+    }
+
     fn get_index_offset(&self, x: usize, y: usize) -> ResultChip8<(usize, usize)> {
-        let x = x % VideoMemory::BIT_WIDTH;
-        let y = y % VideoMemory::BIT_HEIGHT;
+        let x = x % self.width();
+        let y = y % self.height();
 
-        let bit_index = x + (y * VideoMemory::BIT_WIDTH);
+        let bit_index = x + (y * self.width());
         let byte_index = bit_index / 8;
         let bit_offset = bit_index % 8;
 
@@ -151,11 +362,17 @@ fn flush() -> VoidResultChip8 {
 }
 
 fn csi(buf: &[u8]) -> VoidResultChip8 {
-    io::stdout().write(b"\x1B[")?;
-    io::stdout().write(buf)?;
+    io::stdout().write_all(b"\x1B[")?;
+    io::stdout().write_all(buf)?;
     Ok(())
 }
 
+impl Default for TerminalVideoListener {
+    fn default() -> Self {
+        TerminalVideoListener::new()
+    }
+}
+
 impl TerminalVideoListener {
     pub fn new() -> TerminalVideoListener {
         TerminalVideoListener { started: false }
@@ -172,7 +389,7 @@ impl VideoListener for TerminalVideoListener {
         csi(b"2J")?; // Clear screen
         csi(b"H")?; // Cursor to top-left
         csi(b"?25l")?; // Hide cursor
-        io::stdout().write(b"\x1B]2;CHIP8\x07")?; // Set window title
+        io::stdout().write_all(b"\x1B]2;CHIP8\x07")?; // Set window title
         flush()?;
 
         self.started = true;
@@ -191,25 +408,28 @@ impl VideoListener for TerminalVideoListener {
         Ok(())
     }
 
-    fn on_change(&mut self, x: usize, y: usize, value: bool) -> VoidResultChip8 {
+    fn on_change(&mut self, x: usize, y: usize, color: u8) -> VoidResultChip8 {
         // Cursor to (x; y)
         csi(b"")?;
         print!("{};{}H", y + 1, x + 1);
 
-        if value {
-            csi(b"7m")?;
-        } else {
-            csi(b"27m")?;
+        match color {
+            0 => csi(b"0m")?,    // Background color
+            1 => csi(b"0;7m")?,  // Foreground color
+            2 => csi(b"0;41m")?, // Red
+            _ => csi(b"0;44m")?, // Blue
         }
         print!(" ");
-        flush()?;
         Ok(())
     }
 
     fn on_clear(&mut self) -> VoidResultChip8 {
-        csi(b"27m")?; // Set color to black
+        csi(b"0m")?; // Set color to the background color
         csi(b"2J")?; // Clear screen
-        flush()?;
         Ok(())
     }
+
+    fn on_frame(&mut self) -> VoidResultChip8 {
+        flush()
+    }
 }
@@ -0,0 +1,393 @@
+use crate::core::{Address, Error, ResultChip8, VoidResultChip8, Word};
+use crate::debugger::{Debugger, StopReason};
+use crate::memory::{MemoryRange, WatchKind, Watchpoint, WriteMemory};
+use std::convert::TryFrom;
+use std::io::{BufReader, Read, Write};
+use std::sync::atomic::Ordering;
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+
+/// Sent by the client outside of a packet to stop the CPU while it runs
+const INTERRUPT: u8 = 0x03;
+/// V0-VF, I and PC
+const REGISTER_NUM: usize = 0x12;
+const PACKET_SIZE: usize = 0x1000;
+
+/// Exposes a debugger over the GDB Remote Serial Protocol, so that existing frontends can
+/// control the CPU.
+///
+/// Registers are numbered V0-VF (one byte each), then I and PC (two big-endian bytes each).
+pub struct GdbStub {
+    pub debugger: Debugger,
+    no_ack: bool,
+    connected: bool,
+    last_stop: String,
+    /// The arguments of the packets that added each watchpoint, to find them when removed
+    watchpoints: Vec<(String, usize)>,
+}
+
+impl GdbStub {
+    pub fn new(debugger: Debugger) -> GdbStub {
+        GdbStub {
+            debugger,
+            no_ack: false,
+            connected: false,
+            last_stop: "S05".to_owned(),
+            watchpoints: Vec::new(),
+        }
+    }
+
+    /// Serves a single client until it detaches, kills the program or disconnects
+    pub fn serve(
+        &mut self,
+        reader: impl Read + Send + 'static,
+        mut writer: impl Write,
+    ) -> VoidResultChip8 {
+        let bytes = self.spawn_reader(reader);
+        self.no_ack = false;
+        self.connected = true;
+
+        while self.connected {
+            let (packet, valid) = match receive_packet(&bytes) {
+                Some(x) => x,
+                None => break,
+            };
+
+            if !self.no_ack {
+                writer.write_all(if valid { b"+" } else { b"-" })?;
+                writer.flush()?;
+            }
+            if !valid {
+                continue;
+            }
+
+            let packet = String::from_utf8_lossy(&packet);
+            let reply = match self.handle(&packet) {
+                Ok(x) => x,
+                Err(_) => Some("E01".to_owned()),
+            };
+
+            if let Some(reply) = reply {
+                write!(writer, "${}#{:02x}", reply, checksum(reply.as_bytes()))?;
+                writer.flush()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Reads the client on another thread, so that interrupts can arrive while the CPU runs
+    fn spawn_reader(&self, reader: impl Read + Send + 'static) -> Receiver<u8> {
+        let (sender, receiver) = mpsc::channel();
+        let interrupt = self.debugger.interrupt.clone();
+
+        thread::spawn(move || {
+            for byte in BufReader::new(reader).bytes() {
+                let byte = match byte {
+                    Ok(x) => x,
+                    Err(_) => break,
+                };
+
+                if byte == INTERRUPT {
+                    interrupt.store(true, Ordering::SeqCst);
+                } else if sender.send(byte).is_err() {
+                    break;
+                }
+            }
+        });
+
+        receiver
+    }
+
+    /// Runs a single packet, returning the reply to send back, if any
+    fn handle(&mut self, packet: &str) -> ResultChip8<Option<String>> {
+        if packet.is_empty() {
+            return Ok(Some(String::new()));
+        }
+
+        let (command, args) = packet.split_at(1);
+        let reply = match command {
+            "?" => self.last_stop.clone(),
+
+            "g" => {
+                let mut bytes = Vec::new();
+                for index in 0..REGISTER_NUM {
+                    bytes.extend(self.read_register(index)?);
+                }
+                encode_hex(&bytes)
+            }
+
+            "G" => {
+                let bytes = decode_hex(args)?;
+                let mut start = 0;
+                for index in 0..REGISTER_NUM {
+                    let end = start + register_size(index);
+                    let value = bytes
+                        .get(start..end)
+                        .ok_or_else(|| Error::new_str("Not enough register data"))?;
+                    self.write_register(index, value)?;
+                    start = end;
+                }
+                "OK".to_owned()
+            }
+
+            "p" => encode_hex(&self.read_register(parse_number(args)?)?),
+
+            "P" => {
+                let (index, value) = split(args, '=')?;
+                self.write_register(parse_number(index)?, &decode_hex(value)?)?;
+                "OK".to_owned()
+            }
+
+            "m" => {
+                let (start, len) = split(args, ',')?;
+                let start = parse_number(start)?;
+                let len = parse_number(len)?.min(PACKET_SIZE / 2);
+                let end = range_end(start, len)?;
+
+                let mut bytes = Vec::with_capacity(len);
+                for addr in start..end {
+                    match self.read_memory(addr) {
+                        Ok(x) => bytes.push(x),
+                        Err(err) if bytes.is_empty() => return Err(err),
+                        // Partial reads are allowed, and stop at the first unreadable address
+                        Err(_) => break,
+                    };
+                }
+                encode_hex(&bytes)
+            }
+
+            "M" => {
+                let (range, data) = split(args, ':')?;
+                let (start, len) = split(range, ',')?;
+                let start = parse_number(start)?;
+                let bytes = decode_hex(data)?;
+                if bytes.len() != parse_number(len)? {
+                    return Err(Error::new_str("Memory length doesn't match the data"));
+                }
+
+                // Checked first so that nothing is written if the range doesn't fit
+                if !bytes.is_empty() {
+                    to_address(range_end(start, bytes.len())? - 1)?;
+                }
+                for (i, value) in bytes.into_iter().enumerate() {
+                    let addr = to_address(start + i)?;
+                    self.debugger.cpu.memory.set(addr, Word::new(value))?;
+                }
+                "OK".to_owned()
+            }
+
+            "Z" | "z" => {
+                let mut parts = args.split(',');
+                let kind = parts.next().unwrap_or_default();
+                let addr = to_address(parse_number(parts.next().unwrap_or_default())?)?;
+
+                let watch_kind = match kind {
+                    "2" => Some(WatchKind::Write),
+                    "3" => Some(WatchKind::Read),
+                    "4" => Some(WatchKind::ReadWrite),
+                    _ => None,
+                };
+
+                match (command, kind, watch_kind) {
+                    ("Z", "0", _) | ("Z", "1", _) => self.debugger.add_breakpoint(addr),
+                    ("z", "0", _) | ("z", "1", _) => {
+                        self.debugger.remove_breakpoint(addr);
+                    }
+                    ("Z", _, Some(watch_kind)) => {
+                        let len = parse_number(parts.next().unwrap_or_default())?.max(1);
+                        let end = to_address(range_end(addr.into(), len)? - 1)?;
+                        let range = MemoryRange::new(addr, end);
+
+                        let memory = &mut self.debugger.cpu.memory;
+                        let id = memory.add_watchpoint(Watchpoint::new(watch_kind, range));
+                        self.watchpoints.push((args.to_owned(), id));
+                    }
+                    ("z", _, Some(_)) => {
+                        if let Some(index) = self.watchpoints.iter().position(|(x, _)| x == args) {
+                            let (_, id) = self.watchpoints.remove(index);
+                            self.debugger.cpu.memory.remove_watchpoint(id);
+                        }
+                    }
+                    _ => return Ok(Some(String::new())),
+                };
+                "OK".to_owned()
+            }
+
+            "s" | "c" => {
+                if !args.is_empty() {
+                    self.debugger.cpu.registers.program_counter = to_address(parse_number(args)?)?;
+                }
+
+                let reason = if command == "s" {
+                    self.debugger.run_until(|_| true)
+                } else {
+                    self.debugger.run_until(|_| false)
+                };
+
+                self.last_stop = match reason {
+                    StopReason::Halted => "W00".to_owned(),
+                    StopReason::Interrupted => "S02".to_owned(),
+                    StopReason::Error(_) => "S04".to_owned(),
+                    StopReason::Watchpoint(access) => {
+                        let name = match self.watch_kind(access.watchpoint) {
+                            Some(WatchKind::Read) => "rwatch",
+                            Some(WatchKind::ReadWrite) => "awatch",
+                            _ => "watch",
+                        };
+                        format!("T05{}:{:x};", name, u16::from(access.addr))
+                    }
+                    _ => "S05".to_owned(),
+                };
+                self.last_stop.clone()
+            }
+
+            "D" => {
+                self.connected = false;
+                "OK".to_owned()
+            }
+
+            "k" => {
+                self.connected = false;
+                return Ok(None);
+            }
+
+            "H" => "OK".to_owned(),
+
+            "q" if args.starts_with("Supported") => {
+                format!("PacketSize={:x};QStartNoAckMode+", PACKET_SIZE)
+            }
+            "q" if args == "Attached" => "1".to_owned(),
+
+            "Q" if args == "StartNoAckMode" => {
+                self.no_ack = true;
+                "OK".to_owned()
+            }
+
+            // Unsupported packets get an empty reply
+            _ => String::new(),
+        };
+
+        Ok(Some(reply))
+    }
+
+    fn watch_kind(&self, id: usize) -> Option<WatchKind> {
+        self.debugger
+            .cpu
+            .memory
+            .watchpoints()
+            .find(|(x, _)| *x == id)
+            .map(|(_, x)| x.kind)
+    }
+
+    fn read_register(&self, index: usize) -> ResultChip8<Vec<u8>> {
+        let registers = &self.debugger.cpu.registers;
+        let value: u16 = match index {
+            0x0..=0xF => return Ok(vec![registers.values[index].into()]),
+            0x10 => registers.address.into(),
+            0x11 => registers.program_counter.into(),
+            x => return Err(Error::new(format!("Unknown register {:X}", x))),
+        };
+
+        Ok(value.to_be_bytes().to_vec())
+    }
+
+    fn write_register(&mut self, index: usize, bytes: &[u8]) -> VoidResultChip8 {
+        if index >= REGISTER_NUM || bytes.len() != register_size(index) {
+            return Err(Error::new(format!(
+                "Invalid value for register {:X}",
+                index
+            )));
+        }
+
+        let registers = &mut self.debugger.cpu.registers;
+        match index {
+            0x0..=0xF => registers.values[index] = Word::new(bytes[0]),
+            0x10 => registers.address = Address::new(u16::from_be_bytes([bytes[0], bytes[1]])),
+            _ => registers.program_counter = Address::new(u16::from_be_bytes([bytes[0], bytes[1]])),
+        };
+
+        Ok(())
+    }
+
+    fn read_memory(&self, addr: usize) -> ResultChip8<u8> {
+        let value = self.debugger.cpu.memory.peek(to_address(addr)?)?;
+        Ok(value.into())
+    }
+}
+
+/// Waits for the next packet, skipping acknowledgements, and returns its data and whether the
+/// checksum matched. Returns `None` if the client disconnected.
+fn receive_packet(bytes: &Receiver<u8>) -> Option<(Vec<u8>, bool)> {
+    while bytes.recv().ok()? != b'$' {}
+
+    let mut data = Vec::new();
+    loop {
+        match bytes.recv().ok()? {
+            b'#' => break,
+            x => data.push(x),
+        };
+    }
+
+    let digits = [bytes.recv().ok()?, bytes.recv().ok()?];
+    let expected = std::str::from_utf8(&digits)
+        .ok()
+        .and_then(|x| u8::from_str_radix(x, 16).ok());
+
+    let valid = expected == Some(checksum(&data));
+    Some((data, valid))
+}
+
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |acc, x| acc.wrapping_add(*x))
+}
+
+fn register_size(index: usize) -> usize {
+    if index < 0x10 {
+        1
+    } else {
+        2
+    }
+}
+
+fn split(args: &str, separator: char) -> ResultChip8<(&str, &str)> {
+    let index = args
+        .find(separator)
+        .ok_or_else(|| Error::new(format!("Expected {} in {}", separator, args)))?;
+    Ok((&args[..index], &args[index + 1..]))
+}
+
+fn parse_number(text: &str) -> ResultChip8<usize> {
+    usize::from_str_radix(text, 16).map_err(|_| Error::new(format!("Invalid number {}", text)))
+}
+
+/// The end of the memory range starting at `start`, exclusive
+fn range_end(start: usize, len: usize) -> ResultChip8<usize> {
+    start
+        .checked_add(len)
+        .ok_or_else(|| Error::new(format!("Range {:X},{:X} is out of bounds", start, len)))
+}
+
+fn to_address(value: usize) -> ResultChip8<Address> {
+    u16::try_from(value)
+        .map(Address::new)
+        .map_err(|_| Error::new(format!("Address {:X} is out of range", value)))
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|x| format!("{:02x}", x)).collect()
+}
+
+fn decode_hex(text: &str) -> ResultChip8<Vec<u8>> {
+    if !text.len().is_multiple_of(2) || !text.is_ascii() {
+        return Err(Error::new(format!("Invalid hex data {}", text)));
+    }
+
+    (0..text.len())
+        .step_by(2)
+        .map(|i| {
+            u8::from_str_radix(&text[i..i + 2], 16)
+                .map_err(|_| Error::new(format!("Invalid hex data {}", text)))
+        })
+        .collect()
+}
@@ -1,11 +1,674 @@
-pub struct NativeInputManager {}
+use super::{Hotkey, InputBuffer, DEFAULT_RELEASE_TIMEOUT, KEY_NUM};
+use crate::core::{Error, ResultChip8, VoidResultChip8};
+use std::io::{self, Write};
+use std::mem;
+use std::time::{Duration, Instant};
+
+const ESC: u8 = 0x1B;
+
+// How long an incomplete escape sequence waits for the rest of it before the ESC is read as
+// the Escape key. The bytes of a sequence can be split across reads, but they're sent together.
+const ESCAPE_TIMEOUT: Duration = Duration::from_millis(50);
+
+// Kitty keyboard protocol flags: disambiguate escape codes, report event types
+// and report all keys as escape codes
+const KITTY_FLAGS: u32 = 0b1011;
+
+pub struct NativeInputManager {
+    old_termios: libc::termios,
+    decoder: Decoder,
+}
+
+/// Turns the bytes the terminal sends into key states, without talking to the terminal itself
+struct Decoder {
+    pending: Vec<u8>,
+    /// When `pending` started waiting on an incomplete escape sequence
+    escape_since: Option<Instant>,
+    kitty: KittyState,
+    held: [Option<HeldKey>; KEY_NUM],
+    release_timeout: Duration,
+    repeat_interval: Option<Duration>,
+}
+
+#[derive(Eq, PartialEq, Clone, Copy, Debug)]
+enum KittyState {
+    Querying,
+    Enabled,
+    Unsupported,
+}
+
+/// A key that is considered held while the terminal keeps repeating it
+#[derive(Clone, Copy, Debug)]
+struct HeldKey {
+    last_seen: Instant,
+    repeating: bool,
+}
+
+#[derive(Eq, PartialEq, Clone, Copy, Debug)]
+enum KeyAction {
+    Press,
+    Repeat,
+    Release,
+}
+
+#[derive(Eq, PartialEq, Clone, Copy, Debug)]
+enum Event {
+    Key(usize, KeyAction),
+    Hotkey(Hotkey),
+    Interrupt,
+    KittySupported,
+    DeviceAttributes,
+}
+
+/// Something the decoded input asks of the terminal or the process
+#[derive(Eq, PartialEq, Clone, Copy, Debug)]
+enum Request {
+    EnableKitty,
+    Interrupt,
+}
 
 impl NativeInputManager {
-    pub fn new() -> NativeInputManager {
-        panic!("Not implemented yet");
+    pub fn new() -> ResultChip8<NativeInputManager> {
+        unsafe {
+            let mut old_termios: libc::termios = mem::zeroed();
+            if libc::tcgetattr(libc::STDIN_FILENO, &mut old_termios) != 0 {
+                return Err(os_error("Unable to read terminal attributes"));
+            }
+
+            // Raw input, but keep ISIG so that Ctrl+C still reaches the ctrlc handler
+            let mut raw = old_termios;
+            raw.c_iflag &= !(libc::IXON | libc::ICRNL | libc::INLCR | libc::IGNCR | libc::ISTRIP);
+            raw.c_lflag &= !(libc::ICANON | libc::ECHO | libc::IEXTEN);
+
+            // Non-blocking: read() returns immediately, even if no bytes are available
+            raw.c_cc[libc::VMIN] = 0;
+            raw.c_cc[libc::VTIME] = 0;
+
+            if libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &raw) != 0 {
+                return Err(os_error("Unable to set terminal attributes"));
+            }
+
+            // Created before anything else can fail, so that dropping it restores the terminal
+            let manager = NativeInputManager {
+                old_termios,
+                decoder: Decoder::new(),
+            };
+
+            // Application keypad mode, so that the numpad sends different sequences than the number row.
+            // Then query for kitty keyboard protocol support, followed by a primary device attributes
+            // query that every terminal answers, so we know when to stop waiting for the first answer.
+            write_stdout(b"\x1B=\x1B[?u\x1B[c")
+                .map_err(|x| x.chain("Unable to set up terminal input".to_owned()))?;
+
+            Ok(manager)
+        }
+    }
+
+    pub fn set_release_timeout(&mut self, timeout: Duration) {
+        self.decoder.release_timeout = timeout;
     }
 
     pub fn tick(&mut self, buffer: &mut InputBuffer) -> VoidResultChip8 {
-        panic!("Not implemented yet");
+        self.read_pending()?;
+
+        for request in self.decoder.decode(Instant::now(), buffer)? {
+            match request {
+                Request::EnableKitty => write_stdout(format!("\x1B[>{}u", KITTY_FLAGS).as_bytes())?,
+
+                // With all keys reported as escape codes, the terminal no longer generates SIGINT
+                Request::Interrupt => unsafe {
+                    libc::raise(libc::SIGINT);
+                },
+            };
+        }
+
+        Ok(())
+    }
+
+    fn read_pending(&mut self) -> VoidResultChip8 {
+        let mut bytes = [0u8; 256];
+
+        loop {
+            let read = unsafe {
+                libc::read(
+                    libc::STDIN_FILENO,
+                    bytes.as_mut_ptr() as *mut libc::c_void,
+                    bytes.len(),
+                )
+            };
+
+            if read < 0 {
+                let error = io::Error::last_os_error();
+                return match error.kind() {
+                    io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted => Ok(()),
+                    _ => Err(Error::from(error).chain("Unable to read from STDIN".to_owned())),
+                };
+            }
+
+            if read == 0 {
+                return Ok(());
+            }
+
+            self.decoder
+                .pending
+                .extend_from_slice(&bytes[..read as usize]);
+        }
+    }
+}
+
+impl Decoder {
+    fn new() -> Decoder {
+        Decoder {
+            pending: Vec::new(),
+            escape_since: None,
+            kitty: KittyState::Querying,
+            held: [None; KEY_NUM],
+            release_timeout: DEFAULT_RELEASE_TIMEOUT,
+            repeat_interval: None,
+        }
+    }
+
+    /// Decodes all the complete input in `pending` into `buffer`, as if it arrived at `now`
+    fn decode(&mut self, now: Instant, buffer: &mut InputBuffer) -> ResultChip8<Vec<Request>> {
+        let mut requests = Vec::new();
+
+        let mut i = 0;
+        while i < self.pending.len() {
+            let (len, event) = match self.pending[i] {
+                ESC => match decode_escape(&self.pending[i..]) {
+                    Some(x) => x,
+                    None => match self.escape_since {
+                        // Nothing more came, so it was the Escape key followed by normal input
+                        Some(since) if now.duration_since(since) >= ESCAPE_TIMEOUT => (1, None),
+                        // Incomplete sequence, wait for the rest of it
+                        _ => break,
+                    },
+                },
+                x => (1, map_byte(x).map(|key| Event::Key(key, KeyAction::Press))),
+            };
+
+            self.escape_since = None;
+            if let Some(event) = event {
+                requests.extend(self.handle_event(event, now, buffer)?);
+            }
+
+            i += len;
+        }
+
+        self.pending.drain(..i);
+        if !self.pending.is_empty() && self.escape_since.is_none() {
+            self.escape_since = Some(now);
+        }
+
+        if self.kitty != KittyState::Enabled {
+            self.release_expired(now, buffer)?;
+        }
+
+        Ok(requests)
+    }
+
+    fn handle_event(
+        &mut self,
+        event: Event,
+        now: Instant,
+        buffer: &mut InputBuffer,
+    ) -> ResultChip8<Option<Request>> {
+        match event {
+            Event::KittySupported => {
+                if self.kitty == KittyState::Querying {
+                    self.kitty = KittyState::Enabled;
+                    return Ok(Some(Request::EnableKitty));
+                }
+            }
+
+            Event::Hotkey(hotkey) => buffer.push_hotkey(hotkey),
+
+            Event::DeviceAttributes => {
+                if self.kitty == KittyState::Querying {
+                    self.kitty = KittyState::Unsupported;
+                }
+            }
+
+            Event::Interrupt => return Ok(Some(Request::Interrupt)),
+
+            Event::Key(key, KeyAction::Release) => {
+                self.held[key] = None;
+                buffer.release(key)?;
+            }
+
+            Event::Key(key, _) if self.kitty == KittyState::Enabled => buffer.hold(key)?,
+
+            // Terminals without the kitty protocol only send key presses, followed by
+            // autorepeated presses while the key is held down. The first repeat comes after
+            // the autorepeat delay, which is covered by the release timeout. The ones after
+            // that come at the autorepeat rate, which we measure so that releases are detected
+            // sooner.
+            Event::Key(key, _) => match &mut self.held[key] {
+                None => {
+                    self.held[key] = Some(HeldKey {
+                        last_seen: now,
+                        repeating: false,
+                    });
+                    buffer.hold(key)?;
+                }
+
+                Some(held) => {
+                    if held.repeating {
+                        let interval = now.duration_since(held.last_seen);
+                        self.repeat_interval = Some(match self.repeat_interval {
+                            None => interval,
+                            Some(old) => (old * 3 + interval) / 4,
+                        });
+                    }
+
+                    held.last_seen = now;
+                    held.repeating = true;
+                }
+            },
+        };
+
+        Ok(None)
+    }
+
+    fn release_expired(&mut self, now: Instant, buffer: &mut InputBuffer) -> VoidResultChip8 {
+        for key in 0..KEY_NUM {
+            let held = match self.held[key] {
+                None => continue,
+                Some(x) => x,
+            };
+
+            let timeout = match self.repeat_interval {
+                // Twice the interval, so that a single late repeat doesn't release the key
+                Some(interval) if held.repeating => (interval * 2).min(self.release_timeout),
+                _ => self.release_timeout,
+            };
+
+            if now.duration_since(held.last_seen) > timeout {
+                self.held[key] = None;
+                buffer.release(key)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn os_error(message: &str) -> Error {
+    Error::from(io::Error::last_os_error()).chain(message.to_owned())
+}
+
+fn write_stdout(bytes: &[u8]) -> VoidResultChip8 {
+    let mut stdout = io::stdout();
+    stdout.write_all(bytes)?;
+    stdout.flush()?;
+    Ok(())
+}
+
+/// Decodes an escape sequence at the start of `bytes`, which must start with ESC.
+/// Returns the length of the sequence and the event it represents,
+/// or `None` if the sequence is not complete yet.
+fn decode_escape(bytes: &[u8]) -> Option<(usize, Option<Event>)> {
+    // A lone ESC is either the Escape key or the start of a sequence split across reads
+    let intro = *bytes.get(1)?;
+
+    if intro != b'[' && intro != b'O' {
+        // Alt + key
+        return Some((2, None));
+    }
+
+    // Skip parameter and intermediate bytes until the final byte
+    for (i, &byte) in bytes.iter().enumerate().skip(2) {
+        if (0x40..=0x7E).contains(&byte) {
+            return Some((i + 1, map_sequence(intro, &bytes[2..i], byte)));
+        }
+
+        if !(0x20..=0x3F).contains(&byte) {
+            // Malformed sequence, drop the introducer
+            return Some((2, None));
+        }
+    }
+
+    None
+}
+
+/// Splits CSI parameters such as `97;5:3` into fields separated by `;`,
+/// each with sub-fields separated by `:`. Missing values are read as 0.
+fn parse_params(params: &[u8]) -> Vec<Vec<u32>> {
+    params
+        .split(|&x| x == b';')
+        .map(|field| {
+            field
+                .split(|&x| x == b':')
+                .map(|sub| {
+                    sub.iter()
+                        .filter(|x| x.is_ascii_digit())
+                        .fold(0u32, |acc, x| acc.saturating_mul(10) + (x - b'0') as u32)
+                })
+                .collect()
+        })
+        .collect()
+}
+
+fn map_sequence(intro: u8, params: &[u8], last: u8) -> Option<Event> {
+    if intro == b'[' && params.first() == Some(&b'?') {
+        return match last {
+            b'u' => Some(Event::KittySupported),
+            b'c' => Some(Event::DeviceAttributes),
+            _ => None,
+        };
+    }
+
+    let fields = parse_params(params);
+
+    // Kitty reports modifiers and event types as the second field,
+    // legacy sequences have no second field so they are always presses
+    let modifiers = fields.get(1).and_then(|x| x.first()).copied().unwrap_or(1);
+    let action = match fields.get(1).and_then(|x| x.get(1)) {
+        Some(2) => KeyAction::Repeat,
+        Some(3) => KeyAction::Release,
+        _ => KeyAction::Press,
+    };
+
+    if intro == b'[' && last == b'~' {
+        let hotkey = match fields.first().and_then(|x| x.first()) {
+            Some(15) => Hotkey::SaveState,   // F5
+            Some(17) => Hotkey::FastForward, // F6
+            Some(18) => Hotkey::Rewind,      // F7
+            Some(19) => Hotkey::SlowMotion,  // F8
+            Some(20) => Hotkey::LoadState,   // F9
+            _ => return None,
+        };
+
+        return match action {
+            KeyAction::Press => Some(Event::Hotkey(hotkey)),
+            _ => None,
+        };
+    }
+
+    let key = match (intro, last) {
+        (b'[', b'u') => {
+            let code = fields.first().and_then(|x| x.first()).copied().unwrap_or(0);
+            let ctrl = modifiers.saturating_sub(1) & 0b100 != 0;
+
+            if ctrl && code == b'c' as u32 {
+                return match action {
+                    KeyAction::Press => Some(Event::Interrupt),
+                    _ => None,
+                };
+            }
+
+            map_kitty_code(code)
+        }
+
+        // Arrows, sent as CSI in normal mode and SS3 in application cursor mode
+        (_, b'A') => Some(0x8),
+        (_, b'B') => Some(0x2),
+        (_, b'C') => Some(0x6),
+        (_, b'D') => Some(0x4),
+
+        // Numpad in application keypad mode
+        (b'O', b'p'..=b'y') => Some((last - b'p') as usize),
+        (b'O', b'n') => Some(0xA), // Decimal
+        (b'O', b'l') => Some(0xA), // Separator
+        (b'O', b'o') => Some(0xB), // Divide
+        (b'O', b'j') => Some(0xC), // Multiply
+        (b'O', b'm') => Some(0xD), // Subtract
+        (b'O', b'k') => Some(0xE), // Add
+        (b'O', b'M') => Some(0xF), // Enter
+
+        _ => None,
+    }?;
+
+    Some(Event::Key(key, action))
+}
+
+fn map_kitty_code(code: u32) -> Option<usize> {
+    let chip8_key = match code {
+        57399..=57408 => (code - 57399) as usize, // Numpad 0-9
+        57409 => 0xA,                             // Numpad decimal
+        57416 => 0xA,                             // Numpad separator
+        57410 => 0xB,                             // Numpad divide
+        57411 => 0xC,                             // Numpad multiply
+        57412 => 0xD,                             // Numpad subtract
+        57413 => 0xE,                             // Numpad add
+        57414 => 0xF,                             // Numpad enter
+        57417 => 0x4,                             // Numpad left
+        57418 => 0x6,                             // Numpad right
+        57419 => 0x8,                             // Numpad up
+        57420 => 0x2,                             // Numpad down
+
+        x if x < 0x80 => return map_byte(x as u8),
+        _ => return None,
+    };
+
+    Some(chip8_key)
+}
+
+fn map_byte(byte: u8) -> Option<usize> {
+    let chip8_key = match byte.to_ascii_lowercase() {
+        b' ' => 0x0,
+        b'z' => 0x1,
+        b's' => 0x2,
+        b'c' => 0x3,
+        b'a' => 0x4,
+        b'x' => 0x5,
+        b'd' => 0x6,
+        b'q' => 0x7,
+        b'w' => 0x8,
+        b'e' => 0x9,
+        b'1' | b',' | b'.' => 0xA,
+        b'2' => 0xB,
+        b'3' => 0xC,
+        b'r' => 0xD,
+        b'f' => 0xE,
+        b'v' | b'\r' | b'\n' => 0xF,
+        _ => return None,
+    };
+
+    Some(chip8_key)
+}
+
+impl Drop for NativeInputManager {
+    fn drop(&mut self) {
+        let mut stdout = io::stdout();
+        if self.decoder.kitty == KittyState::Enabled {
+            let _ = stdout.write_all(b"\x1B[<u");
+        }
+        let _ = stdout.write_all(b"\x1B>");
+        let _ = stdout.flush();
+
+        unsafe {
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.old_termios);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Feeds bytes to a decoder at times given in milliseconds since it was created
+    struct Terminal {
+        decoder: Decoder,
+        buffer: InputBuffer,
+        start: Instant,
+    }
+
+    impl Terminal {
+        fn new() -> Terminal {
+            let mut decoder = Decoder::new();
+            decoder.release_timeout = Duration::from_millis(600);
+            Terminal {
+                decoder,
+                buffer: InputBuffer::new(),
+                start: Instant::now(),
+            }
+        }
+
+        fn send(&mut self, millis: u64, bytes: &[u8]) -> Vec<Request> {
+            self.decoder.pending.extend_from_slice(bytes);
+            let now = self.start + Duration::from_millis(millis);
+            self.decoder.decode(now, &mut self.buffer).unwrap()
+        }
+
+        fn is_down(&self, key: usize) -> bool {
+            self.buffer.is_down(key).unwrap()
+        }
+    }
+
+    #[test]
+    fn presses_are_released_after_the_timeout() {
+        let mut terminal = Terminal::new();
+        terminal.send(0, b"w");
+        assert!(terminal.is_down(0x8));
+
+        terminal.send(600, b"");
+        assert!(terminal.is_down(0x8));
+        terminal.send(601, b"");
+        assert!(!terminal.is_down(0x8));
+    }
+
+    #[test]
+    fn autorepeat_is_measured() {
+        let mut terminal = Terminal::new();
+        terminal.send(0, b"w");
+
+        // The first repeat comes after the autorepeat delay, which isn't the rate
+        terminal.send(500, b"w");
+        assert_eq!(terminal.decoder.repeat_interval, None);
+        terminal.send(530, b"w");
+        assert_eq!(
+            terminal.decoder.repeat_interval,
+            Some(Duration::from_millis(30))
+        );
+        terminal.send(580, b"w");
+        assert_eq!(
+            terminal.decoder.repeat_interval,
+            Some(Duration::from_millis(35))
+        );
+
+        // Once repeats stop, the key is released after two intervals instead of the timeout
+        terminal.send(650, b"");
+        assert!(terminal.is_down(0x8));
+        terminal.send(651, b"");
+        assert!(!terminal.is_down(0x8));
+
+        // A new press waits for the whole timeout again, since it may not repeat at all
+        terminal.send(1000, b"w");
+        terminal.send(1500, b"");
+        assert!(terminal.is_down(0x8));
+    }
+
+    #[test]
+    fn kitty_reports_releases() {
+        let mut terminal = Terminal::new();
+        assert_eq!(
+            terminal.send(0, b"\x1B[?1u\x1B[?62c"),
+            vec![Request::EnableKitty]
+        );
+        assert_eq!(terminal.decoder.kitty, KittyState::Enabled);
+
+        // Press, repeat and release of A, and a numpad 5 press
+        terminal.send(0, b"\x1B[97u\x1B[57404;1:1u");
+        assert!(terminal.is_down(0x4));
+        assert!(terminal.is_down(0x5));
+        terminal.send(5000, b"\x1B[97;1:2u");
+        assert!(terminal.is_down(0x4));
+        terminal.send(5001, b"\x1B[97;1:3u");
+        assert!(!terminal.is_down(0x4));
+
+        // There are no timeouts, only releases
+        assert!(terminal.is_down(0x5));
+
+        assert_eq!(terminal.send(5002, b"\x1B[99;5u"), vec![Request::Interrupt]);
+        assert_eq!(terminal.send(5003, b"\x1B[99;5:3u"), vec![]);
+    }
+
+    #[test]
+    fn terminals_without_kitty() {
+        let mut terminal = Terminal::new();
+        assert_eq!(terminal.send(0, b"\x1B[?62;22c"), vec![]);
+        assert_eq!(terminal.decoder.kitty, KittyState::Unsupported);
+
+        // A late kitty answer is ignored
+        assert_eq!(terminal.send(0, b"\x1B[?0u"), vec![]);
+        assert_eq!(terminal.decoder.kitty, KittyState::Unsupported);
+
+        // Arrows, the numpad in application mode and hotkeys
+        terminal.send(0, b"\x1B[A\x1BOw\x1B[15~");
+        assert!(terminal.is_down(0x8));
+        assert!(terminal.is_down(0x7));
+        assert_eq!(terminal.buffer.pop_hotkey(), Some(Hotkey::SaveState));
+    }
+
+    #[test]
+    fn sequences_split_across_reads() {
+        let mut terminal = Terminal::new();
+        terminal.send(0, b"\x1B");
+        terminal.send(1, b"[");
+        assert!(!terminal.is_down(0x8));
+        terminal.send(2, b"A");
+        assert!(terminal.is_down(0x8));
+
+        terminal.send(3, b"\x1B[?6");
+        assert_eq!(terminal.decoder.kitty, KittyState::Querying);
+        terminal.send(4, b"2c");
+        assert_eq!(terminal.decoder.kitty, KittyState::Unsupported);
+    }
+
+    #[test]
+    fn lone_escape_times_out() {
+        let mut terminal = Terminal::new();
+        terminal.send(0, b"\x1B");
+        terminal.send(49, b"");
+        assert_eq!(terminal.decoder.pending, vec![ESC]);
+
+        // Then it was the Escape key, and what comes after it is read normally
+        terminal.send(50, b"");
+        assert!(terminal.decoder.pending.is_empty());
+        terminal.send(51, b"s");
+        assert!(terminal.is_down(0x2));
+
+        // Unless it comes in time, as Alt + key
+        terminal.send(100, b"\x1B");
+        terminal.send(101, b"w");
+        assert!(terminal.decoder.pending.is_empty());
+        assert!(!terminal.is_down(0x8));
+    }
+
+    #[test]
+    fn sequences() {
+        let press = |key| Some(Event::Key(key, KeyAction::Press));
+        assert_eq!(map_sequence(b'[', b"", b'A'), press(0x8));
+        assert_eq!(map_sequence(b'O', b"", b'w'), press(0x7));
+        assert_eq!(
+            map_sequence(b'[', b"100;1:2", b'u'),
+            Some(Event::Key(0x6, KeyAction::Repeat))
+        );
+        assert_eq!(map_sequence(b'[', b"99;5", b'u'), Some(Event::Interrupt));
+        assert_eq!(map_sequence(b'[', b"99", b'u'), press(0x3));
+        assert_eq!(map_sequence(b'[', b"103", b'u'), None);
+
+        // Hotkeys only act on presses
+        assert_eq!(
+            map_sequence(b'[', b"20", b'~'),
+            Some(Event::Hotkey(Hotkey::LoadState))
+        );
+        assert_eq!(map_sequence(b'[', b"20;1:3", b'~'), None);
+        assert_eq!(map_sequence(b'[', b"16", b'~'), None);
+
+        assert_eq!(map_sequence(b'[', b"?0", b'u'), Some(Event::KittySupported));
+        assert_eq!(
+            map_sequence(b'[', b"?62;22", b'c'),
+            Some(Event::DeviceAttributes)
+        );
+    }
+
+    #[test]
+    fn params() {
+        assert_eq!(parse_params(b"97;5:3"), vec![vec![97], vec![5, 3]]);
+        assert_eq!(parse_params(b""), vec![vec![0]]);
+        assert_eq!(parse_params(b"1;"), vec![vec![1], vec![0]]);
+        assert_eq!(parse_params(b"::2"), vec![vec![0, 0, 2]]);
     }
 }
@@ -1,12 +1,58 @@
 use crate::core::{Error, ResultChip8, VoidResultChip8};
+use std::cell::RefCell;
+use std::collections::VecDeque;
 use std::convert::TryInto;
+use std::rc::Rc;
+use std::time::Duration;
 
 #[cfg_attr(target_family = "windows", path = "windows.rs")]
 #[cfg_attr(target_family = "unix", path = "linux.rs")]
 mod native;
+mod scripted;
+
+pub use scripted::{ScriptedEvent, ScriptedInput};
+
+/// Where the CPU reads the state of the 16 CHIP-8 keys from
+pub trait InputSource {
+    fn is_down(&self, key: usize) -> ResultChip8<bool>;
+
+    /// Called once before every instruction is executed
+    fn tick(&mut self) -> VoidResultChip8;
+
+    /// Returns the next emulator hotkey pressed since the last call, if any
+    fn poll_hotkey(&mut self) -> Option<Hotkey> {
+        None
+    }
+}
+
+/// Lets a source be shared, to change keys while the CPU owns it
+impl<T: InputSource> InputSource for Rc<RefCell<T>> {
+    fn is_down(&self, key: usize) -> ResultChip8<bool> {
+        self.borrow().is_down(key)
+    }
+
+    fn tick(&mut self) -> VoidResultChip8 {
+        self.borrow_mut().tick()
+    }
+
+    fn poll_hotkey(&mut self) -> Option<Hotkey> {
+        self.borrow_mut().poll_hotkey()
+    }
+}
+
+/// Keys that control the emulator itself instead of the emulated machine
+#[derive(Eq, PartialEq, Clone, Copy, Debug)]
+pub enum Hotkey {
+    SaveState,
+    LoadState,
+    FastForward,
+    SlowMotion,
+    Rewind,
+}
 
 pub struct InputBuffer {
     keys: [KeyState; 0x10],
+    hotkeys: VecDeque<Hotkey>,
 }
 
 #[derive(Eq, PartialEq, Clone, Copy, Debug)]
@@ -18,6 +64,10 @@ enum KeyState {
 
 pub const KEY_NUM: usize = 0x10;
 
+/// How long a key is held after a key press on terminals that never report key releases.
+/// Should be longer than the terminal's autorepeat delay.
+pub const DEFAULT_RELEASE_TIMEOUT: Duration = Duration::from_millis(500);
+
 fn to_key_index(value: impl TryInto<usize>) -> ResultChip8<usize> {
     let index = match value.try_into() {
         Ok(x) => x,
@@ -31,10 +81,17 @@ fn to_key_index(value: impl TryInto<usize>) -> ResultChip8<usize> {
     Ok(index)
 }
 
+impl Default for InputBuffer {
+    fn default() -> Self {
+        InputBuffer::new()
+    }
+}
+
 impl InputBuffer {
     pub fn new() -> InputBuffer {
         InputBuffer {
             keys: [KeyState::Released; KEY_NUM],
+            hotkeys: VecDeque::new(),
         }
     }
 
@@ -64,6 +121,14 @@ impl InputBuffer {
         self.set_state(index, KeyState::Released)
     }
 
+    pub fn push_hotkey(&mut self, hotkey: Hotkey) {
+        self.hotkeys.push_back(hotkey);
+    }
+
+    pub fn pop_hotkey(&mut self) -> Option<Hotkey> {
+        self.hotkeys.pop_front()
+    }
+
     fn set_state(&mut self, index_into: impl TryInto<usize>, state: KeyState) -> VoidResultChip8 {
         let index = to_key_index(index_into)?;
         self.keys[index] = state;
@@ -77,20 +142,30 @@ pub struct InputManager {
 }
 
 impl InputManager {
-    pub fn new() -> InputManager {
-        InputManager {
-            native: native::NativeInputManager::new(),
+    pub fn new() -> ResultChip8<InputManager> {
+        Ok(InputManager {
+            native: native::NativeInputManager::new()?,
             buffer: InputBuffer::new(),
-        }
+        })
     }
 
-    pub fn is_down(&self, index_into: impl TryInto<usize>) -> ResultChip8<bool> {
-        self.buffer.is_down(index_into)
+    pub fn set_release_timeout(&mut self, timeout: Duration) {
+        self.native.set_release_timeout(timeout);
     }
+}
 
-    pub fn tick(&mut self) -> VoidResultChip8 {
+impl InputSource for InputManager {
+    fn is_down(&self, key: usize) -> ResultChip8<bool> {
+        self.buffer.is_down(key)
+    }
+
+    fn tick(&mut self) -> VoidResultChip8 {
         self.native.tick(&mut self.buffer)?;
         self.buffer.tick();
         Ok(())
     }
+
+    fn poll_hotkey(&mut self) -> Option<Hotkey> {
+        self.buffer.pop_hotkey()
+    }
 }
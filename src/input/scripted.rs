@@ -0,0 +1,87 @@
+use super::{to_key_index, InputBuffer, InputSource};
+use crate::core::{ResultChip8, VoidResultChip8};
+use std::collections::VecDeque;
+use std::convert::TryInto;
+
+/// A key state change that happens right before a given tick
+#[derive(Eq, PartialEq, Clone, Copy, Debug)]
+pub struct ScriptedEvent {
+    pub tick: u64,
+    pub key: usize,
+    pub down: bool,
+}
+
+/// Input source controlled by code instead of a keyboard.
+/// Keys can be changed directly, or scheduled to change on a given tick.
+pub struct ScriptedInput {
+    buffer: InputBuffer,
+    script: VecDeque<ScriptedEvent>,
+    ticks: u64,
+}
+
+impl Default for ScriptedInput {
+    fn default() -> Self {
+        ScriptedInput::new()
+    }
+}
+
+impl ScriptedInput {
+    pub fn new() -> ScriptedInput {
+        ScriptedInput {
+            buffer: InputBuffer::new(),
+            script: VecDeque::new(),
+            ticks: 0,
+        }
+    }
+
+    pub fn ticks(&self) -> u64 {
+        self.ticks
+    }
+
+    pub fn hold(&mut self, key: impl TryInto<usize>) -> VoidResultChip8 {
+        self.buffer.hold(key)
+    }
+
+    pub fn release(&mut self, key: impl TryInto<usize>) -> VoidResultChip8 {
+        self.buffer.release(key)
+    }
+
+    /// Schedules a key to be held or released right before the `tick`-th tick, counting from 0.
+    pub fn schedule(&mut self, tick: u64, key: impl TryInto<usize>, down: bool) -> VoidResultChip8 {
+        let key = to_key_index(key)?;
+        let event = ScriptedEvent { tick, key, down };
+
+        // Keep the script sorted, with events on the same tick in insertion order
+        let index = self
+            .script
+            .iter()
+            .position(|x| x.tick > tick)
+            .unwrap_or(self.script.len());
+        self.script.insert(index, event);
+        Ok(())
+    }
+}
+
+impl InputSource for ScriptedInput {
+    fn is_down(&self, key: usize) -> ResultChip8<bool> {
+        self.buffer.is_down(key)
+    }
+
+    fn tick(&mut self) -> VoidResultChip8 {
+        while let Some(event) = self.script.front() {
+            if event.tick > self.ticks {
+                break;
+            }
+
+            if event.down {
+                self.buffer.hold(event.key)?;
+            } else {
+                self.buffer.release(event.key)?;
+            }
+            self.script.pop_front();
+        }
+
+        self.ticks += 1;
+        Ok(())
+    }
+}
@@ -1,5 +1,6 @@
-use super::{InputBuffer, KEY_NUM};
-use crate::core::{Error, VoidResultChip8};
+use super::{Hotkey, InputBuffer, KEY_NUM};
+use crate::core::{Error, ResultChip8, VoidResultChip8};
+use std::time::Duration;
 use winapi::{
     shared::minwindef::DWORD,
     um::{
@@ -20,34 +21,41 @@ pub struct NativeInputManager {
 }
 
 impl NativeInputManager {
-    pub fn new() -> NativeInputManager {
+    pub fn new() -> ResultChip8<NativeInputManager> {
         unsafe {
             let handle = processenv::GetStdHandle(winbase::STD_INPUT_HANDLE);
             if handle == INVALID_HANDLE_VALUE {
-                panic!("Unable to get a handle to STDIN");
+                return Err(Error::new_str("Unable to get a handle to STDIN"));
             }
 
             let mut old_mode: DWORD = 0;
             if consoleapi::GetConsoleMode(handle, &mut old_mode) == 0 {
-                panic!("Unable to read console mode");
+                return Err(Error::new_str("Unable to read console mode"));
             }
 
             if consoleapi::SetConsoleMode(handle, ENABLE_PROCESSED_INPUT) == 0 {
-                panic!("Unable to set console mode");
+                return Err(Error::new_str("Unable to set console mode"));
             }
 
+            // Created before anything else can fail, so that dropping it restores the console mode
+            let manager = NativeInputManager { handle, old_mode };
+
             if wincon::SetConsoleCP(CP_UTF8) == 0 {
-                panic!("Unable to set console input to UTF-8");
+                return Err(Error::new_str("Unable to set console input to UTF-8"));
             }
 
             if wincon::SetConsoleOutputCP(CP_UTF8) == 0 {
-                panic!("Unable to set console output to UTF-8");
+                return Err(Error::new_str("Unable to set console output to UTF-8"));
             }
 
-            NativeInputManager { handle, old_mode }
+            Ok(manager)
         }
     }
 
+    pub fn set_release_timeout(&mut self, _timeout: Duration) {
+        // The console reports key releases, so there is nothing to emulate
+    }
+
     pub fn tick(&mut self, buffer: &mut InputBuffer) -> VoidResultChip8 {
         unsafe {
             loop {
@@ -89,6 +97,22 @@ impl NativeInputManager {
         event: &KEY_EVENT_RECORD,
         buffer: &mut InputBuffer,
     ) -> VoidResultChip8 {
+        let hotkey = match event.wVirtualKeyCode as i32 {
+            winuser::VK_F5 => Some(Hotkey::SaveState),
+            winuser::VK_F6 => Some(Hotkey::FastForward),
+            winuser::VK_F7 => Some(Hotkey::Rewind),
+            winuser::VK_F8 => Some(Hotkey::SlowMotion),
+            winuser::VK_F9 => Some(Hotkey::LoadState),
+            _ => None,
+        };
+
+        if let Some(hotkey) = hotkey {
+            if event.bKeyDown == 1 {
+                buffer.push_hotkey(hotkey);
+            }
+            return Ok(());
+        }
+
         let chip8_key: i32 = match event.wVirtualKeyCode as i32 {
             winuser::VK_NUMPAD0 => 0x0,
             winuser::VK_SPACE => 0x0,
@@ -0,0 +1,31 @@
+//! CHIP-8 emulator core: the machine, its memory, display and input, and the opcode decoder.
+
+mod binary;
+pub mod assembler;
+pub mod audio;
+pub mod core;
+pub mod cpu;
+pub mod debugger;
+pub mod disassembler;
+pub mod display;
+pub mod gdb;
+pub mod input;
+pub mod memory;
+pub mod movie;
+pub mod octo;
+pub mod opcodes;
+pub mod profiler;
+pub mod quirks;
+pub mod random;
+pub mod registers;
+pub mod rewind;
+pub mod scheduler;
+pub mod state;
+pub mod timers;
+pub mod trace;
+
+pub use crate::core::{Address, Error, ResultChip8, VoidResultChip8, Word};
+pub use crate::cpu::CPU;
+pub use crate::display::{TerminalVideoListener, VideoListener, VideoMemory};
+pub use crate::memory::{MemoryMapper, MemoryRange, ReadMemory, WriteMemory};
+pub use crate::opcodes::Opcode;
@@ -1,23 +1,37 @@
-mod core;
-mod cpu;
-mod display;
-mod input;
-mod memory;
-mod opcodes;
-mod registers;
-mod timers;
-
-use crate::core::{Address, Error, ResultChip8, VoidResultChip8, Word};
-use crate::cpu::CPU;
-use crate::display::{TerminalVideoListener, VideoMemory};
-use crate::input::{InputManager, KEY_NUM};
-use crate::memory::{ByteArrayMemory, MemoryRange, WriteMemory};
-use crate::opcodes::Opcode;
+use chip8::assembler;
+use chip8::audio::{AudioSink, BellAudio, NullAudio, WavAudio};
+use chip8::cpu::PROGRAM_START;
+use chip8::debugger::Debugger;
+use chip8::disassembler::{self, LineContent};
+use chip8::gdb::GdbStub;
+use chip8::input::{
+    Hotkey, InputManager, InputSource, ScriptedInput, DEFAULT_RELEASE_TIMEOUT, KEY_NUM,
+};
+use chip8::memory::MemoryRange;
+use chip8::movie::{self, Movie, MovieHeader, MovieRecorder};
+use chip8::octo;
+use chip8::profiler::Profiler;
+use chip8::quirks::Quirks;
+use chip8::random::SeededRandom;
+use chip8::rewind::RewindBuffer;
+use chip8::scheduler::{FrameScheduler, Speed};
+use chip8::state::SaveState;
+use chip8::trace::{TraceFormat, TraceReader, TraceWriter};
+use chip8::{
+    Address, Error, Opcode, ResultChip8, TerminalVideoListener, VideoMemory, VoidResultChip8, CPU,
+};
 
+use std::cell::RefCell;
+use std::collections::VecDeque;
 use std::env;
 use std::fs::File;
-use std::io::{self, Read, Write};
+use std::io::{self, BufWriter, Read, Write};
+use std::net::TcpListener;
+use std::rc::Rc;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc;
+use std::sync::Arc;
 use std::thread;
 use std::time::Duration;
 
@@ -26,7 +40,13 @@ use ansi_term::{
     Color::{Black, Blue, Green, Purple, Red, Yellow},
 };
 
-use ctrlc;
+const FRAMES_PER_SECOND: usize = 60;
+const DEFAULT_REWIND_SECONDS: usize = 10;
+const REWIND_STEP_FRAMES: usize = FRAMES_PER_SECOND / 4;
+// How many of the most executed instructions the profiler report shows
+const PROFILE_HOTSPOTS: usize = 20;
+// How many instructions trace-diff shows before and after the first difference
+const TRACE_DIFF_CONTEXT: usize = 5;
 
 fn main() -> VoidResultChip8 {
     let result = do_main();
@@ -43,6 +63,7 @@ fn main() -> VoidResultChip8 {
 }
 
 fn do_main() -> VoidResultChip8 {
+    #[cfg(target_family = "windows")]
     ansi_term::enable_ansi_support()
         .map_err(|x| Error::new(format!("Unable to turn on ANSI support: Error code {}", x)))?;
 
@@ -53,102 +74,682 @@ fn do_main() -> VoidResultChip8 {
 
     match args[1].as_str() {
         "run" => run(&args),
+        "debug" => debug(&args),
         "view" => disassemble(&args),
+        "trace-diff" => trace_diff(&args),
+        "asm" => assemble(&args),
         "test-display" => test_display(),
-        "test-input" => test_input(),
+        "test-input" => test_input(&args),
         _ => print_help(),
     }
 }
 
 fn print_help() -> VoidResultChip8 {
-    println!("chip8 run <path>");
+    println!("chip8 run [--release-timeout <ms>] [--seed <n>] [--load-state <file>] [--save-state <file>]");
+    println!(
+        "          [--rewind <seconds>] [--record <file> | --play <file>] [--quirks <preset>]"
+    );
+    println!("          [--audio <sink>] [--ipf <n>] [--fast-forward <x>] [--slow-motion <x>]");
+    println!(
+        "          [--trace <file> [--trace-format <format>] [--trace-range <start>-<end>]...]"
+    );
+    println!("          [--profile] [--profile-folded <file>]");
+    println!("          <path>");
     println!("\temulate the ROM located at <path>");
-    println!("chip8 view [-o] <path>");
-    println!("\tprint a disassembly of the ROM located at <path>");
-    println!("\t-o: Offset output by 1 byte");
+    println!("\t--release-timeout: On terminals that don't report key releases, how long a key");
+    println!(
+        "\t                   stays down after being pressed. Default: {}ms",
+        DEFAULT_RELEASE_TIMEOUT.as_millis()
+    );
+    println!("\t--seed: Make the run reproducible, using <n> as the random seed");
+    println!("\t--load-state: Restore the machine state saved in <file> before starting");
+    println!("\t--save-state: Where F5 saves the machine state to and F9 restores it from.");
+    println!("\t              Default: the --load-state file, or <path>.state");
+    println!("\t--rewind: How many seconds of history F7 can rewind through, 0 to disable.");
+    println!("\t          Default: {}", DEFAULT_REWIND_SECONDS);
+    println!("\t--record: Record all input into the movie <file>. Implies --seed, with a random");
+    println!("\t          seed if none is given");
+    println!("\t--play: Replay the input recorded in the movie <file>, with the seed, --ipf and");
+    println!("\t        --quirks it was recorded with");
+    println!("\t--quirks: Emulate the behaviour of another CHIP-8 implementation for ambiguous");
+    println!(
+        "\t          instructions. One of: {}",
+        Quirks::PRESET_NAMES.join(", ")
+    );
+    println!("\t--audio: Where sound goes: none, bell (the terminal bell) or wav:<file>.");
+    println!("\t         Default: bell");
+    println!(
+        "\t--ipf: How many instructions run in every 60Hz frame. Default: {}",
+        FrameScheduler::DEFAULT_INSTRUCTIONS_PER_FRAME
+    );
+    println!(
+        "\t--fast-forward: How many times faster F6 makes the emulation run. Default: {}",
+        FrameScheduler::DEFAULT_FAST_FORWARD
+    );
+    println!(
+        "\t--slow-motion: How many times slower F8 makes the emulation run. Default: {}",
+        1.0 / FrameScheduler::DEFAULT_SLOW_MOTION
+    );
+    println!("\t--trace: Write every instruction that runs and the registers after it to <file>");
+    println!("\t--trace-format: text or binary, which is smaller for long runs. Default: text");
+    println!("\t--trace-range: Only trace the instructions stored between two hex addresses.");
+    println!("\t               Can be given multiple times");
+    println!(
+        "\t--profile: When the program exits or Ctrl+C is pressed, show which instructions and"
+    );
+    println!("\t           subroutines ran the most");
+    println!("\t--profile-folded: Also write the call stacks that ran to <file> in the folded");
+    println!("\t                  format flamegraph tools take. Implies --profile");
+    println!("chip8 debug [--quirks <preset>] [--ipf <n>] [--gdb <port> | --gdb-socket <path>]");
+    println!("          <path>");
+    println!("\tstep through the ROM located at <path> with an interactive debugger");
+    println!("\t--gdb: Let a GDB client control the ROM through a local TCP port");
+    println!("\t--gdb-socket: Let a GDB client control the ROM through a Unix socket");
+    println!("chip8 view [--linear [-o] | --source <file>] [--quirks <preset>] <path>");
+    println!("\tprint a disassembly of the ROM located at <path>, following every jump and call");
+    println!("\tfrom the start of the program and showing anything they don't reach as data");
+    println!("\t--linear: Decode every pair of bytes in order instead");
+    println!("\t-o: Offset linear output by 1 byte");
+    println!("\t--source: Write the disassembly to <file> as labelled source for chip8 asm");
+    println!("\t--quirks: Show instructions the way they behave with the given preset");
+    println!("chip8 asm <source> -o <rom>");
+    println!("\tassemble the source file <source> into the ROM <rom>, as Octo if it ends in .8o");
+    println!("chip8 trace-diff <a> <b>");
+    println!("\tfind the first instruction where the traces <a> and <b> written by --trace differ");
     println!("chip8 test-display");
     println!("\ttests the terminal display mode");
-    println!("chip8 test-input");
+    println!("chip8 test-input [--release-timeout <ms>]");
     println!("\ttests the terminal input manager");
     Ok(())
 }
 
-fn run(args: &Vec<String>) -> VoidResultChip8 {
-    if args.len() != 3 {
-        return print_help();
+struct RunOptions {
+    path: String,
+    release_timeout: Duration,
+    seed: Option<u64>,
+    load_state: Option<String>,
+    save_state: Option<String>,
+    rewind_seconds: usize,
+    record: Option<String>,
+    play: Option<String>,
+    quirks: Option<Quirks>,
+    audio: String,
+    instructions_per_frame: Option<u32>,
+    fast_forward: f64,
+    slow_motion: f64,
+    trace: Option<String>,
+    trace_format: TraceFormat,
+    trace_ranges: Vec<MemoryRange>,
+    profile: bool,
+    profile_folded: Option<String>,
+}
+
+impl RunOptions {
+    /// Returns `None` if the arguments are not valid
+    fn parse(args: &[String]) -> ResultChip8<Option<RunOptions>> {
+        let mut path = None;
+        let mut options = RunOptions {
+            path: String::new(),
+            release_timeout: DEFAULT_RELEASE_TIMEOUT,
+            seed: None,
+            load_state: None,
+            save_state: None,
+            rewind_seconds: DEFAULT_REWIND_SECONDS,
+            record: None,
+            play: None,
+            quirks: None,
+            audio: "bell".to_owned(),
+            instructions_per_frame: None,
+            fast_forward: FrameScheduler::DEFAULT_FAST_FORWARD,
+            slow_motion: 1.0 / FrameScheduler::DEFAULT_SLOW_MOTION,
+            trace: None,
+            trace_format: TraceFormat::Text,
+            trace_ranges: Vec::new(),
+            profile: false,
+            profile_folded: None,
+        };
+
+        let mut i = 2;
+        while i < args.len() {
+            match args[i].as_str() {
+                "--release-timeout" => {
+                    i += 1;
+                    options.release_timeout = parse_millis(args.get(i))?;
+                }
+                "--seed" => {
+                    i += 1;
+                    options.seed = Some(parse_arg(args.get(i), "seed")?);
+                }
+                "--load-state" => {
+                    i += 1;
+                    options.load_state = Some(parse_arg(args.get(i), "save state path")?);
+                }
+                "--save-state" => {
+                    i += 1;
+                    options.save_state = Some(parse_arg(args.get(i), "save state path")?);
+                }
+                "--rewind" => {
+                    i += 1;
+                    options.rewind_seconds = parse_arg(args.get(i), "rewind duration in seconds")?;
+                }
+                "--record" => {
+                    i += 1;
+                    options.record = Some(parse_arg(args.get(i), "movie path")?);
+                }
+                "--play" => {
+                    i += 1;
+                    options.play = Some(parse_arg(args.get(i), "movie path")?);
+                }
+                "--quirks" => {
+                    i += 1;
+                    options.quirks = Some(parse_quirks(args.get(i))?);
+                }
+                "--audio" => {
+                    i += 1;
+                    options.audio = parse_arg(args.get(i), "audio sink")?;
+                }
+                "--ipf" => {
+                    i += 1;
+                    options.instructions_per_frame =
+                        Some(parse_arg(args.get(i), "instructions per frame")?);
+                }
+                "--fast-forward" => {
+                    i += 1;
+                    options.fast_forward = parse_arg(args.get(i), "fast-forward multiplier")?;
+                }
+                "--slow-motion" => {
+                    i += 1;
+                    options.slow_motion = parse_arg(args.get(i), "slow motion divisor")?;
+                }
+                "--trace" => {
+                    i += 1;
+                    options.trace = Some(parse_arg(args.get(i), "trace path")?);
+                }
+                "--trace-format" => {
+                    i += 1;
+                    let format: String = parse_arg(args.get(i), "trace format")?;
+                    options.trace_format = match format.as_str() {
+                        "text" => TraceFormat::Text,
+                        "binary" => TraceFormat::Binary,
+                        x => {
+                            return Err(Error::new(format!(
+                                "Unknown trace format {}, expected text or binary",
+                                x
+                            )))
+                        }
+                    };
+                }
+                "--trace-range" => {
+                    i += 1;
+                    options.trace_ranges.push(parse_range(args.get(i))?);
+                }
+                "--profile" => options.profile = true,
+                "--profile-folded" => {
+                    i += 1;
+                    options.profile = true;
+                    options.profile_folded = Some(parse_arg(args.get(i), "folded stacks path")?);
+                }
+                x if path.is_none() => path = Some(x.to_owned()),
+                _ => return Ok(None),
+            };
+            i += 1;
+        }
+
+        options.path = match path {
+            Some(x) => x,
+            None => return Ok(None),
+        };
+
+        if options.instructions_per_frame == Some(0) {
+            return Err(Error::new_str(
+                "At least one instruction must run per frame",
+            ));
+        }
+
+        if !(options.fast_forward > 0.0 && options.slow_motion > 0.0) {
+            return Err(Error::new_str(
+                "The fast-forward and slow motion multipliers must be positive",
+            ));
+        }
+
+        if options.record.is_some() && options.play.is_some() {
+            return Err(Error::new_str(
+                "Can't record and play a movie at the same time",
+            ));
+        }
+
+        if options.load_state.is_some() && (options.record.is_some() || options.play.is_some()) {
+            return Err(Error::new_str(
+                "Movies always start from power on, they can't be combined with --load-state",
+            ));
+        }
+
+        Ok(Some(options))
     }
+}
 
-    let mut file = File::open(&args[2])?;
+fn run(args: &[String]) -> VoidResultChip8 {
+    let options = match RunOptions::parse(args)? {
+        Some(x) => x,
+        None => return print_help(),
+    };
+
+    let mut file = File::open(&options.path)?;
     let mut buffer = Vec::new();
     file.read_to_end(&mut buffer)?;
 
-    let mut cpu = CPU::new();
-    cpu.memory.add(
-        ByteArrayMemory::zero(0x1000 - 0x200),
-        MemoryRange::new(0x200, 0xFFF),
-        "Main Memory",
-    )?;
+    let rom_hash = movie::rom_hash(&buffer);
+    let mut seed = options.seed;
+    let mut instructions_per_frame = options
+        .instructions_per_frame
+        .unwrap_or(FrameScheduler::DEFAULT_INSTRUCTIONS_PER_FRAME);
+    let mut quirks = options.quirks.unwrap_or_default();
+
+    let mut cpu = if let Some(play) = &options.play {
+        let movie = Movie::load_file(play)?;
+        if movie.header.rom_hash != rom_hash {
+            return Err(Error::new_str(
+                "The movie was recorded with a different ROM",
+            ));
+        }
+
+        if options.quirks.is_some_and(|x| x != movie.header.quirks) {
+            return Err(Error::new_str(
+                "The movie was recorded with different quirks, leave out --quirks to use them",
+            ));
+        }
+
+        seed = Some(movie.header.seed);
+        quirks = movie.header.quirks;
+        instructions_per_frame = movie.header.instructions_per_frame;
+        CPU::new(movie.to_input()?)
+    } else {
+        let mut input = InputManager::new()?;
+        input.set_release_timeout(options.release_timeout);
+
+        match &options.record {
+            None => CPU::new(input),
+            Some(record) => {
+                let header = MovieHeader {
+                    rom_hash,
+                    seed: *seed.get_or_insert_with(rand::random),
+                    instructions_per_frame,
+                    quirks,
+                };
+                CPU::new(MovieRecorder::create_file(record, header, input)?)
+            }
+        }
+    };
+
+    cpu.quirks = quirks;
+    cpu.audio_sink = create_audio_sink(&options.audio)?;
+    cpu.load_rom(&buffer)?;
+    if let Some(seed) = seed {
+        cpu.random = Box::new(SeededRandom::new(seed));
+    }
+    let display = cpu.vram.attach(TerminalVideoListener::new())?;
+
+    if let Some(trace) = &options.trace {
+        let mut writer = TraceWriter::create_file(trace, options.trace_format)?;
+        writer.ranges = options.trace_ranges.clone();
+        cpu.tracers.push(Box::new(writer));
+    }
+
+    // Ctrl+C ends the run instead of the process, so that the terminal is restored and the
+    // profile, if any, is reported
+    let stop = Arc::new(AtomicBool::new(false));
+    let handler_stop = stop.clone();
+    ctrlc::set_handler(move || handler_stop.store(true, Ordering::SeqCst))?;
+
+    let profiler = if options.profile {
+        let profiler = Rc::new(RefCell::new(Profiler::new()));
+        cpu.tracers.push(Box::new(profiler.clone()));
+        Some(profiler)
+    } else {
+        None
+    };
 
-    for i in 0..buffer.len() {
-        let addr = Address::new(0x200 + i as u16);
-        let word = Word::new(buffer[i]);
-        cpu.memory.set(addr, word)?;
+    if let Some(load_state) = &options.load_state {
+        SaveState::load_file(load_state)?.restore(&mut cpu)?;
     }
 
-    cpu.vram.attach(TerminalVideoListener::new())?;
+    let mut session = Session {
+        state_path: match (options.save_state, options.load_state) {
+            (Some(x), _) | (None, Some(x)) => x,
+            (None, None) => format!("{}.state", options.path),
+        },
+        rewind: match options.rewind_seconds {
+            0 => None,
+            x => Some(RewindBuffer::new(x * FRAMES_PER_SECOND)),
+        },
+        recording: options.record.is_some(),
+        stop,
+        load_errors: Vec::new(),
+    };
+
+    let mut scheduler = FrameScheduler::new(instructions_per_frame);
+    scheduler.fast_forward = options.fast_forward;
+    scheduler.slow_motion = 1.0 / options.slow_motion;
+    // The profile is still reported when the program fails, since it shows what led up to it
+    let result = scheduler.run(&mut cpu, |cpu, scheduler| session.on_frame(cpu, scheduler));
+    cpu.vram.detach(display)?;
 
-    cpu.tick_loop()
+    for err in session.load_errors.iter() {
+        println!("{}", err);
+    }
+
+    if let Some(profiler) = profiler {
+        let profiler = profiler.borrow();
+        profiler.write_report(&mut io::stdout(), PROFILE_HOTSPOTS)?;
+        if let Some(path) = &options.profile_folded {
+            let mut file = BufWriter::new(File::create(path)?);
+            profiler.write_folded(&mut file)?;
+            file.flush()?;
+        }
+    }
+
+    result
 }
 
-fn disassemble(args: &Vec<String>) -> VoidResultChip8 {
-    if args.len() < 3 || args.len() > 4 {
-        return print_help();
+fn create_audio_sink(name: &str) -> ResultChip8<Box<dyn AudioSink>> {
+    Ok(match name {
+        "none" => Box::new(NullAudio),
+        "bell" => Box::new(BellAudio::new()),
+        x if x.starts_with("wav:") => Box::new(WavAudio::create_file(&x["wav:".len()..])?),
+        x => return Err(Error::new(format!("Unknown audio sink {}", x))),
+    })
+}
+
+/// Emulator features that run in between frames during `chip8 run`
+struct Session {
+    state_path: String,
+    rewind: Option<RewindBuffer>,
+    // Going back in time would make the recorded movie impossible to play back
+    recording: bool,
+    /// Set from another thread to end the run
+    stop: Arc<AtomicBool>,
+    /// Save states that couldn't be loaded, shown once the screen is no longer in use
+    load_errors: Vec<Error>,
+}
+
+impl Session {
+    fn on_frame(&mut self, cpu: &mut CPU, scheduler: &mut FrameScheduler) -> VoidResultChip8 {
+        while let Some(hotkey) = cpu.input.poll_hotkey() {
+            match hotkey {
+                Hotkey::SaveState => SaveState::capture(cpu)?.save_file(&self.state_path)?,
+                Hotkey::LoadState | Hotkey::Rewind if self.recording => {}
+                Hotkey::LoadState => {
+                    // The state might just not have been saved yet, so the run goes on
+                    let result = SaveState::load_file(&self.state_path).and_then(|x| {
+                        x.restore(cpu).map_err(|x| {
+                            x.chain(format!("Unable to restore save state {}", self.state_path))
+                        })
+                    });
+                    if let Err(err) = result {
+                        self.load_errors.push(err);
+                    }
+                }
+                Hotkey::Rewind => self.rewind(cpu)?,
+                Hotkey::FastForward => scheduler.toggle(Speed::FastForward),
+                Hotkey::SlowMotion => scheduler.toggle(Speed::SlowMotion),
+            };
+        }
+
+        // After a rewind this is the restored state, so the next rewind continues from it
+        if let Some(rewind) = &mut self.rewind {
+            rewind.push(SaveState::capture(cpu)?);
+        }
+
+        if self.stop.load(Ordering::SeqCst) {
+            scheduler.stop();
+        }
+
+        Ok(())
+    }
+
+    fn rewind(&mut self, cpu: &mut CPU) -> VoidResultChip8 {
+        let rewind = match &mut self.rewind {
+            None => return Ok(()),
+            Some(x) => x,
+        };
+
+        let mut target = None;
+        for _ in 0..REWIND_STEP_FRAMES {
+            match rewind.pop() {
+                None => break,
+                Some(x) => target = Some(x),
+            };
+        }
+
+        if let Some(state) = target {
+            state.restore(cpu)?;
+        }
+
+        Ok(())
     }
+}
+
+fn parse_quirks(arg: Option<&String>) -> ResultChip8<Quirks> {
+    let name: String = parse_arg(arg, "quirks preset")?;
+    Quirks::from_name(&name).ok_or_else(|| {
+        Error::new(format!(
+            "Unknown quirks preset {}, expected one of: {}",
+            name,
+            Quirks::PRESET_NAMES.join(", ")
+        ))
+    })
+}
+
+fn parse_arg<T: FromStr>(arg: Option<&String>, name: &str) -> ResultChip8<T> {
+    let arg = arg.ok_or_else(|| Error::new(format!("Missing {}", name)))?;
+    arg.parse::<T>()
+        .map_err(|_| Error::new(format!("Invalid {}: {}", name, arg)))
+}
 
-    let (mut file, offset) = if args.len() == 3 {
-        (File::open(&args[2])?, false)
-    } else if args[2] != "-o" {
+/// Parses an inclusive range of hex addresses, like 200-2FF
+fn parse_range(arg: Option<&String>) -> ResultChip8<MemoryRange> {
+    let arg: String = parse_arg(arg, "address range")?;
+    let invalid = || Error::new(format!("Invalid address range: {}", arg));
+
+    let mut parts = arg.splitn(2, '-');
+    let mut next = || {
+        let part = parts.next().ok_or_else(invalid)?;
+        u16::from_str_radix(part, 16).map_err(|_| invalid())
+    };
+    let (start, end) = (next()?, next()?);
+
+    if start > end {
+        return Err(invalid());
+    }
+    Ok(MemoryRange::new(start, end))
+}
+
+fn parse_millis(arg: Option<&String>) -> ResultChip8<Duration> {
+    let millis = parse_arg(arg, "duration in milliseconds")?;
+    Ok(Duration::from_millis(millis))
+}
+
+fn trace_diff(args: &[String]) -> VoidResultChip8 {
+    if args.len() != 4 {
         return print_help();
-    } else {
-        (File::open(&args[3])?, true)
+    }
+
+    let mut a = TraceReader::open_file(&args[2])?;
+    let mut b = TraceReader::open_file(&args[3])?;
+    let mut history = VecDeque::with_capacity(TRACE_DIFF_CONTEXT);
+    let mut count = 0u64;
+
+    loop {
+        let (entry_a, entry_b) = match (a.next().transpose()?, b.next().transpose()?) {
+            (Some(x), Some(y)) => (x, y),
+            (None, None) => {
+                println!("The traces are identical, {} instructions long", count);
+                return Ok(());
+            }
+            (entry_a, entry_b) => {
+                for entry in history.iter() {
+                    println!("  {}", entry);
+                }
+
+                let (shorter, longer) = if entry_a.is_none() {
+                    (&args[2], &args[3])
+                } else {
+                    (&args[3], &args[2])
+                };
+                println!(
+                    "{} ends after {} instructions, {} goes on with:",
+                    shorter, count, longer
+                );
+
+                if let Some(entry) = entry_a {
+                    println!("- {}", entry);
+                    for entry in a.take(TRACE_DIFF_CONTEXT - 1) {
+                        println!("- {}", entry?);
+                    }
+                }
+                if let Some(entry) = entry_b {
+                    println!("+ {}", entry);
+                    for entry in b.take(TRACE_DIFF_CONTEXT - 1) {
+                        println!("+ {}", entry?);
+                    }
+                }
+                return Ok(());
+            }
+        };
+
+        let differences = entry_a.differences(&entry_b);
+        if differences.is_empty() {
+            if history.len() == TRACE_DIFF_CONTEXT {
+                history.pop_front();
+            }
+            history.push_back(entry_a);
+            count += 1;
+            continue;
+        }
+
+        for entry in history.iter() {
+            println!("  {}", entry);
+        }
+        println!("- {}", entry_a);
+        println!("+ {}", entry_b);
+        println!(
+            "The traces differ after {} instructions: {}",
+            count,
+            differences.join(", ")
+        );
+
+        println!("{} goes on with:", args[2]);
+        for entry in a.take(TRACE_DIFF_CONTEXT) {
+            println!("- {}", entry?);
+        }
+        println!("{} goes on with:", args[3]);
+        for entry in b.take(TRACE_DIFF_CONTEXT) {
+            println!("+ {}", entry?);
+        }
+        return Ok(());
+    }
+}
+
+fn disassemble(args: &[String]) -> VoidResultChip8 {
+    let mut path = None;
+    let mut linear = false;
+    let mut offset = false;
+    let mut source = None;
+    let mut quirks = Quirks::default();
+
+    let mut args = args.iter().skip(2);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--linear" => linear = true,
+            "-o" => offset = true,
+            "--source" => match args.next() {
+                Some(x) => source = Some(x),
+                None => return print_help(),
+            },
+            "--quirks" => quirks = parse_quirks(args.next())?,
+            x if path.is_none() => path = Some(x),
+            _ => return print_help(),
+        };
+    }
+
+    let path = match path {
+        Some(x) if (linear || !offset) && !(linear && source.is_some()) => x,
+        _ => return print_help(),
     };
 
+    let mut file = File::open(path)?;
     let mut buffer = Vec::with_capacity(0x1000);
     file.read_to_end(&mut buffer)?;
 
-    let mut i = 0;
-    while i < buffer.len() {
-        let addr = Address::new(0x0200 + i as u16);
+    let start = Address::new(PROGRAM_START);
+    if let Some(source) = source {
+        let mut out = BufWriter::new(File::create(source)?);
+        disassembler::write_source(&buffer, start, &mut out)?;
+        out.flush()?;
+        return Ok(());
+    }
 
-        print!("{} | ", Blue.paint(addr.to_string()));
+    let lines = if linear {
+        disassembler::disassemble(&buffer, start, offset)
+    } else {
+        disassembler::disassemble_flow(&buffer, start)
+    };
 
-        if i == 0 && offset {
-            println!("__{:02X}: Lone byte at the start of file", buffer[i]);
-        } else if i + 1 >= buffer.len() {
-            println!("{:02X}__: Lone byte at the end of file", buffer[i]);
-        } else {
-            let value = u16::from_be_bytes([buffer[i], buffer[i + 1]]);
-            print!("{:04X}: ", value);
+    for line in lines {
+        print!("{} | ", Blue.paint(line.address.to_string()));
 
-            match Opcode::decode(value) {
-                Err(x) => println!("{} {}", Red.paint("ERROR"), Red.paint(x.to_string())),
-                Ok(x) => println!("{}", color_opcode(x)),
-            };
+        match line.content {
+            LineContent::LoneByte(x) if line.address == start => {
+                println!("__{:02X}: Lone byte at the start of file", x)
+            }
+            LineContent::LoneByte(x) => println!("{:02X}__: Lone byte at the end of file", x),
+            LineContent::Data(x) => {
+                let bits: String = (0..8)
+                    .map(|i| if x & (0x80 >> i) != 0 { '#' } else { '.' })
+                    .collect();
+                println!("{:02X}  : {}", x, Black.bold().paint(bits))
+            }
+            LineContent::Opcode(value, x) => {
+                println!("{:04X}: {}", value, color_opcode(quirks.apply(x)))
+            }
+            LineContent::Invalid(value, x) => println!(
+                "{:04X}: {} {}",
+                value,
+                Red.paint("ERROR"),
+                Red.paint(x.to_string())
+            ),
         };
-
-        i += if i == 0 && offset { 1 } else { 2 };
     }
 
     Ok(())
 }
 
+fn assemble(args: &[String]) -> VoidResultChip8 {
+    let (source, rom) = match &args[2..] {
+        [source, o, rom] if o == "-o" => (source, rom),
+        [o, rom, source] if o == "-o" => (source, rom),
+        _ => return print_help(),
+    };
+
+    let bytes = if source.ends_with(".8o") {
+        octo::compile_file(source)?
+    } else {
+        assembler::assemble_file(source)?
+    };
+    std::fs::write(rom, &bytes)?;
+    println!("Wrote {} bytes to {}", bytes.len(), rom);
+    Ok(())
+}
+
 fn color_opcode<'a>(code: Opcode) -> ANSIString<'a> {
     let s = code.to_string();
     match code {
         Opcode::Nop => Black.bold().paint(s),
-        Opcode::Return | Opcode::Jump(_) | Opcode::Call(_) | Opcode::CallNative(_) => {
-            Purple.paint(s)
-        }
+        Opcode::Return
+        | Opcode::Exit
+        | Opcode::Jump(_)
+        | Opcode::Call(_)
+        | Opcode::CallNative(_) => Purple.paint(s),
         Opcode::CondJump { .. } => Green.paint(s),
         _ => Yellow.paint(s),
     }
@@ -162,14 +763,15 @@ fn test_display() -> VoidResultChip8 {
     ctrlc::set_handler(move || tx.send(()).unwrap())?;
 
     'main: loop {
-        for y in 0..VideoMemory::BIT_HEIGHT {
-            for x in 0..VideoMemory::BIT_WIDTH {
+        for y in 0..vram.height() {
+            for x in 0..vram.width() {
                 if rx.try_recv().is_ok() {
                     break 'main;
                 }
 
-                vram.flip(x, y)?;
+                vram.flip(x, y, 1)?;
             }
+            vram.present()?;
             thread::sleep(Duration::from_millis(1));
         }
     }
@@ -178,13 +780,142 @@ fn test_display() -> VoidResultChip8 {
     Ok(())
 }
 
-fn test_input() -> VoidResultChip8 {
-    let mut input = InputManager::new();
+fn debug(args: &[String]) -> VoidResultChip8 {
+    let mut path = None;
+    let mut quirks = Quirks::default();
+    let mut instructions_per_frame = FrameScheduler::DEFAULT_INSTRUCTIONS_PER_FRAME;
+    let mut gdb_port: Option<u16> = None;
+    let mut gdb_socket: Option<String> = None;
+
+    let mut i = 2;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--quirks" => {
+                i += 1;
+                quirks = parse_quirks(args.get(i))?;
+            }
+            "--ipf" => {
+                i += 1;
+                instructions_per_frame = parse_arg(args.get(i), "instructions per frame")?;
+            }
+            "--gdb" => {
+                i += 1;
+                gdb_port = Some(parse_arg(args.get(i), "port")?);
+            }
+            "--gdb-socket" => {
+                i += 1;
+                gdb_socket = Some(parse_arg(args.get(i), "socket path")?);
+            }
+            x if path.is_none() => path = Some(x),
+            _ => return print_help(),
+        };
+        i += 1;
+    }
+
+    let path = match path {
+        Some(x) => x,
+        None => return print_help(),
+    };
+
+    let mut file = File::open(path)?;
+    let mut buffer = Vec::new();
+    file.read_to_end(&mut buffer)?;
+
+    let mut cpu = CPU::new(ScriptedInput::new());
+    cpu.quirks = quirks;
+    cpu.load_rom(&buffer)?;
+
+    let mut debugger = Debugger::new(cpu, instructions_per_frame);
+    match (gdb_port, gdb_socket) {
+        (Some(_), Some(_)) => {
+            return Err(Error::new_str(
+                "Only one of --gdb and --gdb-socket can be used",
+            ))
+        }
+        (Some(port), None) => return serve_gdb_tcp(debugger, port),
+        (None, Some(socket)) => return serve_gdb_unix(debugger, &socket),
+        (None, None) => {}
+    };
+
+    let interrupt = debugger.interrupt.clone();
+    ctrlc::set_handler(move || interrupt.store(true, Ordering::SeqCst))?;
+
+    println!("Type help for a list of commands");
+    let stdin = io::stdin();
+    let mut stdout = io::stdout();
+    loop {
+        print!("(chip8) ");
+        stdout.flush()?;
+
+        let mut line = String::new();
+        if stdin.read_line(&mut line)? == 0 {
+            return Ok(());
+        }
+
+        // Ignore any Ctrl+C pressed while waiting for the command
+        debugger.interrupt.store(false, Ordering::SeqCst);
+
+        match debugger.execute(&line, &mut stdout) {
+            Ok(true) => {}
+            Ok(false) => return Ok(()),
+            Err(err) => println!("{}", err),
+        };
+    }
+}
+
+fn serve_gdb_tcp(debugger: Debugger, port: u16) -> VoidResultChip8 {
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+    println!("Waiting for GDB on {}", listener.local_addr()?);
+
+    let (stream, _) = listener.accept()?;
+    // Packets are tiny and each one waits for the reply to the previous one
+    stream.set_nodelay(true)?;
+    serve_gdb(debugger, stream.try_clone()?, stream)
+}
+
+#[cfg(unix)]
+fn serve_gdb_unix(debugger: Debugger, path: &str) -> VoidResultChip8 {
+    use std::os::unix::net::UnixListener;
+
+    let listener = UnixListener::bind(path)?;
+    println!("Waiting for GDB on {}", path);
+
+    let (stream, _) = listener.accept()?;
+    let result = serve_gdb(debugger, stream.try_clone()?, stream);
+    std::fs::remove_file(path)?;
+    result
+}
+
+#[cfg(not(unix))]
+fn serve_gdb_unix(_: Debugger, _: &str) -> VoidResultChip8 {
+    Err(Error::new_str(
+        "Unix sockets are not supported on this platform",
+    ))
+}
+
+fn serve_gdb(
+    mut debugger: Debugger,
+    reader: impl Read + Send + 'static,
+    writer: impl Write,
+) -> VoidResultChip8 {
+    debugger.cpu.vram.attach(TerminalVideoListener::new())?;
+    GdbStub::new(debugger).serve(reader, writer)
+}
+
+fn test_input(args: &[String]) -> VoidResultChip8 {
+    let release_timeout = match args.get(2).map(String::as_str) {
+        None => DEFAULT_RELEASE_TIMEOUT,
+        Some("--release-timeout") if args.len() == 4 => parse_millis(args.get(3))?,
+        _ => return print_help(),
+    };
+
+    let mut input = InputManager::new()?;
+    input.set_release_timeout(release_timeout);
     let (tx, rx) = mpsc::sync_channel(0);
     ctrlc::set_handler(move || tx.send(()).unwrap())?;
 
     // Clear screen and hide cursor
-    io::stdout().write(b"\x1B[m\x1B[2J\x1B[?25l")?;
+    io::stdout().write_all(b"\x1B[m\x1B[2J\x1B[?25l")?;
 
     loop {
         if rx.try_recv().is_ok() {
@@ -194,7 +925,7 @@ fn test_input() -> VoidResultChip8 {
         input.tick()?;
 
         // Cursor to top-left
-        io::stdout().write(b"\x1B[H\x1B[?25l")?;
+        io::stdout().write_all(b"\x1B[H\x1B[?25l")?;
 
         for i in 0..KEY_NUM {
             let state = input.is_down(i)?;
@@ -1,8 +1,8 @@
 use crate::core::{Address, Error, ResultChip8, VoidResultChip8, Word};
+use std::cell::Cell;
 use std::fmt::{self, Display, Formatter, Write};
 use std::fs::File;
 use std::io::Read;
-use std::ops::Range;
 
 pub trait ReadMemory {
     fn get(&self, addr: Address) -> ResultChip8<Word>;
@@ -22,11 +22,8 @@ pub trait WriteMemory {
     fn set(&mut self, addr: Address, value: Word) -> VoidResultChip8;
 
     fn set_range(&mut self, start_addr: Address, values: &[Word]) -> VoidResultChip8 {
-        let mut addr = start_addr;
-
-        for value in values {
-            self.set(addr, value.clone())?;
-            addr += 1;
+        for (i, value) in values.iter().enumerate() {
+            self.set(start_addr + i, *value)?;
         }
 
         Ok(())
@@ -36,7 +33,7 @@ pub trait WriteMemory {
 pub trait ReadWriteMemory: ReadMemory + WriteMemory {}
 impl<T> ReadWriteMemory for T where T: ReadMemory + WriteMemory {}
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
 pub struct MemoryRange {
     pub min: Address,
     pub max: Address,
@@ -82,6 +79,7 @@ impl IntoIterator for MemoryRange {
         MemoryRangeIterator {
             current: self.min,
             end: self.max,
+            finished: false,
         }
     }
 }
@@ -90,17 +88,23 @@ impl IntoIterator for MemoryRange {
 pub struct MemoryRangeIterator {
     current: Address,
     end: Address,
+    // Needed because a range can end at the highest address, after which `current` would wrap around
+    finished: bool,
 }
 
 impl Iterator for MemoryRangeIterator {
     type Item = Address;
 
     fn next(&mut self) -> Option<Address> {
-        if self.current > self.end {
+        if self.finished || self.current > self.end {
             None
         } else {
             let old = self.current;
-            self.current += 1;
+            if old == self.end {
+                self.finished = true;
+            } else {
+                self.current += 1;
+            }
             Some(old)
         }
     }
@@ -140,9 +144,17 @@ impl<T: WriteMemory> WriteMemory for WriteMemoryWrapper<T> {
     }
 }
 
+#[derive(Eq, PartialEq, Clone, Copy, Debug)]
+enum BankAccess {
+    Read,
+    Write,
+    ReadWrite,
+}
+
 struct MemoryMapperBank {
     name: String,
     range: MemoryRange,
+    access: BankAccess,
     delegate: Box<dyn ReadWriteMemory>,
 }
 
@@ -152,19 +164,218 @@ impl MemoryMapperBank {
     }
 }
 
+/// A copy of everything stored in a bank, used to save and restore its state
+#[derive(Clone, Debug)]
+pub struct BankContents {
+    pub name: String,
+    pub range: MemoryRange,
+    pub data: Vec<Word>,
+}
+
 impl Display for MemoryMapperBank {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         write!(f, "{} ({})", self.name, self.range)
     }
 }
 
+/// The way an instruction touched memory
+#[derive(Eq, PartialEq, Clone, Copy, Debug)]
+pub enum AccessKind {
+    Read,
+    Write,
+    Execute,
+}
+
+/// The accesses a watchpoint looks for
+#[derive(Eq, PartialEq, Clone, Copy, Debug)]
+pub enum WatchKind {
+    Read,
+    Write,
+    ReadWrite,
+    Execute,
+}
+
+impl WatchKind {
+    pub fn matches(self, kind: AccessKind) -> bool {
+        match self {
+            WatchKind::Read => kind == AccessKind::Read,
+            WatchKind::Write => kind == AccessKind::Write,
+            WatchKind::ReadWrite => kind != AccessKind::Execute,
+            WatchKind::Execute => kind == AccessKind::Execute,
+        }
+    }
+}
+
+impl Display for WatchKind {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let name = match self {
+            WatchKind::Read => "r",
+            WatchKind::Write => "w",
+            WatchKind::ReadWrite => "rw",
+            WatchKind::Execute => "x",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// An access that matched a watchpoint
+#[derive(Eq, PartialEq, Clone, Copy, Debug)]
+pub struct MemoryAccess {
+    pub watchpoint: usize,
+    pub kind: AccessKind,
+    pub addr: Address,
+    pub value: Word,
+    /// Address of the instruction that made the access
+    pub program_counter: Address,
+}
+
+/// What happens when a watchpoint matches an access
+pub enum WatchAction {
+    /// Keep the access until it's taken with `MemoryMapper::take_watch_hit`, so that whatever
+    /// is running the CPU can stop it
+    Pause,
+    Callback(Box<dyn Fn(&MemoryAccess)>),
+}
+
+pub struct Watchpoint {
+    pub kind: WatchKind,
+    pub range: MemoryRange,
+    /// Only match accesses of this value, like a specific value being written
+    pub value: Option<Word>,
+    pub action: WatchAction,
+}
+
+impl Watchpoint {
+    pub fn new(kind: WatchKind, range: MemoryRange) -> Watchpoint {
+        Watchpoint {
+            kind,
+            range,
+            value: None,
+            action: WatchAction::Pause,
+        }
+    }
+
+    fn matches(&self, kind: AccessKind, addr: Address, value: Word) -> bool {
+        self.kind.matches(kind)
+            && self.range.contains(addr)
+            && self.value.is_none_or(|x| x == value)
+    }
+}
+
 pub struct MemoryMapper {
     banks: Vec<MemoryMapperBank>,
+    watchpoints: Vec<(usize, Watchpoint)>,
+    next_watchpoint: usize,
+    program_counter: Address,
+    watch_hit: Cell<Option<MemoryAccess>>,
+    write_log: Option<Vec<(Address, Word)>>,
+}
+
+impl Default for MemoryMapper {
+    fn default() -> Self {
+        MemoryMapper::new()
+    }
 }
 
 impl MemoryMapper {
-    pub fn new<'a>() -> MemoryMapper {
-        MemoryMapper { banks: Vec::new() }
+    pub fn new() -> MemoryMapper {
+        MemoryMapper {
+            banks: Vec::new(),
+            watchpoints: Vec::new(),
+            next_watchpoint: 1,
+            program_counter: Address::ZERO,
+            watch_hit: Cell::new(None),
+            write_log: None,
+        }
+    }
+
+    /// Starts keeping every write, until they're taken with `take_write_log`
+    pub fn start_write_log(&mut self) {
+        self.write_log = Some(Vec::new());
+    }
+
+    /// Returns the writes made since `start_write_log` and stops keeping them
+    pub fn take_write_log(&mut self) -> Vec<(Address, Word)> {
+        self.write_log.take().unwrap_or_default()
+    }
+
+    /// Adds a watchpoint, returning the ID it can be removed with
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) -> usize {
+        let id = self.next_watchpoint;
+        self.next_watchpoint += 1;
+        self.watchpoints.push((id, watchpoint));
+        id
+    }
+
+    /// Returns whether there was a watchpoint with that ID
+    pub fn remove_watchpoint(&mut self, id: usize) -> bool {
+        let before = self.watchpoints.len();
+        self.watchpoints.retain(|(x, _)| *x != id);
+        before != self.watchpoints.len()
+    }
+
+    pub fn watchpoints(&self) -> impl Iterator<Item = (usize, &Watchpoint)> {
+        self.watchpoints.iter().map(|(id, x)| (*id, x))
+    }
+
+    /// Returns the first access that matched a pausing watchpoint since the last call
+    pub fn take_watch_hit(&mut self) -> Option<MemoryAccess> {
+        self.watch_hit.take()
+    }
+
+    /// Sets the address of the instruction that makes the accesses from now on, which is
+    /// reported to watchpoints
+    pub fn begin_instruction(&mut self, program_counter: Address) {
+        self.program_counter = program_counter;
+    }
+
+    /// Reads an address without triggering watchpoints, for tools that inspect memory
+    pub fn peek(&self, addr: Address) -> ResultChip8<Word> {
+        let bank = self
+            .banks
+            .iter()
+            .find(|x| x.range.contains(addr))
+            .ok_or_else(|| Error::new(format!("No bank mapped to address {}", addr)))?;
+
+        let addr_offset = bank.offset(addr);
+        bank.delegate.get(addr_offset).map_err(|x| {
+            x.chain(format!(
+                "Unable to read address {} from bank {}",
+                addr, bank
+            ))
+        })
+    }
+
+    /// Reads part of an instruction that is about to run
+    pub fn fetch(&self, addr: Address) -> ResultChip8<Word> {
+        let value = self.peek(addr)?;
+        self.check_watchpoints(AccessKind::Execute, addr, value);
+        Ok(value)
+    }
+
+    fn check_watchpoints(&self, kind: AccessKind, addr: Address, value: Word) {
+        for (id, watchpoint) in self.watchpoints.iter() {
+            if !watchpoint.matches(kind, addr, value) {
+                continue;
+            }
+
+            let access = MemoryAccess {
+                watchpoint: *id,
+                kind,
+                addr,
+                value,
+                program_counter: self.program_counter,
+            };
+
+            match &watchpoint.action {
+                WatchAction::Pause => {
+                    if self.watch_hit.get().is_none() {
+                        self.watch_hit.set(Some(access));
+                    }
+                }
+                WatchAction::Callback(callback) => callback(&access),
+            };
+        }
     }
 
     pub fn add(
@@ -172,6 +383,114 @@ impl MemoryMapper {
         bank: impl ReadWriteMemory + 'static,
         range: MemoryRange,
         name: &str,
+    ) -> VoidResultChip8 {
+        self.add_bank(Box::new(bank), range, name, BankAccess::ReadWrite)
+    }
+
+    pub fn add_read(
+        &mut self,
+        bank: impl ReadMemory + 'static,
+        range: MemoryRange,
+        name: &str,
+    ) -> VoidResultChip8 {
+        self.add_bank(
+            Box::new(ReadMemoryWrapper(bank)),
+            range,
+            name,
+            BankAccess::Read,
+        )
+    }
+
+    pub fn add_write(
+        &mut self,
+        bank: impl WriteMemory + 'static,
+        range: MemoryRange,
+        name: &str,
+    ) -> VoidResultChip8 {
+        self.add_bank(
+            Box::new(WriteMemoryWrapper(bank)),
+            range,
+            name,
+            BankAccess::Write,
+        )
+    }
+
+    /// Copies the contents of every bank that can be both read and written
+    pub fn dump_writable_banks(&self) -> ResultChip8<Vec<BankContents>> {
+        let mut result = Vec::new();
+
+        for bank in self.banks.iter() {
+            if bank.access != BankAccess::ReadWrite {
+                continue;
+            }
+
+            let mut data = Vec::with_capacity(usize::from(bank.range.len()) + 1);
+            for addr in bank.range {
+                data.push(bank.delegate.get(bank.offset(addr))?);
+            }
+
+            result.push(BankContents {
+                name: bank.name.clone(),
+                range: bank.range,
+                data,
+            });
+        }
+
+        Ok(result)
+    }
+
+    /// Overwrites a bank with contents previously returned by `dump_writable_banks`
+    pub fn restore_bank(&mut self, contents: &BankContents) -> VoidResultChip8 {
+        let index = self.find_restorable_bank(contents)?;
+        let bank = &mut self.banks[index];
+        for (addr, value) in bank.range.into_iter().zip(contents.data.iter()) {
+            let offset = bank.offset(addr);
+            bank.delegate.set(offset, *value)?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns an error if `restore_bank` would fail for `contents`, without changing anything
+    pub fn check_bank(&self, contents: &BankContents) -> VoidResultChip8 {
+        self.find_restorable_bank(contents).map(|_| ())
+    }
+
+    fn find_restorable_bank(&self, contents: &BankContents) -> ResultChip8<usize> {
+        let index = self
+            .banks
+            .iter()
+            .position(|x| x.name == contents.name && x.range == contents.range)
+            .ok_or_else(|| {
+                Error::new(format!(
+                    "No bank {} ({}) is mapped",
+                    contents.name, contents.range
+                ))
+            })?;
+
+        let bank = &self.banks[index];
+        if bank.access != BankAccess::ReadWrite {
+            return Err(Error::new(format!("Bank {} is not writable", bank)));
+        }
+
+        if contents.data.len() != usize::from(bank.range.len()) + 1 {
+            return Err(Error::new(format!(
+                "Expected {} bytes for bank {}, got {}",
+                usize::from(bank.range.len()) + 1,
+                bank,
+                contents.data.len()
+            )));
+        }
+
+        Ok(index)
+    }
+
+    fn add_bank(
+        &mut self,
+        bank: Box<dyn ReadWriteMemory>,
+        range: MemoryRange,
+        name: &str,
+        access: BankAccess,
     ) -> VoidResultChip8 {
         let overlapping: Vec<&MemoryMapperBank> = self
             .banks
@@ -190,45 +509,18 @@ impl MemoryMapper {
         self.banks.push(MemoryMapperBank {
             name: name.to_owned(),
             range,
-            delegate: Box::new(bank),
+            access,
+            delegate: bank,
         });
-        return Ok(());
-    }
-
-    pub fn add_read(
-        &mut self,
-        bank: impl ReadMemory + 'static,
-        range: MemoryRange,
-        name: &str,
-    ) -> VoidResultChip8 {
-        self.add(ReadMemoryWrapper(bank), range, name)
-    }
-
-    pub fn add_write(
-        &mut self,
-        bank: impl WriteMemory + 'static,
-        range: MemoryRange,
-        name: &str,
-    ) -> VoidResultChip8 {
-        self.add(WriteMemoryWrapper(bank), range, name)
+        Ok(())
     }
 }
 
 impl ReadMemory for MemoryMapper {
     fn get(&self, addr: Address) -> ResultChip8<Word> {
-        let bank = self
-            .banks
-            .iter()
-            .find(|x| x.range.contains(addr))
-            .ok_or_else(|| Error::new(format!("No bank mapped to address {}", addr)))?;
-
-        let addr_offset = bank.offset(addr);
-        bank.delegate.get(addr_offset).map_err(|x| {
-            x.chain(format!(
-                "Unable to read address {} from bank {}",
-                addr, bank
-            ))
-        })
+        let value = self.peek(addr)?;
+        self.check_watchpoints(AccessKind::Read, addr, value);
+        Ok(value)
     }
 }
 
@@ -245,7 +537,13 @@ impl WriteMemory for MemoryMapper {
                 "Unable to write to address {} in bank {}",
                 addr, bank
             ))
-        })
+        })?;
+
+        if let Some(log) = &mut self.write_log {
+            log.push((addr, value));
+        }
+        self.check_watchpoints(AccessKind::Write, addr, value);
+        Ok(())
     }
 }
 
@@ -255,7 +553,7 @@ impl ByteArrayMemory {
     pub fn new<T: Into<Word> + Copy>(data: &[T]) -> ByteArrayMemory {
         let mut vec = Vec::with_capacity(data.len());
         for x in data {
-            vec.push(x.clone().into());
+            vec.push((*x).into());
         }
 
         ByteArrayMemory(vec)
@@ -288,7 +586,7 @@ impl ReadMemory for ByteArrayMemory {
     fn get(&self, addr: Address) -> ResultChip8<Word> {
         self.0
             .get(usize::from(addr))
-            .map(Clone::clone)
+            .copied()
             .ok_or_else(|| ByteArrayMemory::make_bounds_error(addr))
     }
 }
@@ -0,0 +1,251 @@
+use crate::binary::{
+    read_u16, read_u32, read_u64, read_u64_or_end, read_u8, write_u16, write_u32, write_u64,
+    write_u8,
+};
+use crate::core::{Error, ResultChip8, VoidResultChip8};
+use crate::input::{Hotkey, InputSource, ScriptedInput, KEY_NUM};
+use crate::quirks::{AddressIncrement, Quirks};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+
+const MAGIC: &[u8; 4] = b"C8MV";
+const VERSION: u16 = 2;
+
+/// Everything besides the input that must match for a movie to play back exactly
+#[derive(Eq, PartialEq, Clone, Copy, Debug)]
+pub struct MovieHeader {
+    pub rom_hash: u64,
+    pub seed: u64,
+    pub instructions_per_frame: u32,
+    pub quirks: Quirks,
+}
+
+#[derive(Eq, PartialEq, Clone, Copy, Debug)]
+pub struct MovieEvent {
+    pub frame: u64,
+    pub key: u8,
+    pub down: bool,
+}
+
+/// A recording of every key state change, made on a reproducible run
+pub struct Movie {
+    pub header: MovieHeader,
+    pub events: Vec<MovieEvent>,
+}
+
+/// Hashes a ROM with 64-bit FNV-1a, so movies can check they're played on the ROM they were recorded on
+pub fn rom_hash(rom: &[u8]) -> u64 {
+    rom.iter().fold(0xcbf29ce484222325, |hash, x| {
+        (hash ^ u64::from(*x)).wrapping_mul(0x100000001b3)
+    })
+}
+
+impl Movie {
+    pub fn load_file(path: &str) -> ResultChip8<Movie> {
+        let mut file = BufReader::new(File::open(path)?);
+        Movie::read(&mut file).map_err(|x| x.chain(format!("Unable to load movie from {}", path)))
+    }
+
+    pub fn read(input: &mut impl Read) -> ResultChip8<Movie> {
+        let header = read_header(input)?;
+
+        let mut events = Vec::new();
+        // Events go until the end of the file, since recording can be interrupted at any time
+        while let Some(frame) = read_u64_or_end(input)? {
+            let key = read_u8(input)?;
+            if usize::from(key) >= KEY_NUM {
+                return Err(Error::new(format!("Invalid key {:X} in movie", key)));
+            }
+
+            let down = read_u8(input)? != 0;
+            events.push(MovieEvent { frame, key, down });
+        }
+
+        Ok(Movie { header, events })
+    }
+
+    /// Creates an input source that replays this movie
+    pub fn to_input(&self) -> ResultChip8<ScriptedInput> {
+        let mut input = ScriptedInput::new();
+        for event in self.events.iter() {
+            let tick = event.frame * u64::from(self.header.instructions_per_frame);
+            input.schedule(tick, event.key, event.down)?;
+        }
+        Ok(input)
+    }
+}
+
+fn read_header(input: &mut impl Read) -> ResultChip8<MovieHeader> {
+    let mut magic = [0u8; 4];
+    input.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(Error::new_str("Not a movie file"));
+    }
+
+    let version = read_u16(input)?;
+    if version != VERSION {
+        return Err(Error::new(format!(
+            "Unsupported movie version {}, the newest supported version is {}",
+            version, VERSION
+        )));
+    }
+
+    Ok(MovieHeader {
+        rom_hash: read_u64(input)?,
+        seed: read_u64(input)?,
+        instructions_per_frame: read_u32(input)?,
+        quirks: read_quirks(input)?,
+    })
+}
+
+const SHIFT_USES_VY: u8 = 1 << 0;
+const JUMP_USES_VX: u8 = 1 << 1;
+const CLIP_SPRITES: u8 = 1 << 2;
+const LOGIC_RESETS_FLAG: u8 = 1 << 3;
+const EXTENDED_MEMORY: u8 = 1 << 4;
+
+fn read_quirks(input: &mut impl Read) -> ResultChip8<Quirks> {
+    let flags = read_u8(input)?;
+    if flags >> 5 != 0 {
+        return Err(Error::new(format!("Invalid quirks {:02X} in movie", flags)));
+    }
+
+    let load_store_increment = match read_u8(input)? {
+        0 => AddressIncrement::None,
+        1 => AddressIncrement::X,
+        2 => AddressIncrement::XPlusOne,
+        x => {
+            return Err(Error::new(format!(
+                "Invalid address increment {} in movie",
+                x
+            )))
+        }
+    };
+
+    Ok(Quirks {
+        shift_uses_vy: flags & SHIFT_USES_VY != 0,
+        load_store_increment,
+        jump_uses_vx: flags & JUMP_USES_VX != 0,
+        clip_sprites: flags & CLIP_SPRITES != 0,
+        logic_resets_flag: flags & LOGIC_RESETS_FLAG != 0,
+        extended_memory: flags & EXTENDED_MEMORY != 0,
+    })
+}
+
+fn write_quirks(out: &mut impl Write, quirks: &Quirks) -> VoidResultChip8 {
+    let mut flags = 0;
+    for (enabled, flag) in [
+        (quirks.shift_uses_vy, SHIFT_USES_VY),
+        (quirks.jump_uses_vx, JUMP_USES_VX),
+        (quirks.clip_sprites, CLIP_SPRITES),
+        (quirks.logic_resets_flag, LOGIC_RESETS_FLAG),
+        (quirks.extended_memory, EXTENDED_MEMORY),
+    ] {
+        if enabled {
+            flags |= flag;
+        }
+    }
+    write_u8(out, flags)?;
+
+    let increment = match quirks.load_store_increment {
+        AddressIncrement::None => 0,
+        AddressIncrement::X => 1,
+        AddressIncrement::XPlusOne => 2,
+    };
+    write_u8(out, increment)
+}
+
+/// Wraps another input source and records its key state changes into a movie.
+///
+/// Keys only change at the start of a frame, so that playback can reproduce them exactly.
+pub struct MovieRecorder<I: InputSource> {
+    inner: I,
+    out: Box<dyn Write>,
+    instructions_per_frame: u32,
+    ticks: u64,
+    keys: [bool; KEY_NUM],
+}
+
+impl<I: InputSource> MovieRecorder<I> {
+    pub fn create_file(path: &str, header: MovieHeader, inner: I) -> ResultChip8<MovieRecorder<I>> {
+        let file = BufWriter::new(File::create(path)?);
+        MovieRecorder::new(file, header, inner)
+    }
+
+    pub fn new(
+        out: impl Write + 'static,
+        header: MovieHeader,
+        inner: I,
+    ) -> ResultChip8<MovieRecorder<I>> {
+        let mut recorder = MovieRecorder {
+            inner,
+            out: Box::new(out),
+            instructions_per_frame: header.instructions_per_frame.max(1),
+            ticks: 0,
+            keys: [false; KEY_NUM],
+        };
+
+        recorder.out.write_all(MAGIC)?;
+        write_u16(&mut recorder.out, VERSION)?;
+        write_u64(&mut recorder.out, header.rom_hash)?;
+        write_u64(&mut recorder.out, header.seed)?;
+        write_u32(&mut recorder.out, header.instructions_per_frame)?;
+        write_quirks(&mut recorder.out, &header.quirks)?;
+        recorder.out.flush()?;
+
+        Ok(recorder)
+    }
+
+    fn record_frame(&mut self) -> VoidResultChip8 {
+        self.inner.tick()?;
+
+        let frame = self.ticks / u64::from(self.instructions_per_frame);
+        let mut changed = false;
+
+        for key in 0..KEY_NUM {
+            let down = self.inner.is_down(key)?;
+            if down == self.keys[key] {
+                continue;
+            }
+
+            self.keys[key] = down;
+            changed = true;
+
+            write_u64(&mut self.out, frame)?;
+            write_u8(&mut self.out, key as u8)?;
+            write_u8(&mut self.out, down as u8)?;
+        }
+
+        if changed {
+            // Recording usually ends with the process being interrupted, so don't keep anything buffered
+            self.out.flush()?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<I: InputSource> InputSource for MovieRecorder<I> {
+    fn is_down(&self, key: usize) -> ResultChip8<bool> {
+        self.keys
+            .get(key)
+            .copied()
+            .ok_or_else(|| Error::new_str("Key index out of bounds"))
+    }
+
+    fn tick(&mut self) -> VoidResultChip8 {
+        if self
+            .ticks
+            .is_multiple_of(u64::from(self.instructions_per_frame))
+        {
+            self.record_frame()?;
+        }
+
+        self.ticks += 1;
+        Ok(())
+    }
+
+    fn poll_hotkey(&mut self) -> Option<Hotkey> {
+        self.inner.poll_hotkey()
+    }
+}
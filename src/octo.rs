@@ -0,0 +1,1026 @@
+use crate::assembler::Location;
+use crate::core::{Address, Error, ResultChip8, VoidResultChip8, Word};
+use crate::cpu::PROGRAM_START;
+use crate::opcodes::{Condition, Opcode, OpcodeParam, Operation, Timer};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fs;
+use std::rc::Rc;
+
+/// How many macros can be expanded, to stop macros that expand themselves forever
+const MAX_EXPANSIONS: usize = 0x10000;
+
+/// Compiles the Octo source file at `path` into a ROM to be loaded at the start of the program
+pub fn compile_file(path: &str) -> ResultChip8<Vec<u8>> {
+    let source = fs::read_to_string(path)
+        .map_err(|x| Error::new(format!("Unable to read {}: {}", path, x)))?;
+    compile_named(&source, path)
+}
+
+/// Compiles Octo source into a ROM to be loaded at the start of the program
+pub fn compile(source: &str) -> ResultChip8<Vec<u8>> {
+    compile_named(source, "<source>")
+}
+
+fn compile_named(source: &str, name: &str) -> ResultChip8<Vec<u8>> {
+    let mut compiler = Compiler::new(source, Rc::new(name.to_owned()))?;
+    while !compiler.tokens.is_empty() {
+        compiler.statement()?;
+    }
+    compiler.finish()
+}
+
+#[derive(Clone, Debug)]
+struct Token {
+    text: String,
+    location: Location,
+}
+
+struct Macro {
+    args: Vec<String>,
+    body: Vec<Token>,
+}
+
+/// A block that's still open, waiting for the jumps out of it to be filled in
+enum Block {
+    If {
+        jump: usize,
+        location: Location,
+    },
+    Else {
+        jump: usize,
+        location: Location,
+    },
+    Loop {
+        start: u16,
+        breaks: Vec<usize>,
+        location: Location,
+    },
+}
+
+/// How a label used before it's defined gets filled in once it is
+enum Fixup {
+    /// The lowest 12 bits of an instruction
+    Address,
+    /// The operand of `i := long`
+    Long,
+    /// The bytes loaded into v0 and v1 by `:unpack`, with the nibble that goes in front
+    Unpack(u8),
+}
+
+#[derive(Clone, Copy)]
+enum Value {
+    Register(u8),
+    Number(Word),
+}
+
+/// A condition of `if` and `while`
+struct Test {
+    left: u8,
+    op: String,
+    right: Option<Value>,
+}
+
+struct Compiler {
+    /// Tokens still to be read, last first, so that macros can be expanded in place
+    tokens: Vec<Token>,
+    end: Location,
+    rom: Vec<u8>,
+    here: usize,
+    /// Whether the ROM starts with a jump to main, which isn't needed if main comes first
+    entry_jump: bool,
+    labels: HashMap<String, (u16, Location)>,
+    constants: HashMap<String, i64>,
+    aliases: HashMap<String, u8>,
+    macros: HashMap<String, Macro>,
+    fixups: Vec<(usize, Token, Fixup)>,
+    blocks: Vec<Block>,
+    expansions: usize,
+}
+
+impl Compiler {
+    fn new(source: &str, file: Rc<String>) -> ResultChip8<Compiler> {
+        let mut tokens = Vec::new();
+        for (i, line) in source.lines().enumerate() {
+            let mut chars = line.char_indices().peekable();
+            while let Some((start, c)) = chars.next() {
+                if c.is_whitespace() {
+                    continue;
+                }
+                if c == '#' {
+                    break;
+                }
+
+                let mut end = start + c.len_utf8();
+                while let Some((x, c)) = chars.peek() {
+                    if c.is_whitespace() {
+                        break;
+                    }
+                    end = x + c.len_utf8();
+                    chars.next();
+                }
+
+                tokens.push(Token {
+                    text: line[start..end].to_owned(),
+                    location: Location {
+                        file: file.clone(),
+                        line: i + 1,
+                        column: line[..start].chars().count() + 1,
+                    },
+                });
+            }
+        }
+
+        let end = Location {
+            line: source.lines().count().max(1),
+            column: source.lines().last().map_or(0, |x| x.chars().count()) + 1,
+            file,
+        };
+        tokens.reverse();
+
+        let mut compiler = Compiler {
+            tokens,
+            end,
+            rom: Vec::new(),
+            here: PROGRAM_START.into(),
+            entry_jump: true,
+            labels: HashMap::new(),
+            constants: HashMap::new(),
+            aliases: HashMap::new(),
+            macros: HashMap::new(),
+            fixups: Vec::new(),
+            blocks: Vec::new(),
+            expansions: 0,
+        };
+
+        // Filled in with a jump to main at the end
+        let start = compiler.end.clone();
+        compiler.jump_placeholder(&start)?;
+        Ok(compiler)
+    }
+
+    fn next(&mut self) -> ResultChip8<Token> {
+        self.tokens
+            .pop()
+            .ok_or_else(|| self.end.error("Unexpected end of file".to_owned()))
+    }
+
+    fn peek(&self) -> Option<&str> {
+        self.tokens.last().map(|x| x.text.as_str())
+    }
+
+    fn expect(&mut self, text: &str) -> ResultChip8<Token> {
+        let token = self.next()?;
+        if token.text != text {
+            return Err(token
+                .location
+                .error(format!("Expected `{}`, got `{}`", text, token.text)));
+        }
+        Ok(token)
+    }
+
+    fn statement(&mut self) -> VoidResultChip8 {
+        let token = self.next()?;
+        let at = &token.location;
+
+        match token.text.as_str() {
+            ":" => {
+                let name = self.new_name()?;
+                self.define_label(&name)?;
+            }
+            ":const" => {
+                let name = self.new_name()?;
+                let value = self.next()?;
+                let value = self.value(&value)?;
+                self.constants.insert(name.text, value);
+            }
+            ":alias" => {
+                let name = self.new_name()?;
+                let reg = self.next()?;
+                let reg = self.register(&reg)?;
+                self.aliases.insert(name.text, reg);
+            }
+            ":calc" => {
+                let name = self.new_name()?;
+                let value = self.calc()?;
+                self.constants.insert(name.text, value);
+            }
+            ":byte" => {
+                let value = match self.peek() {
+                    Some("{") => self.calc()?,
+                    _ => {
+                        let token = self.next()?;
+                        self.value(&token)?
+                    }
+                };
+                let value = byte(value, at)?;
+                self.write(&[value.into()], at)?;
+            }
+            ":org" => {
+                let token = self.next()?;
+                let addr = self.value(&token)?;
+                if addr < i64::from(PROGRAM_START) || addr > 0xFFFF {
+                    return Err(token
+                        .location
+                        .error(format!("Can't put code at {:#X}", addr)));
+                }
+                self.here = addr as usize;
+            }
+            ":macro" => {
+                let name = self.new_name()?;
+                let mut args = Vec::new();
+                loop {
+                    let arg = self.next()?;
+                    if arg.text == "{" {
+                        break;
+                    }
+                    args.push(arg.text);
+                }
+
+                let mut body = Vec::new();
+                let mut depth = 0;
+                loop {
+                    let token = self.next()?;
+                    match token.text.as_str() {
+                        "{" => depth += 1,
+                        "}" if depth == 0 => break,
+                        "}" => depth -= 1,
+                        _ => {}
+                    };
+                    body.push(token);
+                }
+                self.macros.insert(name.text, Macro { args, body });
+            }
+            ":unpack" => {
+                let nibble = self.next()?;
+                let nibble = self.value(&nibble)?;
+                if !(0..=0xF).contains(&nibble) {
+                    return Err(at.error(format!("{} doesn't fit in a nibble", nibble)));
+                }
+
+                let label = self.next()?;
+                let addr = self.address(&label, Fixup::Unpack(nibble as u8))?;
+                self.emit(load(0, (nibble << 4) | (i64::from(addr) >> 8)), at)?;
+                self.emit(load(1, i64::from(addr) & 0xFF), at)?;
+            }
+            ":breakpoint" => {
+                self.next()?;
+            }
+            ":monitor" => {
+                self.next()?;
+                self.next()?;
+            }
+
+            "return" | ";" => self.emit(Opcode::Return, at)?,
+            "clear" => self.emit(Opcode::ClearScreen, at)?,
+            "exit" => self.emit(Opcode::Exit, at)?,
+            "hires" => self.emit(Opcode::SetHighResolution(true), at)?,
+            "lores" => self.emit(Opcode::SetHighResolution(false), at)?,
+            "scroll-down" => {
+                let n = self.nibble()?;
+                self.emit(Opcode::ScrollDown(n), at)?
+            }
+            "scroll-up" => {
+                let n = self.nibble()?;
+                self.emit(Opcode::ScrollUp(n), at)?
+            }
+            "scroll-right" => self.emit(Opcode::ScrollRight, at)?,
+            "scroll-left" => self.emit(Opcode::ScrollLeft, at)?,
+            "plane" => {
+                let n = self.nibble()?;
+                self.emit(Opcode::SelectPlanes(n), at)?
+            }
+            "audio" => self.emit(Opcode::LoadAudioPattern, at)?,
+            "bcd" => {
+                let reg = self.next_register()?;
+                self.emit(Opcode::WriteBCD(reg), at)?
+            }
+            "save" | "load" => {
+                let start = self.next_register()?;
+                let opcode = match (self.peek(), token.text.as_str()) {
+                    (Some("-"), name) => {
+                        self.next()?;
+                        let end = self.next_register()?;
+                        if name == "save" {
+                            Opcode::DumpValueRange { start, end }
+                        } else {
+                            Opcode::LoadValueRange { start, end }
+                        }
+                    }
+                    (_, "save") => Opcode::DumpValueRegisters(start),
+                    _ => Opcode::LoadValueRegisters(start),
+                };
+                self.emit(opcode, at)?
+            }
+            "saveflags" => {
+                let reg = self.next_register()?;
+                self.emit(Opcode::DumpFlagRegisters(reg), at)?
+            }
+            "loadflags" => {
+                let reg = self.next_register()?;
+                self.emit(Opcode::LoadFlagRegisters(reg), at)?
+            }
+            "sprite" => {
+                let x = self.next_register()?;
+                let y = self.next_register()?;
+                let height = self.nibble()?;
+                self.emit(Opcode::Draw { x, y, height }, at)?
+            }
+            "jump" | "jump0" | "native" => {
+                let target = self.next()?;
+                let addr = Address::new(self.address(&target, Fixup::Address)?);
+                let opcode = match token.text.as_str() {
+                    "jump" => Opcode::Jump(addr),
+                    "jump0" => Opcode::OffsetJump(addr),
+                    _ => Opcode::CallNative(addr),
+                };
+                self.emit(opcode, at)?
+            }
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(":=")?;
+                let reg = self.next_register()?;
+                let opcode = match token.text.as_str() {
+                    "delay" => Opcode::SetTimer {
+                        reg,
+                        timer: Timer::Delay,
+                    },
+                    "buzzer" => Opcode::SetTimer {
+                        reg,
+                        timer: Timer::Sound,
+                    },
+                    _ => Opcode::SetPitch(reg),
+                };
+                self.emit(opcode, at)?
+            }
+            "i" => self.assign_address(at)?,
+
+            "if" => {
+                let test = self.test()?;
+                let then = self.next()?;
+                match then.text.as_str() {
+                    "then" => self.skip(&test, false, at)?,
+                    "begin" => {
+                        self.skip(&test, true, at)?;
+                        let jump = self.jump_placeholder(at)?;
+                        self.blocks.push(Block::If {
+                            jump,
+                            location: at.clone(),
+                        });
+                    }
+                    _ => {
+                        return Err(then
+                            .location
+                            .error(format!("Expected `then` or `begin`, got `{}`", then.text)))
+                    }
+                }
+            }
+            "else" => match self.blocks.pop() {
+                Some(Block::If { jump, .. }) => {
+                    let end = self.jump_placeholder(at)?;
+                    self.patch(jump, self.here, at)?;
+                    self.blocks.push(Block::Else {
+                        jump: end,
+                        location: at.clone(),
+                    });
+                }
+                _ => return Err(at.error("`else` without `if ... begin`".to_owned())),
+            },
+            "end" => match self.blocks.pop() {
+                Some(Block::If { jump, .. }) | Some(Block::Else { jump, .. }) => {
+                    self.patch(jump, self.here, at)?
+                }
+                _ => return Err(at.error("`end` without `if ... begin`".to_owned())),
+            },
+            "loop" => self.blocks.push(Block::Loop {
+                start: self.here as u16,
+                breaks: Vec::new(),
+                location: at.clone(),
+            }),
+            "while" => {
+                let test = self.test()?;
+                self.skip(&test, true, at)?;
+                let jump = self.jump_placeholder(at)?;
+                match self.blocks.iter_mut().rev().find_map(|x| match x {
+                    Block::Loop { breaks, .. } => Some(breaks),
+                    _ => None,
+                }) {
+                    Some(breaks) => breaks.push(jump),
+                    None => return Err(at.error("`while` outside of a loop".to_owned())),
+                };
+            }
+            "again" => match self.blocks.pop() {
+                Some(Block::Loop { start, breaks, .. }) => {
+                    self.emit(Opcode::Jump(Address::new(start)), at)?;
+                    for jump in breaks {
+                        self.patch(jump, self.here, at)?;
+                    }
+                }
+                _ => return Err(at.error("`again` without `loop`".to_owned())),
+            },
+
+            _ => {
+                if let Some(reg) = self.try_register(&token.text) {
+                    return self.assign(reg, at);
+                }
+                if let Some(value) = parse_number(&token.text) {
+                    let value = byte(value, at)?;
+                    return self.write(&[value.into()], at);
+                }
+                if self.macros.contains_key(&token.text) {
+                    return self.expand(&token);
+                }
+                if let Some(value) = self.constants.get(&token.text) {
+                    let value = byte(*value, at)?;
+                    return self.write(&[value.into()], at);
+                }
+                if !is_name(&token.text) {
+                    return Err(at.error(format!("Unexpected `{}`", token.text)));
+                }
+
+                let addr = self.address(&token, Fixup::Address)?;
+                self.emit(Opcode::Call(Address::new(addr)), at)?
+            }
+        };
+
+        Ok(())
+    }
+
+    /// Reads an assignment to a register, after the register itself
+    fn assign(&mut self, reg: u8, at: &Location) -> VoidResultChip8 {
+        let op = self.next()?;
+        let right = self.next()?;
+
+        let assign = |right: OpcodeParam, op: Operation| Opcode::Assign {
+            left_reg: reg,
+            right,
+            op,
+        };
+        let source = self.try_register(&right.text);
+
+        let opcode = match (op.text.as_str(), source) {
+            (":=", _) if right.text == "key" => Opcode::BlockOnKey(reg),
+            (":=", _) if right.text == "delay" => Opcode::GetDelayTimer(reg),
+            (":=", _) if right.text == "random" => {
+                let mask = self.next()?;
+                let mask = byte(self.value(&mask)?, &mask.location)?;
+                Opcode::Random { reg, mask }
+            }
+            (":=", Some(x)) => assign(OpcodeParam::Register(x), Operation::None),
+            (":=", None) => {
+                let value = byte(self.value(&right)?, &right.location)?;
+                assign(OpcodeParam::Immediate(value), Operation::None)
+            }
+            ("+=", Some(x)) => assign(OpcodeParam::Register(x), Operation::Add),
+            ("+=", None) => {
+                let value = byte(self.value(&right)?, &right.location)?;
+                assign(OpcodeParam::Immediate(value), Operation::Add)
+            }
+            ("-=", Some(x)) => assign(OpcodeParam::Register(x), Operation::Sub),
+            ("-=", None) => {
+                // There's no subtraction of an immediate, so this adds its negation modulo 256
+                let value = byte(self.value(&right)?, &right.location)?;
+                let value = Word::new(0u8.wrapping_sub(value.into()));
+                assign(OpcodeParam::Immediate(value), Operation::Add)
+            }
+            ("=-", Some(x)) => assign(OpcodeParam::Register(x), Operation::ReverseSub),
+            ("|=", Some(x)) => assign(OpcodeParam::Register(x), Operation::Or),
+            ("&=", Some(x)) => assign(OpcodeParam::Register(x), Operation::And),
+            ("^=", Some(x)) => assign(OpcodeParam::Register(x), Operation::Xor),
+            (">>=", Some(x)) => Opcode::Shift {
+                reg,
+                source: x,
+                right: true,
+            },
+            ("<<=", Some(x)) => Opcode::Shift {
+                reg,
+                source: x,
+                right: false,
+            },
+            _ => {
+                return Err(op
+                    .location
+                    .error(format!("Can't use `{}` with `{}`", op.text, right.text)))
+            }
+        };
+
+        self.emit(opcode, at)
+    }
+
+    /// Reads an assignment to i, after `i` itself
+    fn assign_address(&mut self, at: &Location) -> VoidResultChip8 {
+        let op = self.next()?;
+        let right = self.next()?;
+
+        let opcode = match (op.text.as_str(), right.text.as_str()) {
+            (":=", "hex") => Opcode::GetCharacterAddress(self.next_register()?),
+            (":=", "bighex") => Opcode::GetLargeCharacterAddress(self.next_register()?),
+            (":=", "long") => {
+                let target = self.next()?;
+                let addr = self.address(&target, Fixup::Long)?;
+                Opcode::AssignLongAddress(Address::new(addr))
+            }
+            (":=", _) => Opcode::AssignAddress(Address::new(self.address(&right, Fixup::Address)?)),
+            ("+=", _) => Opcode::AddAddress(self.register(&right)?),
+            _ => return Err(op.location.error(format!("Can't use `{}` with i", op.text))),
+        };
+
+        self.emit(opcode, at)
+    }
+
+    fn test(&mut self) -> ResultChip8<Test> {
+        let left = self.next_register()?;
+        let op = self.next()?;
+
+        let right = match op.text.as_str() {
+            "key" | "-key" => None,
+            "==" | "!=" | "<" | ">" | "<=" | ">=" => {
+                let right = self.next()?;
+                Some(match self.try_register(&right.text) {
+                    Some(x) => Value::Register(x),
+                    None => Value::Number(byte(self.value(&right)?, &right.location)?),
+                })
+            }
+            x => return Err(op.location.error(format!("Unknown comparison `{}`", x))),
+        };
+
+        Ok(Test {
+            left,
+            op: op.text,
+            right,
+        })
+    }
+
+    /// Writes instructions that skip the next one when `test` turns out to be `when`
+    fn skip(&mut self, test: &Test, when: bool, at: &Location) -> VoidResultChip8 {
+        let cond = |x: bool| {
+            if x {
+                Condition::Equal
+            } else {
+                Condition::NotEqual
+            }
+        };
+        let param = |x: &Value| match x {
+            Value::Register(x) => OpcodeParam::Register(*x),
+            Value::Number(x) => OpcodeParam::Immediate(*x),
+        };
+
+        let right = match &test.right {
+            None => {
+                let pressed = test.op == "key";
+                return self.emit(
+                    Opcode::CondKeyJump {
+                        reg: test.left,
+                        cond: cond(pressed == when),
+                    },
+                    at,
+                );
+            }
+            Some(x) => x,
+        };
+
+        let (left, right, flag) = match test.op.as_str() {
+            "==" | "!=" => {
+                return self.emit(
+                    Opcode::CondJump {
+                        left: OpcodeParam::Register(test.left),
+                        right: param(right),
+                        cond: cond((test.op == "==") == when),
+                    },
+                    at,
+                )
+            }
+            // The others compare with a subtraction, whose flag is set when the left side is
+            // greater than or equal to the right
+            "<" => (Value::Register(test.left), *right, 0),
+            ">=" => (Value::Register(test.left), *right, 1),
+            ">" => (*right, Value::Register(test.left), 0),
+            _ => (*right, Value::Register(test.left), 1),
+        };
+
+        match (left, right) {
+            (_, Value::Register(right)) => {
+                self.emit(load_param(0xF, param(&left)), at)?;
+                self.emit(
+                    Opcode::Assign {
+                        left_reg: 0xF,
+                        right: OpcodeParam::Register(right),
+                        op: Operation::Sub,
+                    },
+                    at,
+                )?;
+            }
+            (Value::Register(left), Value::Number(right)) => {
+                self.emit(load_param(0xF, OpcodeParam::Immediate(right)), at)?;
+                self.emit(
+                    Opcode::Assign {
+                        left_reg: 0xF,
+                        right: OpcodeParam::Register(left),
+                        op: Operation::ReverseSub,
+                    },
+                    at,
+                )?;
+            }
+            (Value::Number(_), Value::Number(_)) => unreachable!(),
+        };
+
+        self.emit(
+            Opcode::CondJump {
+                left: OpcodeParam::Register(0xF),
+                right: OpcodeParam::Immediate(Word::new(flag)),
+                cond: cond(when),
+            },
+            at,
+        )
+    }
+
+    fn expand(&mut self, name: &Token) -> VoidResultChip8 {
+        self.expansions += 1;
+        if self.expansions > MAX_EXPANSIONS {
+            return Err(name
+                .location
+                .error("Too many macros were expanded, is one expanding itself?".to_owned()));
+        }
+
+        let arg_num = self.macros[&name.text].args.len();
+        let mut args = Vec::with_capacity(arg_num);
+        for _ in 0..arg_num {
+            args.push(self.next()?);
+        }
+
+        let body = &self.macros[&name.text];
+        let expanded: Vec<Token> = body
+            .body
+            .iter()
+            .map(
+                |token| match body.args.iter().position(|x| *x == token.text) {
+                    Some(i) => args[i].clone(),
+                    None => token.clone(),
+                },
+            )
+            .collect();
+        self.tokens.extend(expanded.into_iter().rev());
+        Ok(())
+    }
+
+    /// Reads a `:calc` expression, from its opening brace to its closing one
+    fn calc(&mut self) -> ResultChip8<i64> {
+        let open = self.expect("{")?;
+        let mut tokens = Vec::new();
+        loop {
+            let token = self.next()?;
+            if token.text == "}" {
+                break;
+            }
+            tokens.push(token);
+        }
+
+        let mut tokens = &tokens[..];
+        let value = self.expression(&mut tokens, &open.location)?;
+        match tokens.first() {
+            Some(x) => Err(x.location.error(format!("Unexpected `{}`", x.text))),
+            None => Ok(value),
+        }
+    }
+
+    /// Evaluates an expression, right to left since Octo has no operator precedence
+    fn expression(&self, tokens: &mut &[Token], at: &Location) -> ResultChip8<i64> {
+        let left = self.term(tokens, at)?;
+
+        let op = match tokens.first() {
+            Some(x) if x.text != ")" => x.clone(),
+            _ => return Ok(left),
+        };
+        *tokens = &tokens[1..];
+        let right = self.expression(tokens, &op.location)?;
+
+        let fail = || {
+            op.location
+                .error(format!("Invalid operation {} {} {}", left, op.text, right))
+        };
+        Ok(match op.text.as_str() {
+            "+" => left.checked_add(right).ok_or_else(fail)?,
+            "-" => left.checked_sub(right).ok_or_else(fail)?,
+            "*" => left.checked_mul(right).ok_or_else(fail)?,
+            "/" => left.checked_div(right).ok_or_else(fail)?,
+            "%" => left.checked_rem(right).ok_or_else(fail)?,
+            "pow" => u32::try_from(right)
+                .ok()
+                .and_then(|x| left.checked_pow(x))
+                .ok_or_else(fail)?,
+            "&" => left & right,
+            "|" => left | right,
+            "^" => left ^ right,
+            "<<" => u32::try_from(right)
+                .ok()
+                .and_then(|x| left.checked_shl(x))
+                .ok_or_else(fail)?,
+            ">>" => u32::try_from(right)
+                .ok()
+                .and_then(|x| left.checked_shr(x))
+                .ok_or_else(fail)?,
+            "min" => left.min(right),
+            "max" => left.max(right),
+            "<" => i64::from(left < right),
+            ">" => i64::from(left > right),
+            "<=" => i64::from(left <= right),
+            ">=" => i64::from(left >= right),
+            "==" => i64::from(left == right),
+            "!=" => i64::from(left != right),
+            x => return Err(op.location.error(format!("Unknown operator `{}`", x))),
+        })
+    }
+
+    fn term(&self, tokens: &mut &[Token], at: &Location) -> ResultChip8<i64> {
+        let token = match tokens.split_first() {
+            Some((x, rest)) => {
+                *tokens = rest;
+                x
+            }
+            None => return Err(at.error("Expected a value".to_owned())),
+        };
+
+        Ok(match token.text.as_str() {
+            "(" => {
+                let value = self.expression(tokens, &token.location)?;
+                match tokens.split_first() {
+                    Some((x, rest)) if x.text == ")" => *tokens = rest,
+                    _ => return Err(token.location.error("Missing the closing `)`".to_owned())),
+                };
+                value
+            }
+            "-" => -self.term(tokens, at)?,
+            "~" => !self.term(tokens, at)?,
+            "!" => i64::from(self.term(tokens, at)? == 0),
+            "abs" => self.term(tokens, at)?.abs(),
+            "sign" => self.term(tokens, at)?.signum(),
+            "@" => {
+                let addr = self.term(tokens, at)?;
+                let index = usize::try_from(addr - i64::from(PROGRAM_START)).ok();
+                match index.and_then(|x| self.rom.get(x)) {
+                    Some(x) => i64::from(*x),
+                    None => 0,
+                }
+            }
+            "HERE" => self.here as i64,
+            _ => self.value(token)?,
+        })
+    }
+
+    fn define_label(&mut self, name: &Token) -> VoidResultChip8 {
+        // Nothing needs to jump to main if it's the first thing in the program
+        if name.text == "main" && self.entry_jump && self.here == 0x202 && self.rom.len() == 2 {
+            self.entry_jump = false;
+            self.rom.clear();
+            self.here = PROGRAM_START.into();
+            for (addr, _) in self.labels.values_mut() {
+                *addr = PROGRAM_START;
+            }
+        }
+
+        self.labels
+            .insert(name.text.clone(), (self.here as u16, name.location.clone()));
+        Ok(())
+    }
+
+    /// Reads the name of something being defined
+    fn new_name(&mut self) -> ResultChip8<Token> {
+        let name = self.next()?;
+        if !is_name(&name.text) || self.try_register(&name.text).is_some() {
+            return Err(name
+                .location
+                .error(format!("`{}` can't be used as a name", name.text)));
+        }
+
+        if let Some((_, previous)) = self.labels.get(&name.text) {
+            return Err(name.location.error(format!(
+                "`{}` was already defined at {}",
+                name.text, previous
+            )));
+        }
+        if self.constants.contains_key(&name.text)
+            || self.aliases.contains_key(&name.text)
+            || self.macros.contains_key(&name.text)
+        {
+            return Err(name
+                .location
+                .error(format!("`{}` was already defined", name.text)));
+        }
+
+        Ok(name)
+    }
+
+    fn try_register(&self, text: &str) -> Option<u8> {
+        if let Some(x) = self.aliases.get(text) {
+            return Some(*x);
+        }
+
+        let mut chars = text.chars();
+        match (chars.next(), chars.next(), chars.next()) {
+            (Some('v'), Some(x), None) | (Some('V'), Some(x), None) => {
+                x.to_digit(16).map(|x| x as u8)
+            }
+            _ => None,
+        }
+    }
+
+    fn register(&self, token: &Token) -> ResultChip8<u8> {
+        self.try_register(&token.text).ok_or_else(|| {
+            token
+                .location
+                .error(format!("Expected a register, got `{}`", token.text))
+        })
+    }
+
+    fn next_register(&mut self) -> ResultChip8<u8> {
+        let token = self.next()?;
+        self.register(&token)
+    }
+
+    fn nibble(&mut self) -> ResultChip8<u8> {
+        let token = self.next()?;
+        let value = self.value(&token)?;
+        if !(0..=0xF).contains(&value) {
+            return Err(token
+                .location
+                .error(format!("{} must be between 0 and 15", value)));
+        }
+        Ok(value as u8)
+    }
+
+    /// The value of a number, constant or label that's already defined
+    fn value(&self, token: &Token) -> ResultChip8<i64> {
+        if let Some(x) = parse_number(&token.text) {
+            return Ok(x);
+        }
+        if let Some(x) = self.constants.get(&token.text) {
+            return Ok(*x);
+        }
+        if let Some((x, _)) = self.labels.get(&token.text) {
+            return Ok(i64::from(*x));
+        }
+
+        Err(token
+            .location
+            .error(format!("Unknown value `{}`", token.text)))
+    }
+
+    /// The address of the instruction about to be written, which is filled in later if it's a
+    /// label that isn't defined yet
+    fn address(&mut self, token: &Token, fixup: Fixup) -> ResultChip8<u16> {
+        let max = match fixup {
+            Fixup::Long => 0xFFFF,
+            _ => 0xFFF,
+        };
+
+        if parse_number(&token.text).is_some()
+            || self.constants.contains_key(&token.text)
+            || self.labels.contains_key(&token.text)
+        {
+            let value = self.value(token)?;
+            if !(0..=max).contains(&value) {
+                return Err(token
+                    .location
+                    .error(format!("{:#X} is out of reach here", value)));
+            }
+            return Ok(value as u16);
+        }
+
+        if !is_name(&token.text) {
+            return Err(token
+                .location
+                .error(format!("Expected an address, got `{}`", token.text)));
+        }
+
+        self.fixups.push((self.offset(), token.clone(), fixup));
+        Ok(0)
+    }
+
+    fn jump_placeholder(&mut self, at: &Location) -> ResultChip8<usize> {
+        let offset = self.offset();
+        self.emit(Opcode::Jump(Address::ZERO), at)?;
+        Ok(offset)
+    }
+
+    /// Points the jump at `offset` to `addr`
+    fn patch(&mut self, offset: usize, addr: usize, at: &Location) -> VoidResultChip8 {
+        if addr > 0xFFF {
+            return Err(at.error(format!("{:#X} is out of reach for a jump", addr)));
+        }
+        self.rom[offset] = (self.rom[offset] & 0xF0) | (addr >> 8) as u8;
+        self.rom[offset + 1] = addr as u8;
+        Ok(())
+    }
+
+    fn offset(&self) -> usize {
+        self.here - usize::from(PROGRAM_START)
+    }
+
+    fn emit(&mut self, opcode: Opcode, at: &Location) -> VoidResultChip8 {
+        let bytes = opcode.encode_bytes().map_err(|x| at.error(x.to_string()))?;
+        self.write(&bytes, at)
+    }
+
+    fn write(&mut self, bytes: &[u8], at: &Location) -> VoidResultChip8 {
+        if self.here + bytes.len() > 0x10000 {
+            return Err(at.error("The program doesn't fit in memory".to_owned()));
+        }
+
+        let offset = self.offset();
+        if self.rom.len() < offset + bytes.len() {
+            self.rom.resize(offset + bytes.len(), 0);
+        }
+        self.rom[offset..offset + bytes.len()].copy_from_slice(bytes);
+        self.here += bytes.len();
+        Ok(())
+    }
+
+    fn finish(mut self) -> ResultChip8<Vec<u8>> {
+        if let Some(block) = self.blocks.last() {
+            return Err(match block {
+                Block::If { location, .. } | Block::Else { location, .. } => {
+                    location.error("Missing `end`".to_owned())
+                }
+                Block::Loop { location, .. } => location.error("Missing `again`".to_owned()),
+            });
+        }
+
+        let main = match self.labels.get("main") {
+            Some((x, _)) => *x,
+            None => return Err(self.end.error("The program has no `main` label".to_owned())),
+        };
+        if self.entry_jump {
+            let at = self.end.clone();
+            self.patch(0, main.into(), &at)?;
+        }
+
+        for (offset, token, fixup) in std::mem::take(&mut self.fixups) {
+            let addr = match self.labels.get(&token.text) {
+                Some((x, _)) => *x,
+                None => {
+                    return Err(token
+                        .location
+                        .error(format!("Unknown label `{}`", token.text)))
+                }
+            };
+
+            match fixup {
+                Fixup::Address => self.patch(offset, addr.into(), &token.location)?,
+                Fixup::Long => {
+                    self.rom[offset + 2..offset + 4].copy_from_slice(&addr.to_be_bytes())
+                }
+                Fixup::Unpack(nibble) => {
+                    self.rom[offset + 1] = (nibble << 4) | (addr >> 8) as u8;
+                    self.rom[offset + 3] = addr as u8;
+                }
+            };
+        }
+
+        Ok(self.rom)
+    }
+}
+
+fn load(reg: u8, value: i64) -> Opcode {
+    load_param(reg, OpcodeParam::Immediate(Word::new(value as u8)))
+}
+
+fn load_param(reg: u8, right: OpcodeParam) -> Opcode {
+    Opcode::Assign {
+        left_reg: reg,
+        right,
+        op: Operation::None,
+    }
+}
+
+fn byte(value: i64, at: &Location) -> ResultChip8<Word> {
+    if !(-0x80..=0xFF).contains(&value) {
+        return Err(at.error(format!("{} doesn't fit in a byte", value)));
+    }
+    Ok(Word::new(value as u8))
+}
+
+fn is_name(text: &str) -> bool {
+    let mut chars = text.chars();
+    chars
+        .next()
+        .is_some_and(|x| x.is_ascii_alphabetic() || x == '_')
+        && chars.all(|x| x.is_ascii_alphanumeric() || x == '_' || x == '-')
+}
+
+fn parse_number(text: &str) -> Option<i64> {
+    let (negative, text) = match text.strip_prefix('-') {
+        Some(x) => (true, x),
+        None => (false, text),
+    };
+
+    let value = if let Some(x) = text.strip_prefix("0x") {
+        i64::from_str_radix(x, 16).ok()?
+    } else if let Some(x) = text.strip_prefix("0b") {
+        i64::from_str_radix(x, 2).ok()?
+    } else if text.starts_with(|x: char| x.is_ascii_digit()) {
+        text.parse().ok()?
+    } else {
+        return None;
+    };
+
+    Some(if negative { -value } else { value })
+}
@@ -1,6 +1,7 @@
 use crate::core::{Address, Error, ResultChip8, Word};
 use std::cmp::PartialEq;
 use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
 
 pub type ValueRegisterIndex = u8;
 
@@ -14,6 +15,7 @@ pub enum Opcode {
     },
     Shift {
         reg: ValueRegisterIndex,
+        source: ValueRegisterIndex,
         right: bool,
     },
     Random {
@@ -23,11 +25,14 @@ pub enum Opcode {
 
     // Address Register
     AssignAddress(Address),
+    AssignLongAddress(Address),
     AddAddress(ValueRegisterIndex),
     GetCharacterAddress(ValueRegisterIndex),
+    GetLargeCharacterAddress(ValueRegisterIndex),
 
     // Flow Control
     Return,
+    Exit,
     Jump(Address),
     OffsetJump(Address),
     Call(Address),
@@ -45,6 +50,12 @@ pub enum Opcode {
         y: ValueRegisterIndex,
         height: u8,
     },
+    ScrollDown(u8),
+    ScrollUp(u8),
+    ScrollRight,
+    ScrollLeft,
+    SetHighResolution(bool),
+    SelectPlanes(u8),
 
     // IO
     BlockOnKey(ValueRegisterIndex),
@@ -60,11 +71,25 @@ pub enum Opcode {
         timer: Timer,
     },
 
+    // Sound
+    LoadAudioPattern,
+    SetPitch(ValueRegisterIndex),
+
     // Misc
     Nop,
     WriteBCD(ValueRegisterIndex),
     DumpValueRegisters(ValueRegisterIndex),
     LoadValueRegisters(ValueRegisterIndex),
+    DumpFlagRegisters(ValueRegisterIndex),
+    LoadFlagRegisters(ValueRegisterIndex),
+    DumpValueRange {
+        start: ValueRegisterIndex,
+        end: ValueRegisterIndex,
+    },
+    LoadValueRange {
+        start: ValueRegisterIndex,
+        end: ValueRegisterIndex,
+    },
 }
 
 #[derive(PartialEq, Eq, Copy, Clone, Debug)]
@@ -132,6 +157,30 @@ pub enum Timer {
 }
 
 impl Opcode {
+    /// Whether the instruction starting with `value` is followed by a 16-bit operand,
+    /// which must be decoded with `decode_with_operand`
+    pub fn has_operand(value: u16) -> bool {
+        value == 0xF000
+    }
+
+    pub fn decode_with_operand(value: u16, operand: u16) -> ResultChip8<Opcode> {
+        match value {
+            0xF000 => Ok(Opcode::AssignLongAddress(Address::new(operand))),
+            _ => Err(Error::new(format!(
+                "Opcode {:04X} doesn't take an operand",
+                value
+            ))),
+        }
+    }
+
+    /// How many bytes this instruction takes in memory
+    pub fn size(&self) -> u16 {
+        match self {
+            Opcode::AssignLongAddress(_) => 4,
+            _ => 2,
+        }
+    }
+
     pub fn decode_bytes(bytes: &[impl Into<u8> + Clone; 2]) -> ResultChip8<Opcode> {
         let value = u16::from_be_bytes([bytes[0].clone().into(), bytes[1].clone().into()]);
         Opcode::decode(value)
@@ -150,17 +199,60 @@ impl Opcode {
             return Ok(Opcode::Return);
         }
 
+        if value & 0xFFF0 == 0x00C0 {
+            return Ok(Opcode::ScrollDown((value & 0x000F) as u8));
+        }
+
+        if value & 0xFFF0 == 0x00D0 {
+            return Ok(Opcode::ScrollUp((value & 0x000F) as u8));
+        }
+
+        if Opcode::has_operand(value) {
+            return Err(Error::new(format!(
+                "Opcode {:04X} must be followed by a 16-bit operand",
+                value
+            )));
+        }
+
+        if value == 0xF002 {
+            return Ok(Opcode::LoadAudioPattern);
+        }
+
+        match value {
+            0x00FB => return Ok(Opcode::ScrollRight),
+            0x00FC => return Ok(Opcode::ScrollLeft),
+            0x00FD => return Ok(Opcode::Exit),
+            0x00FE => return Ok(Opcode::SetHighResolution(false)),
+            0x00FF => return Ok(Opcode::SetHighResolution(true)),
+            _ => (),
+        }
+
         let first_nibble = ((value & 0xF000) >> 12) as u8;
-        if [0x0, 0x1, 0x2, 0xA, 0xB].contains(&first_nibble) {
-            let addr = Address::new(value & 0x0FFF);
-            return Ok(match first_nibble {
-                0x0 => Opcode::CallNative(addr),
-                0x1 => Opcode::Jump(addr),
-                0x2 => Opcode::Call(addr),
-                0xA => Opcode::AssignAddress(addr),
-                0xB => Opcode::OffsetJump(addr),
-                _ => panic!(),
-            });
+        let addr = Address::new(value & 0x0FFF);
+        match first_nibble {
+            0x0 => return Ok(Opcode::CallNative(addr)),
+            0x1 => return Ok(Opcode::Jump(addr)),
+            0x2 => return Ok(Opcode::Call(addr)),
+            0xA => return Ok(Opcode::AssignAddress(addr)),
+            0xB => return Ok(Opcode::OffsetJump(addr)),
+            _ => (),
+        }
+
+        if first_nibble == 0x5 {
+            let start = ((value & 0x0F00) >> 8) as u8;
+            let end = ((value & 0x00F0) >> 4) as u8;
+            match value & 0x000F {
+                0x2 => return Ok(Opcode::DumpValueRange { start, end }),
+                0x3 => return Ok(Opcode::LoadValueRange { start, end }),
+                _ => (),
+            }
+        }
+
+        if [0x5, 0x9].contains(&first_nibble) && value & 0x000F != 0 {
+            return Err(Error::new(format!(
+                "Last nibble invalid in opcode {:04X}",
+                value
+            )));
         }
 
         if [0x3, 0x4, 0x5, 0x9].contains(&first_nibble) {
@@ -169,7 +261,7 @@ impl Opcode {
                 left: OpcodeParam::Register(reg),
                 right: match first_nibble {
                     3 | 4 => OpcodeParam::Immediate(((value & 0x00FF) as u8).into()),
-                    _ => OpcodeParam::Register(((value & 0x00F0) >> 8) as u8),
+                    _ => OpcodeParam::Register(((value & 0x00F0) >> 4) as u8),
                 },
                 cond: match first_nibble {
                     3 | 5 => Condition::Equal,
@@ -184,10 +276,10 @@ impl Opcode {
             return Ok(Opcode::Assign {
                 left_reg: reg,
                 right: OpcodeParam::Immediate(Word::new(immediate)),
-                op: match first_nibble {
-                    6 => Operation::None,
-                    7 => Operation::Add,
-                    _ => panic!(),
+                op: if first_nibble == 0x6 {
+                    Operation::None
+                } else {
+                    Operation::Add
                 },
             });
         }
@@ -201,17 +293,11 @@ impl Opcode {
             if last_nibble == 0x6 || last_nibble == 0xE {
                 return Ok(Opcode::Shift {
                     reg: reg1,
+                    source: reg2,
                     right: last_nibble == 0x6,
                 });
             }
 
-            if last_nibble > 7 {
-                return Err(Error::new(format!(
-                    "Last nibble invalid in opcode {:04X}",
-                    value
-                )));
-            }
-
             return Ok(Opcode::Assign {
                 left_reg: reg1,
                 right: OpcodeParam::Register(reg2),
@@ -223,7 +309,12 @@ impl Opcode {
                     4 => Operation::Add,
                     5 => Operation::Sub,
                     7 => Operation::ReverseSub,
-                    _ => panic!(),
+                    _ => {
+                        return Err(Error::new(format!(
+                            "Last nibble invalid in opcode {:04X}",
+                            value
+                        )))
+                    }
                 },
             });
         }
@@ -247,19 +338,17 @@ impl Opcode {
             let reg = ((value & 0x0F00) >> 8) as u8;
             let last_byte = (value & 0x00FF) as u8;
 
-            if last_byte != 0x9E && last_byte != 0xA1 {
-                return Err(Error::new(format!(
-                    "Last byte invalid in opcode {:04X}",
-                    value
-                )));
-            }
-
             return Ok(Opcode::CondKeyJump {
                 reg,
                 cond: match last_byte {
                     0x9E => Condition::Equal,
                     0xA1 => Condition::NotEqual,
-                    _ => panic!(),
+                    _ => {
+                        return Err(Error::new(format!(
+                            "Last byte invalid in opcode {:04X}",
+                            value
+                        )))
+                    }
                 },
             });
         }
@@ -269,6 +358,7 @@ impl Opcode {
             let last_byte = (value & 0x00FF) as u8;
 
             return match last_byte {
+                0x01 => Ok(Opcode::SelectPlanes(reg)),
                 0x07 => Ok(Opcode::GetDelayTimer(reg)),
                 0x0A => Ok(Opcode::BlockOnKey(reg)),
                 0x15 => Ok(Opcode::SetTimer {
@@ -281,9 +371,13 @@ impl Opcode {
                 }),
                 0x1E => Ok(Opcode::AddAddress(reg)),
                 0x29 => Ok(Opcode::GetCharacterAddress(reg)),
+                0x30 => Ok(Opcode::GetLargeCharacterAddress(reg)),
                 0x33 => Ok(Opcode::WriteBCD(reg)),
+                0x3A => Ok(Opcode::SetPitch(reg)),
                 0x55 => Ok(Opcode::DumpValueRegisters(reg)),
                 0x65 => Ok(Opcode::LoadValueRegisters(reg)),
+                0x75 => Ok(Opcode::DumpFlagRegisters(reg)),
+                0x85 => Ok(Opcode::LoadFlagRegisters(reg)),
 
                 _ => Err(Error::new(format!(
                     "Last byte invalid in opcode {:04X}",
@@ -292,7 +386,167 @@ impl Opcode {
             };
         }
 
-        return Err(Error::new(format!("Invalid opcode {:04X}", value)));
+        Err(Error::new(format!("Invalid opcode {:04X}", value)))
+    }
+
+    pub fn encode_bytes(&self) -> ResultChip8<Vec<u8>> {
+        let mut bytes = Vec::with_capacity(self.size().into());
+        let (value, operand) = self.encode()?;
+        bytes.extend_from_slice(&value.to_be_bytes());
+        if let Some(operand) = operand {
+            bytes.extend_from_slice(&operand.to_be_bytes());
+        }
+        Ok(bytes)
+    }
+
+    /// Turns the instruction back into the value that decodes to it, along with the operand
+    /// that follows it for instructions that take one
+    pub fn encode(&self) -> ResultChip8<(u16, Option<u16>)> {
+        let field = |value: u16, max: u16| -> ResultChip8<u16> {
+            if value > max {
+                return Err(Error::new(format!(
+                    "{:X} is out of range in opcode {}",
+                    value, self
+                )));
+            }
+            Ok(value)
+        };
+        let x = |reg: ValueRegisterIndex| -> ResultChip8<u16> { Ok(field(reg.into(), 0xF)? << 8) };
+        let y = |reg: ValueRegisterIndex| -> ResultChip8<u16> { Ok(field(reg.into(), 0xF)? << 4) };
+        let n = |value: u8| field(value.into(), 0xF);
+        let nnn = |addr: Address| field(addr.into(), 0xFFF);
+
+        let value = match *self {
+            // Value Registers
+            Opcode::Assign {
+                left_reg,
+                right: OpcodeParam::Immediate(value),
+                op,
+            } => {
+                let prefix = match op {
+                    Operation::None => 0x6000,
+                    Operation::Add => 0x7000,
+                    _ => {
+                        return Err(Error::new(format!(
+                            "Opcode {} can't be used with an immediate value",
+                            self
+                        )))
+                    }
+                };
+                prefix | x(left_reg)? | u16::from(value)
+            }
+            Opcode::Assign {
+                left_reg,
+                right: OpcodeParam::Register(right),
+                op,
+            } => {
+                let suffix = match op {
+                    Operation::None => 0x0,
+                    Operation::Or => 0x1,
+                    Operation::And => 0x2,
+                    Operation::Xor => 0x3,
+                    Operation::Add => 0x4,
+                    Operation::Sub => 0x5,
+                    Operation::ReverseSub => 0x7,
+                };
+                0x8000 | x(left_reg)? | y(right)? | suffix
+            }
+            Opcode::Shift { reg, source, right } => {
+                0x8000 | x(reg)? | y(source)? | if right { 0x6 } else { 0xE }
+            }
+            Opcode::Random { reg, mask } => 0xC000 | x(reg)? | u16::from(mask),
+
+            // Address Register
+            Opcode::AssignAddress(addr) => 0xA000 | nnn(addr)?,
+            Opcode::AssignLongAddress(addr) => return Ok((0xF000, Some(addr.into()))),
+            Opcode::AddAddress(reg) => 0xF01E | x(reg)?,
+            Opcode::GetCharacterAddress(reg) => 0xF029 | x(reg)?,
+            Opcode::GetLargeCharacterAddress(reg) => 0xF030 | x(reg)?,
+
+            // Flow Control
+            Opcode::Return => 0x00EE,
+            Opcode::Exit => 0x00FD,
+            Opcode::Jump(addr) => 0x1000 | nnn(addr)?,
+            Opcode::OffsetJump(addr) => 0xB000 | nnn(addr)?,
+            Opcode::Call(addr) => 0x2000 | nnn(addr)?,
+            // Addresses that would decode as another instruction can't be called natively
+            Opcode::CallNative(addr) => match u16::from(addr) {
+                0x0000 | 0x00E0 | 0x00EE | 0x00C0..=0x00DF | 0x00FB..=0x00FF => {
+                    return Err(Error::new(format!("Opcode {} can't be encoded", self)))
+                }
+                _ => nnn(addr)?,
+            },
+            Opcode::CondJump {
+                left: OpcodeParam::Register(left),
+                right,
+                cond,
+            } => match (right, cond) {
+                (OpcodeParam::Immediate(value), Condition::Equal) => {
+                    0x3000 | x(left)? | u16::from(value)
+                }
+                (OpcodeParam::Immediate(value), Condition::NotEqual) => {
+                    0x4000 | x(left)? | u16::from(value)
+                }
+                (OpcodeParam::Register(right), Condition::Equal) => 0x5000 | x(left)? | y(right)?,
+                (OpcodeParam::Register(right), Condition::NotEqual) => {
+                    0x9000 | x(left)? | y(right)?
+                }
+            },
+            Opcode::CondJump {
+                left: OpcodeParam::Immediate(_),
+                ..
+            } => {
+                return Err(Error::new(format!(
+                    "Opcode {} must compare a register",
+                    self
+                )))
+            }
+
+            // Graphics
+            Opcode::ClearScreen => 0x00E0,
+            Opcode::Draw {
+                x: left,
+                y: right,
+                height,
+            } => 0xD000 | x(left)? | y(right)? | n(height)?,
+            Opcode::ScrollDown(lines) => 0x00C0 | n(lines)?,
+            Opcode::ScrollUp(lines) => 0x00D0 | n(lines)?,
+            Opcode::ScrollRight => 0x00FB,
+            Opcode::ScrollLeft => 0x00FC,
+            Opcode::SetHighResolution(false) => 0x00FE,
+            Opcode::SetHighResolution(true) => 0x00FF,
+            Opcode::SelectPlanes(planes) => 0xF001 | x(planes)?,
+
+            // IO
+            Opcode::BlockOnKey(reg) => 0xF00A | x(reg)?,
+            Opcode::CondKeyJump { reg, cond } => match cond {
+                Condition::Equal => 0xE09E | x(reg)?,
+                Condition::NotEqual => 0xE0A1 | x(reg)?,
+            },
+
+            // Timers
+            Opcode::GetDelayTimer(reg) => 0xF007 | x(reg)?,
+            Opcode::SetTimer { reg, timer } => match timer {
+                Timer::Delay => 0xF015 | x(reg)?,
+                Timer::Sound => 0xF018 | x(reg)?,
+            },
+
+            // Sound
+            Opcode::LoadAudioPattern => 0xF002,
+            Opcode::SetPitch(reg) => 0xF03A | x(reg)?,
+
+            // Misc
+            Opcode::Nop => 0x0000,
+            Opcode::WriteBCD(reg) => 0xF033 | x(reg)?,
+            Opcode::DumpValueRegisters(reg) => 0xF055 | x(reg)?,
+            Opcode::LoadValueRegisters(reg) => 0xF065 | x(reg)?,
+            Opcode::DumpFlagRegisters(reg) => 0xF075 | x(reg)?,
+            Opcode::LoadFlagRegisters(reg) => 0xF085 | x(reg)?,
+            Opcode::DumpValueRange { start, end } => 0x5002 | x(start)? | y(end)?,
+            Opcode::LoadValueRange { start, end } => 0x5003 | x(start)? | y(end)?,
+        };
+
+        Ok((value, None))
     }
 }
 
@@ -319,25 +573,37 @@ impl Display for Opcode {
                     right
                 ),
             },
-            Opcode::Shift { reg, right } => write!(
+            Opcode::Shift { reg, source, right } if reg == source => write!(
                 fmt,
                 "{} {}= 1",
                 OpcodeParam::Register(*reg),
                 if *right { ">>" } else { "<<" }
             ),
+            Opcode::Shift { reg, source, right } => write!(
+                fmt,
+                "{} = {} {} 1",
+                OpcodeParam::Register(*reg),
+                OpcodeParam::Register(*source),
+                if *right { ">>" } else { "<<" }
+            ),
             Opcode::Random { reg, mask } => {
                 write!(fmt, "{} = rand() & {}", OpcodeParam::Register(*reg), mask)
             }
 
             // Address Register
             Opcode::AssignAddress(x) => write!(fmt, "I = {}", x),
+            Opcode::AssignLongAddress(x) => write!(fmt, "I = long {}", x),
             Opcode::AddAddress(x) => write!(fmt, "I += {}", OpcodeParam::Register(*x)),
             Opcode::GetCharacterAddress(x) => {
                 write!(fmt, "I = char[{}]", OpcodeParam::Register(*x))
             }
+            Opcode::GetLargeCharacterAddress(x) => {
+                write!(fmt, "I = large_char[{}]", OpcodeParam::Register(*x))
+            }
 
             // Flow Control
             Opcode::Return => write!(fmt, "return"),
+            Opcode::Exit => write!(fmt, "exit()"),
             Opcode::Jump(x) => write!(fmt, "goto {}", x),
             Opcode::OffsetJump(x) => write!(fmt, "goto {} + {}", x, OpcodeParam::Register(0)),
             Opcode::Call(x) => write!(fmt, "{}()", x),
@@ -348,13 +614,26 @@ impl Display for Opcode {
 
             // Graphics
             Opcode::ClearScreen => write!(fmt, "clear()"),
+            Opcode::Draw { x, y, height: 0 } => write!(
+                fmt,
+                "draw *I at ({}; {}) size 16x16",
+                OpcodeParam::Register(*x),
+                OpcodeParam::Register(*y)
+            ),
             Opcode::Draw { x, y, height } => write!(
                 fmt,
                 "draw *I at ({}; {}) size 8x{}",
                 OpcodeParam::Register(*x),
                 OpcodeParam::Register(*y),
-                height + 1
+                height
             ),
+            Opcode::ScrollDown(x) => write!(fmt, "scroll_down({})", x),
+            Opcode::ScrollUp(x) => write!(fmt, "scroll_up({})", x),
+            Opcode::ScrollRight => write!(fmt, "scroll_right(4)"),
+            Opcode::ScrollLeft => write!(fmt, "scroll_left(4)"),
+            Opcode::SetHighResolution(true) => write!(fmt, "high_res()"),
+            Opcode::SetHighResolution(false) => write!(fmt, "low_res()"),
+            Opcode::SelectPlanes(x) => write!(fmt, "plane({})", x),
 
             // IO
             Opcode::BlockOnKey(x) => write!(fmt, "{} = wait_for_key()", OpcodeParam::Register(*x)),
@@ -373,6 +652,10 @@ impl Display for Opcode {
                 write!(fmt, "{} = {}", timer, OpcodeParam::Register(*reg))
             }
 
+            // Sound
+            Opcode::LoadAudioPattern => write!(fmt, "audio = *I"),
+            Opcode::SetPitch(x) => write!(fmt, "pitch = {}", OpcodeParam::Register(*x)),
+
             // Misc
             Opcode::Nop => write!(fmt, "nop"),
             Opcode::WriteBCD(x) => write!(fmt, "*I = BCD({})", OpcodeParam::Register(*x)),
@@ -388,7 +671,297 @@ impl Display for Opcode {
                 OpcodeParam::Register(0),
                 OpcodeParam::Register(*x)
             ),
+            Opcode::DumpFlagRegisters(x) => write!(
+                fmt,
+                "flags = [{}..={}]",
+                OpcodeParam::Register(0),
+                OpcodeParam::Register(*x)
+            ),
+            Opcode::LoadFlagRegisters(x) => write!(
+                fmt,
+                "[{}..={}] = flags",
+                OpcodeParam::Register(0),
+                OpcodeParam::Register(*x)
+            ),
+            // Unlike FX55 and FX65, these leave I alone and can go backwards
+            Opcode::DumpValueRange { start, end } => write!(
+                fmt,
+                "*I = range({}, {})",
+                OpcodeParam::Register(*start),
+                OpcodeParam::Register(*end)
+            ),
+            Opcode::LoadValueRange { start, end } => write!(
+                fmt,
+                "range({}, {}) = *I",
+                OpcodeParam::Register(*start),
+                OpcodeParam::Register(*end)
+            ),
+        }
+    }
+}
+
+/// Builds an opcode from the fields of its text, if they make a valid one
+type Builder = fn(&[u16]) -> Option<Opcode>;
+
+/// The forms `Opcode` is displayed in, with `{r}` for a register, `{b}` for a byte, `{a}` for
+/// an address, `{l}` for a 16-bit address and `{n}` for a decimal number up to 15
+const SYNTAX: &[(&str, Builder)] = &[
+    // Value Registers
+    ("{r} = {r}", |x| {
+        Some(assign(x[0], reg(x[1]), Operation::None))
+    }),
+    ("{r} = {b}", |x| {
+        Some(assign(x[0], imm(x[1]), Operation::None))
+    }),
+    ("{r} += {r}", |x| {
+        Some(assign(x[0], reg(x[1]), Operation::Add))
+    }),
+    ("{r} += {b}", |x| {
+        Some(assign(x[0], imm(x[1]), Operation::Add))
+    }),
+    ("{r} -= {r}", |x| {
+        Some(assign(x[0], reg(x[1]), Operation::Sub))
+    }),
+    ("{r} |= {r}", |x| {
+        Some(assign(x[0], reg(x[1]), Operation::Or))
+    }),
+    ("{r} &= {r}", |x| {
+        Some(assign(x[0], reg(x[1]), Operation::And))
+    }),
+    ("{r} ^= {r}", |x| {
+        Some(assign(x[0], reg(x[1]), Operation::Xor))
+    }),
+    ("{r} = {r} - {r}", |x| {
+        if x[0] != x[2] {
+            return None;
+        }
+        Some(assign(x[0], reg(x[1]), Operation::ReverseSub))
+    }),
+    ("{r} >>= 1", |x| Some(shift(x[0], x[0], true))),
+    ("{r} <<= 1", |x| Some(shift(x[0], x[0], false))),
+    ("{r} = {r} >> 1", |x| Some(shift(x[0], x[1], true))),
+    ("{r} = {r} << 1", |x| Some(shift(x[0], x[1], false))),
+    ("{r} = rand() & {b}", |x| {
+        Some(Opcode::Random {
+            reg: x[0] as u8,
+            mask: Word::new(x[1] as u8),
+        })
+    }),
+    // Address Register
+    ("I = {a}", |x| {
+        Some(Opcode::AssignAddress(Address::new(x[0])))
+    }),
+    ("I = long {l}", |x| {
+        Some(Opcode::AssignLongAddress(Address::new(x[0])))
+    }),
+    ("I += {r}", |x| Some(Opcode::AddAddress(x[0] as u8))),
+    ("I = char[{r}]", |x| {
+        Some(Opcode::GetCharacterAddress(x[0] as u8))
+    }),
+    ("I = large_char[{r}]", |x| {
+        Some(Opcode::GetLargeCharacterAddress(x[0] as u8))
+    }),
+    // Flow Control
+    ("return", |_| Some(Opcode::Return)),
+    ("exit()", |_| Some(Opcode::Exit)),
+    ("goto {a}", |x| Some(Opcode::Jump(Address::new(x[0])))),
+    ("goto {a} + V0", |x| {
+        Some(Opcode::OffsetJump(Address::new(x[0])))
+    }),
+    ("{a}()", |x| Some(Opcode::Call(Address::new(x[0])))),
+    ("Native {a}()", |x| {
+        Some(Opcode::CallNative(Address::new(x[0])))
+    }),
+    ("if {r} == key { skip }", |x| {
+        Some(Opcode::CondKeyJump {
+            reg: x[0] as u8,
+            cond: Condition::Equal,
+        })
+    }),
+    ("if {r} != key { skip }", |x| {
+        Some(Opcode::CondKeyJump {
+            reg: x[0] as u8,
+            cond: Condition::NotEqual,
+        })
+    }),
+    ("if {r} == {r} { skip }", |x| {
+        Some(compare(x[0], reg(x[1]), Condition::Equal))
+    }),
+    ("if {r} == {b} { skip }", |x| {
+        Some(compare(x[0], imm(x[1]), Condition::Equal))
+    }),
+    ("if {r} != {r} { skip }", |x| {
+        Some(compare(x[0], reg(x[1]), Condition::NotEqual))
+    }),
+    ("if {r} != {b} { skip }", |x| {
+        Some(compare(x[0], imm(x[1]), Condition::NotEqual))
+    }),
+    // Graphics
+    ("clear()", |_| Some(Opcode::ClearScreen)),
+    ("draw *I at ({r}; {r}) size 16x16", |x| {
+        Some(draw(x[0], x[1], 0))
+    }),
+    ("draw *I at ({r}; {r}) size 8x{n}", |x| match x[2] {
+        0 => None,
+        height => Some(draw(x[0], x[1], height)),
+    }),
+    ("scroll_down({n})", |x| Some(Opcode::ScrollDown(x[0] as u8))),
+    ("scroll_up({n})", |x| Some(Opcode::ScrollUp(x[0] as u8))),
+    ("scroll_right(4)", |_| Some(Opcode::ScrollRight)),
+    ("scroll_left(4)", |_| Some(Opcode::ScrollLeft)),
+    ("high_res()", |_| Some(Opcode::SetHighResolution(true))),
+    ("low_res()", |_| Some(Opcode::SetHighResolution(false))),
+    ("plane({n})", |x| Some(Opcode::SelectPlanes(x[0] as u8))),
+    // IO
+    ("{r} = wait_for_key()", |x| {
+        Some(Opcode::BlockOnKey(x[0] as u8))
+    }),
+    // Timers
+    ("{r} = delay_timer", |x| {
+        Some(Opcode::GetDelayTimer(x[0] as u8))
+    }),
+    ("delay_timer = {r}", |x| {
+        Some(Opcode::SetTimer {
+            reg: x[0] as u8,
+            timer: Timer::Delay,
+        })
+    }),
+    ("sound_timer = {r}", |x| {
+        Some(Opcode::SetTimer {
+            reg: x[0] as u8,
+            timer: Timer::Sound,
+        })
+    }),
+    // Sound
+    ("audio = *I", |_| Some(Opcode::LoadAudioPattern)),
+    ("pitch = {r}", |x| Some(Opcode::SetPitch(x[0] as u8))),
+    // Misc
+    ("nop", |_| Some(Opcode::Nop)),
+    ("*I = BCD({r})", |x| Some(Opcode::WriteBCD(x[0] as u8))),
+    ("*I = [V0..={r}]", |x| {
+        Some(Opcode::DumpValueRegisters(x[0] as u8))
+    }),
+    ("[V0..={r}] = *I", |x| {
+        Some(Opcode::LoadValueRegisters(x[0] as u8))
+    }),
+    ("*I = range({r}, {r})", |x| {
+        Some(Opcode::DumpValueRange {
+            start: x[0] as u8,
+            end: x[1] as u8,
+        })
+    }),
+    ("range({r}, {r}) = *I", |x| {
+        Some(Opcode::LoadValueRange {
+            start: x[0] as u8,
+            end: x[1] as u8,
+        })
+    }),
+    ("flags = [V0..={r}]", |x| {
+        Some(Opcode::DumpFlagRegisters(x[0] as u8))
+    }),
+    ("[V0..={r}] = flags", |x| {
+        Some(Opcode::LoadFlagRegisters(x[0] as u8))
+    }),
+];
+
+fn reg(x: u16) -> OpcodeParam {
+    OpcodeParam::Register(x as u8)
+}
+
+fn imm(x: u16) -> OpcodeParam {
+    OpcodeParam::Immediate(Word::new(x as u8))
+}
+
+fn assign(left_reg: u16, right: OpcodeParam, op: Operation) -> Opcode {
+    Opcode::Assign {
+        left_reg: left_reg as u8,
+        right,
+        op,
+    }
+}
+
+fn shift(reg: u16, source: u16, right: bool) -> Opcode {
+    Opcode::Shift {
+        reg: reg as u8,
+        source: source as u8,
+        right,
+    }
+}
+
+fn compare(left: u16, right: OpcodeParam, cond: Condition) -> Opcode {
+    Opcode::CondJump {
+        left: OpcodeParam::Register(left as u8),
+        right,
+        cond,
+    }
+}
+
+fn draw(x: u16, y: u16, height: u16) -> Opcode {
+    Opcode::Draw {
+        x: x as u8,
+        y: y as u8,
+        height: height as u8,
+    }
+}
+
+/// Matches `text` against one of the `SYNTAX` patterns, returning the values of its fields
+fn scan(text: &str, pattern: &str) -> Option<Vec<u16>> {
+    let mut values = Vec::new();
+    let mut text = text;
+    let mut pattern = pattern;
+
+    while !pattern.is_empty() {
+        if let Some(rest) = pattern.strip_prefix(' ') {
+            text = text.trim_start();
+            pattern = rest;
+            continue;
         }
+
+        if pattern.starts_with('{') && pattern.as_bytes().get(2) == Some(&b'}') {
+            let end = text
+                .find(|x: char| !x.is_ascii_alphanumeric())
+                .unwrap_or(text.len());
+            let (field, rest) = text.split_at(end);
+            let value = match pattern.as_bytes()[1] {
+                b'r' => match field.strip_prefix(|x| x == 'V' || x == 'v') {
+                    Some(x) if x.len() == 1 => u16::from_str_radix(x, 16).ok(),
+                    _ => None,
+                },
+                b'b' => u16::from_str_radix(field, 16).ok().filter(|x| *x <= 0xFF),
+                b'a' => u16::from_str_radix(field, 16).ok().filter(|x| *x <= 0xFFF),
+                b'l' => u16::from_str_radix(field, 16).ok(),
+                _ => field.parse::<u16>().ok().filter(|x| *x <= 0xF),
+            }?;
+
+            values.push(value);
+            text = rest;
+            pattern = &pattern[3..];
+            continue;
+        }
+
+        let len = pattern.find([' ', '{']).unwrap_or(pattern.len()).max(1);
+        text = text.strip_prefix(&pattern[..len])?;
+        pattern = &pattern[len..];
+    }
+
+    if text.is_empty() {
+        Some(values)
+    } else {
+        None
+    }
+}
+
+/// Parses an instruction in the form it's displayed in, like `V3 += 05`
+impl FromStr for Opcode {
+    type Err = Error;
+
+    fn from_str(text: &str) -> ResultChip8<Opcode> {
+        let text = text.trim();
+        SYNTAX
+            .iter()
+            .filter_map(|(pattern, build)| build(&scan(text, pattern)?))
+            .next()
+            .ok_or_else(|| Error::new(format!("Invalid instruction {}", text)))
     }
 }
 
@@ -0,0 +1,199 @@
+use crate::core::{Address, VoidResultChip8};
+use crate::opcodes::Opcode;
+use crate::trace::{TraceEntry, TraceSink};
+use std::collections::HashMap;
+use std::io::Write;
+
+/// How much of the run was spent inside a subroutine
+#[derive(Eq, PartialEq, Clone, Copy, Debug)]
+pub struct SubroutineProfile {
+    pub address: Address,
+    pub calls: u64,
+    /// Instructions that ran in the subroutine or anything it called
+    pub total: u64,
+    /// Instructions that ran in the subroutine itself
+    pub own: u64,
+}
+
+/// Counts how many times every instruction runs, and in which subroutines.
+///
+/// Time is measured in instructions, since that's what limits a program every frame.
+pub struct Profiler {
+    executions: HashMap<Address, u64>,
+    opcodes: HashMap<Address, Opcode>,
+    calls: HashMap<Address, u64>,
+    /// Instructions that ran with each call stack, outermost subroutine first
+    stacks: HashMap<Vec<Address>, u64>,
+    call_stack: Vec<Address>,
+    pub instructions: u64,
+    pub frames: u64,
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Profiler::new()
+    }
+}
+
+impl Profiler {
+    pub fn new() -> Profiler {
+        Profiler {
+            executions: HashMap::new(),
+            opcodes: HashMap::new(),
+            calls: HashMap::new(),
+            stacks: HashMap::new(),
+            call_stack: Vec::new(),
+            instructions: 0,
+            frames: 0,
+        }
+    }
+
+    /// Every address that ran and how many times, most executed first
+    pub fn hotspots(&self) -> Vec<(Address, u64)> {
+        let mut result: Vec<(Address, u64)> =
+            self.executions.iter().map(|(x, y)| (*x, *y)).collect();
+        result.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        result
+    }
+
+    /// Every subroutine that was called, the most expensive first
+    pub fn subroutines(&self) -> Vec<SubroutineProfile> {
+        let mut result: HashMap<Address, SubroutineProfile> = HashMap::new();
+
+        for (stack, count) in self.stacks.iter() {
+            let mut seen = Vec::new();
+            for addr in stack.iter() {
+                let profile = result.entry(*addr).or_insert(SubroutineProfile {
+                    address: *addr,
+                    calls: self.calls.get(addr).copied().unwrap_or(0),
+                    total: 0,
+                    own: 0,
+                });
+
+                // Recursive calls would otherwise count the same instructions more than once
+                if !seen.contains(addr) {
+                    profile.total += count;
+                    seen.push(*addr);
+                }
+            }
+
+            if let Some(addr) = stack.last() {
+                result.get_mut(addr).unwrap().own += count;
+            }
+        }
+
+        let mut result: Vec<SubroutineProfile> = result.into_values().collect();
+        result.sort_by(|a, b| b.total.cmp(&a.total).then(a.address.cmp(&b.address)));
+        result
+    }
+
+    /// Writes the hottest `count` instructions and all subroutines in a human-readable table
+    pub fn write_report(&self, out: &mut impl Write, count: usize) -> VoidResultChip8 {
+        let frames = self.frames.max(1) as f64;
+        let percent = |x: u64| 100.0 * x as f64 / self.instructions.max(1) as f64;
+
+        writeln!(
+            out,
+            "{} instructions ran in {} frames, {:.1} per frame",
+            self.instructions,
+            self.frames,
+            self.instructions as f64 / frames
+        )?;
+
+        writeln!(out)?;
+        writeln!(out, "Hotspots:")?;
+        writeln!(
+            out,
+            "{:>12} {:>7} {:>10}  Instruction",
+            "Count", "%", "Per frame"
+        )?;
+        for (addr, executions) in self.hotspots().into_iter().take(count) {
+            let opcode = match self.opcodes.get(&addr) {
+                Some(x) => x.to_string(),
+                None => "???".to_owned(),
+            };
+            writeln!(
+                out,
+                "{:>12} {:>6.2}% {:>10.1}  {}: {}",
+                executions,
+                percent(executions),
+                executions as f64 / frames,
+                addr,
+                opcode
+            )?;
+        }
+
+        writeln!(out)?;
+        writeln!(out, "Subroutines:")?;
+        writeln!(
+            out,
+            "{:>12} {:>12} {:>7} {:>12} {:>7}  Address",
+            "Calls", "Total", "%", "Own", "%"
+        )?;
+        for profile in self.subroutines() {
+            writeln!(
+                out,
+                "{:>12} {:>12} {:>6.2}% {:>12} {:>6.2}%  {}",
+                profile.calls,
+                profile.total,
+                percent(profile.total),
+                profile.own,
+                percent(profile.own),
+                profile.address
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes the call stacks in the folded format flamegraph tools take, one line per stack
+    /// with its frames separated by semicolons and followed by its instruction count
+    pub fn write_folded(&self, out: &mut impl Write) -> VoidResultChip8 {
+        let mut stacks: Vec<(&Vec<Address>, &u64)> = self.stacks.iter().collect();
+        stacks.sort();
+
+        for (stack, count) in stacks {
+            write!(out, "main")?;
+            for addr in stack.iter() {
+                write!(out, ";sub_{}", addr)?;
+            }
+            writeln!(out, " {}", count)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl TraceSink for Profiler {
+    fn trace(&mut self, entry: &TraceEntry) -> VoidResultChip8 {
+        self.instructions += 1;
+        *self.executions.entry(entry.program_counter).or_insert(0) += 1;
+        self.opcodes.insert(entry.program_counter, entry.opcode);
+        match self.stacks.get_mut(&self.call_stack) {
+            Some(x) => *x += 1,
+            None => {
+                self.stacks.insert(self.call_stack.clone(), 1);
+            }
+        };
+
+        match entry.opcode {
+            Opcode::Call(addr) => {
+                self.call_stack.push(addr);
+                *self.calls.entry(addr).or_insert(0) += 1;
+            }
+            Opcode::Return => {
+                self.call_stack.pop();
+            }
+            _ => {}
+        };
+
+        // Stay in sync with the real stack if it's changed some other way, like a state load
+        self.call_stack.truncate(entry.stack_depth);
+        Ok(())
+    }
+
+    fn end_frame(&mut self) -> VoidResultChip8 {
+        self.frames += 1;
+        Ok(())
+    }
+}
@@ -0,0 +1,95 @@
+use crate::opcodes::Opcode;
+
+/// How far FX55 and FX65 move I after accessing the registers
+#[derive(Eq, PartialEq, Clone, Copy, Debug, Default)]
+pub enum AddressIncrement {
+    /// I is left alone
+    #[default]
+    None,
+    /// I ends up pointing to the last register accessed
+    X,
+    /// I ends up pointing right after the last register accessed
+    XPlusOne,
+}
+
+/// Toggles for the instructions that behave differently between CHIP-8 implementations
+#[derive(Eq, PartialEq, Clone, Copy, Debug, Default)]
+pub struct Quirks {
+    /// 8XY6 and 8XYE shift VY and store the result in VX, instead of shifting VX in place
+    pub shift_uses_vy: bool,
+    /// How FX55 and FX65 change I
+    pub load_store_increment: AddressIncrement,
+    /// BNNN jumps to NNN + VX, where X is the highest nibble of NNN, instead of NNN + V0
+    pub jump_uses_vx: bool,
+    /// Sprites are cut off at the edges of the screen instead of wrapping around to the other side
+    pub clip_sprites: bool,
+    /// 8XY1, 8XY2 and 8XY3 set VF to 0
+    pub logic_resets_flag: bool,
+    /// The whole 64KB address space of XO-CHIP can be used, instead of only 4KB
+    pub extended_memory: bool,
+}
+
+impl Quirks {
+    pub const COSMAC_VIP: Quirks = Quirks {
+        shift_uses_vy: true,
+        load_store_increment: AddressIncrement::XPlusOne,
+        jump_uses_vx: false,
+        clip_sprites: true,
+        logic_resets_flag: true,
+        extended_memory: false,
+    };
+
+    pub const CHIP_48: Quirks = Quirks {
+        shift_uses_vy: false,
+        load_store_increment: AddressIncrement::X,
+        jump_uses_vx: true,
+        clip_sprites: true,
+        logic_resets_flag: false,
+        extended_memory: false,
+    };
+
+    pub const SUPER_CHIP: Quirks = Quirks {
+        shift_uses_vy: false,
+        load_store_increment: AddressIncrement::None,
+        jump_uses_vx: true,
+        clip_sprites: true,
+        logic_resets_flag: false,
+        extended_memory: false,
+    };
+
+    pub const XO_CHIP: Quirks = Quirks {
+        shift_uses_vy: true,
+        load_store_increment: AddressIncrement::XPlusOne,
+        jump_uses_vx: false,
+        clip_sprites: false,
+        logic_resets_flag: false,
+        extended_memory: true,
+    };
+
+    /// Rewrites `opcode` into the instruction it behaves as with these quirks, so that it's
+    /// shown the way it runs. Without `shift_uses_vy`, 8XY6 and 8XYE shift VX in place.
+    pub fn apply(&self, opcode: Opcode) -> Opcode {
+        match opcode {
+            Opcode::Shift { reg, right, .. } if !self.shift_uses_vy => Opcode::Shift {
+                reg,
+                source: reg,
+                right,
+            },
+            x => x,
+        }
+    }
+
+    /// Names accepted by `from_name`, in the same order as the presets they refer to
+    pub const PRESET_NAMES: &'static [&'static str] = &["vip", "chip48", "schip", "xochip"];
+
+    /// Finds a preset by its name, as listed in `PRESET_NAMES`
+    pub fn from_name(name: &str) -> Option<Quirks> {
+        match name {
+            "vip" => Some(Quirks::COSMAC_VIP),
+            "chip48" => Some(Quirks::CHIP_48),
+            "schip" => Some(Quirks::SUPER_CHIP),
+            "xochip" => Some(Quirks::XO_CHIP),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,69 @@
+use crate::core::Word;
+use rand::{random, RngCore, SeedableRng};
+use rand_chacha::ChaCha20Rng;
+
+/// Where the CPU gets the random numbers for `Opcode::Random` from
+pub trait RandomSource {
+    fn next_word(&mut self) -> Word;
+
+    /// Where in its sequence the source is, if it's reproducible
+    fn state(&self) -> Option<RandomState> {
+        None
+    }
+}
+
+/// Enough to continue the sequence of a `SeededRandom` exactly where it was
+#[derive(Eq, PartialEq, Clone, Copy, Debug)]
+pub struct RandomState {
+    pub seed: u64,
+    /// How many 32-bit words were generated since seeding
+    pub position: u128,
+}
+
+/// Non-reproducible random numbers from the thread-local generator
+pub struct ThreadRandom;
+
+impl RandomSource for ThreadRandom {
+    fn next_word(&mut self) -> Word {
+        Word::new(random::<u8>())
+    }
+}
+
+/// Reproducible random numbers: the same seed always generates the same sequence
+pub struct SeededRandom {
+    seed: u64,
+    rng: ChaCha20Rng,
+}
+
+impl SeededRandom {
+    pub fn new(seed: u64) -> SeededRandom {
+        SeededRandom {
+            seed,
+            rng: ChaCha20Rng::seed_from_u64(seed),
+        }
+    }
+
+    /// Continues the sequence from where `state` was taken
+    pub fn from_state(state: RandomState) -> SeededRandom {
+        let mut random = SeededRandom::new(state.seed);
+        random.rng.set_word_pos(state.position);
+        random
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+}
+
+impl RandomSource for SeededRandom {
+    fn next_word(&mut self) -> Word {
+        Word::new(self.rng.next_u32() as u8)
+    }
+
+    fn state(&self) -> Option<RandomState> {
+        Some(RandomState {
+            seed: self.seed,
+            position: self.rng.get_word_pos(),
+        })
+    }
+}
@@ -6,6 +6,14 @@ pub struct Registers {
     pub values: [Word; 0x10],
     pub program_counter: Address,
     pub address: Address,
+    /// SUPER-CHIP's persistent user flags, stored in the HP-48's RPL registers on real hardware
+    pub rpl_flags: [Word; 0x10],
+}
+
+impl Default for Registers {
+    fn default() -> Self {
+        Registers::new()
+    }
 }
 
 impl Registers {
@@ -14,6 +22,7 @@ impl Registers {
             values: [Word::ZERO; 0x10],
             program_counter: Address::new(0x0200u16),
             address: Address::ZERO,
+            rpl_flags: [Word::ZERO; 0x10],
         }
     }
 
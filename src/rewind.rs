@@ -0,0 +1,125 @@
+use crate::core::Word;
+use crate::state::SaveState;
+use std::collections::VecDeque;
+
+/// A bounded history of machine states, oldest states are dropped first.
+///
+/// Only the most recent state is kept in full. Every older state is stored as the
+/// list of changes needed to go back to it from the state right after it.
+pub struct RewindBuffer {
+    capacity: usize,
+    latest: Option<SaveState>,
+    deltas: VecDeque<Delta>,
+}
+
+/// Turns a state into the state that came right before it
+struct Delta {
+    // Only the small fields are used, banks and VRAM are stored as changes below
+    state: SaveState,
+    bank_changes: Vec<Vec<(usize, Word)>>,
+    vram_changes: Vec<(usize, u8)>,
+}
+
+impl RewindBuffer {
+    pub fn new(capacity: usize) -> RewindBuffer {
+        RewindBuffer {
+            capacity: capacity.max(1),
+            latest: None,
+            deltas: VecDeque::new(),
+        }
+    }
+
+    /// Number of states that can currently be rewound to
+    pub fn len(&self) -> usize {
+        match self.latest {
+            None => 0,
+            Some(_) => self.deltas.len() + 1,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.latest.is_none()
+    }
+
+    pub fn clear(&mut self) {
+        self.latest = None;
+        self.deltas.clear();
+    }
+
+    pub fn push(&mut self, state: SaveState) {
+        if let Some(previous) = self.latest.take() {
+            match Delta::between(&state, &previous) {
+                Some(delta) => self.deltas.push_front(delta),
+                // The memory layout changed, so older states can't be reached from this one anymore
+                None => self.deltas.clear(),
+            }
+        }
+
+        self.latest = Some(state);
+        self.deltas.truncate(self.capacity - 1);
+    }
+
+    /// Removes and returns the most recent state
+    pub fn pop(&mut self) -> Option<SaveState> {
+        let latest = self.latest.take()?;
+        self.latest = self.deltas.pop_front().map(|x| x.apply(&latest));
+        Some(latest)
+    }
+}
+
+impl Delta {
+    fn between(newer: &SaveState, older: &SaveState) -> Option<Delta> {
+        if newer.vram.len() != older.vram.len() || newer.banks.len() != older.banks.len() {
+            return None;
+        }
+
+        let mut bank_changes = Vec::with_capacity(older.banks.len());
+        for (new_bank, old_bank) in newer.banks.iter().zip(older.banks.iter()) {
+            if new_bank.name != old_bank.name || new_bank.range != old_bank.range {
+                return None;
+            }
+            bank_changes.push(changes(&new_bank.data, &old_bank.data));
+        }
+
+        Some(Delta {
+            state: SaveState {
+                stack: older.stack.clone(),
+                banks: Vec::new(),
+                vram: Vec::new(),
+                ..*older
+            },
+            bank_changes,
+            vram_changes: changes(&newer.vram, &older.vram),
+        })
+    }
+
+    fn apply(self, newer: &SaveState) -> SaveState {
+        let mut banks = newer.banks.clone();
+        for (bank, changes) in banks.iter_mut().zip(self.bank_changes) {
+            for (i, value) in changes {
+                bank.data[i] = value;
+            }
+        }
+
+        let mut vram = newer.vram.clone();
+        for (i, value) in self.vram_changes {
+            vram[i] = value;
+        }
+
+        SaveState {
+            banks,
+            vram,
+            ..self.state
+        }
+    }
+}
+
+/// Returns the index and old value of every element that differs between `new` and `old`
+fn changes<T: PartialEq + Copy>(new: &[T], old: &[T]) -> Vec<(usize, T)> {
+    new.iter()
+        .zip(old.iter())
+        .enumerate()
+        .filter(|(_, (new, old))| new != old)
+        .map(|(i, (_, old))| (i, *old))
+        .collect()
+}
@@ -0,0 +1,114 @@
+use crate::core::VoidResultChip8;
+use crate::cpu::CPU;
+use crate::timers::TIMER_PERIOD;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How fast frames are run compared to real time
+#[derive(Eq, PartialEq, Clone, Copy, Debug)]
+pub enum Speed {
+    Normal,
+    FastForward,
+    SlowMotion,
+}
+
+/// Where the scheduler gets the time from, so that pacing can be tested without waiting
+pub trait Clock {
+    fn now(&self) -> Instant;
+    fn sleep(&mut self, duration: Duration);
+}
+
+/// The real time
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep(&mut self, duration: Duration) {
+        thread::sleep(duration);
+    }
+}
+
+/// Runs the CPU one 60Hz frame at a time: a fixed number of instructions, then the timers
+/// and the display.
+///
+/// The speed only changes how long frames take in real time, so it never changes what the
+/// emulated program sees.
+pub struct FrameScheduler {
+    pub instructions_per_frame: u32,
+    /// Multiplier applied to the frame rate while fast-forwarding
+    pub fast_forward: f64,
+    /// Multiplier applied to the frame rate while in slow motion
+    pub slow_motion: f64,
+    pub speed: Speed,
+    pub clock: Box<dyn Clock>,
+    stopped: bool,
+}
+
+impl FrameScheduler {
+    pub const DEFAULT_INSTRUCTIONS_PER_FRAME: u32 = 16;
+    pub const DEFAULT_FAST_FORWARD: f64 = 4.0;
+    pub const DEFAULT_SLOW_MOTION: f64 = 0.25;
+
+    pub fn new(instructions_per_frame: u32) -> FrameScheduler {
+        FrameScheduler {
+            instructions_per_frame: instructions_per_frame.max(1),
+            fast_forward: FrameScheduler::DEFAULT_FAST_FORWARD,
+            slow_motion: FrameScheduler::DEFAULT_SLOW_MOTION,
+            speed: Speed::Normal,
+            clock: Box::new(SystemClock),
+            stopped: false,
+        }
+    }
+
+    pub fn multiplier(&self) -> f64 {
+        match self.speed {
+            Speed::Normal => 1.0,
+            Speed::FastForward => self.fast_forward,
+            Speed::SlowMotion => self.slow_motion,
+        }
+    }
+
+    /// Switches to `speed`, or back to normal speed if it's already active
+    pub fn toggle(&mut self, speed: Speed) {
+        self.speed = if self.speed == speed {
+            Speed::Normal
+        } else {
+            speed
+        };
+    }
+
+    /// Makes `run` return after the current frame
+    pub fn stop(&mut self) {
+        self.stopped = true;
+    }
+
+    /// Runs the CPU until the program exits or `stop` is called, calling `on_frame` after
+    /// every frame
+    pub fn run(
+        &mut self,
+        cpu: &mut CPU,
+        mut on_frame: impl FnMut(&mut CPU, &mut FrameScheduler) -> VoidResultChip8,
+    ) -> VoidResultChip8 {
+        let mut next_frame = self.clock.now();
+
+        self.stopped = false;
+        while !cpu.halted && !self.stopped {
+            cpu.frame(self.instructions_per_frame)?;
+            on_frame(cpu, self)?;
+
+            next_frame += TIMER_PERIOD.div_f64(self.multiplier());
+            let now = self.clock.now();
+            if next_frame > now {
+                self.clock.sleep(next_frame - now);
+            } else {
+                // Running behind, don't try to catch up by rushing the next frames
+                next_frame = now;
+            }
+        }
+
+        Ok(())
+    }
+}
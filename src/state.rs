@@ -0,0 +1,267 @@
+use crate::audio::AudioPattern;
+use crate::binary::{read_bytes, read_u16, read_u64, read_u8, write_bytes, write_u16, write_u64};
+use crate::core::{Address, Error, ResultChip8, VoidResultChip8, Word};
+use crate::cpu::CPU;
+use crate::display::VideoMemory;
+use crate::memory::{BankContents, MemoryRange};
+use crate::random::{RandomState, SeededRandom};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::time::Duration;
+
+const MAGIC: &[u8; 4] = b"C8ST";
+const VERSION: u16 = 4;
+
+/// A snapshot of everything the emulated program can observe or change
+#[derive(Clone, Debug)]
+pub struct SaveState {
+    pub values: [Word; 0x10],
+    pub program_counter: Address,
+    pub address: Address,
+    pub rpl_flags: [Word; 0x10],
+    pub delay_timer: Word,
+    pub sound_timer: Word,
+    pub timer_accumulator: Duration,
+    pub stack: Vec<Address>,
+    pub banks: Vec<BankContents>,
+    pub vram: Vec<u8>,
+    pub selected_planes: u8,
+    pub audio: AudioPattern,
+    pub halted: bool,
+    /// Only reproducible random sources have a state to save
+    pub random: Option<RandomState>,
+}
+
+impl SaveState {
+    pub fn capture(cpu: &CPU) -> ResultChip8<SaveState> {
+        Ok(SaveState {
+            values: cpu.registers.values,
+            program_counter: cpu.registers.program_counter,
+            address: cpu.registers.address,
+            rpl_flags: cpu.registers.rpl_flags,
+            delay_timer: cpu.timers.delay_timer,
+            sound_timer: cpu.timers.sound_timer,
+            timer_accumulator: cpu.timers.timer_accumulator,
+            stack: cpu.stack.clone(),
+            banks: cpu.memory.dump_writable_banks()?,
+            vram: cpu.vram.data().to_vec(),
+            selected_planes: cpu.vram.selected_planes(),
+            audio: cpu.audio,
+            halted: cpu.halted,
+            random: cpu.random.state(),
+        })
+    }
+
+    /// Everything is checked before the CPU is changed, so it's left untouched on errors
+    pub fn restore(&self, cpu: &mut CPU) -> VoidResultChip8 {
+        for bank in &self.banks {
+            cpu.memory.check_bank(bank)?;
+        }
+        VideoMemory::check_restore(&self.vram)?;
+        if self.selected_planes > VideoMemory::ALL_PLANES {
+            return Err(Error::new(format!(
+                "Invalid plane selection {:X}",
+                self.selected_planes
+            )));
+        }
+
+        for bank in &self.banks {
+            cpu.memory.restore_bank(bank)?;
+        }
+        cpu.vram.restore(&self.vram)?;
+        cpu.vram.select_planes(self.selected_planes)?;
+        cpu.audio = self.audio;
+
+        cpu.registers.values = self.values;
+        cpu.registers.program_counter = self.program_counter;
+        cpu.registers.address = self.address;
+        cpu.registers.rpl_flags = self.rpl_flags;
+        cpu.timers.delay_timer = self.delay_timer;
+        cpu.timers.sound_timer = self.sound_timer;
+        cpu.timers.timer_accumulator = self.timer_accumulator;
+        cpu.stack = self.stack.clone();
+        cpu.halted = self.halted;
+        if let Some(random) = self.random {
+            cpu.random = Box::new(SeededRandom::from_state(random));
+        }
+        Ok(())
+    }
+
+    pub fn load_file(path: &str) -> ResultChip8<SaveState> {
+        File::open(path)
+            .map_err(Error::from)
+            .and_then(|file| SaveState::read(&mut BufReader::new(file)))
+            .map_err(|x| x.chain(format!("Unable to load save state from {}", path)))
+    }
+
+    pub fn save_file(&self, path: &str) -> VoidResultChip8 {
+        let mut file = BufWriter::new(File::create(path)?);
+        self.write(&mut file)?;
+        file.flush()?;
+        Ok(())
+    }
+
+    pub fn read(input: &mut impl Read) -> ResultChip8<SaveState> {
+        let mut magic = [0u8; 4];
+        input.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(Error::new_str("Not a save state file"));
+        }
+
+        let version = read_u16(input)?;
+        match version {
+            1..=4 => SaveState::read_fields(input, version),
+            _ => Err(Error::new(format!(
+                "Unsupported save state version {}, the newest supported version is {}",
+                version, VERSION
+            ))),
+        }
+    }
+
+    /// Every version only added fields at the end: version 2 added the RPL flags, version 3
+    /// the selected planes and the audio pattern, and version 4 the halt and random states
+    fn read_fields(input: &mut impl Read, version: u16) -> ResultChip8<SaveState> {
+        let mut values = [Word::ZERO; 0x10];
+        for value in values.iter_mut() {
+            *value = Word::new(read_u8(input)?);
+        }
+
+        let program_counter = Address::new(read_u16(input)?);
+        let address = Address::new(read_u16(input)?);
+
+        let delay_timer = Word::new(read_u8(input)?);
+        let sound_timer = Word::new(read_u8(input)?);
+        let timer_accumulator = Duration::from_nanos(read_u64(input)?);
+
+        let stack_len = read_u16(input)?;
+        let mut stack = Vec::with_capacity(stack_len.into());
+        for _ in 0..stack_len {
+            stack.push(Address::new(read_u16(input)?));
+        }
+
+        let bank_num = read_u16(input)?;
+        let mut banks = Vec::with_capacity(bank_num.into());
+        for _ in 0..bank_num {
+            let name_bytes = read_bytes(input)?;
+            let name = String::from_utf8(name_bytes)
+                .map_err(|_| Error::new_str("Bank name is not valid UTF-8"))?;
+
+            let min = read_u16(input)?;
+            let max = read_u16(input)?;
+            if min > max {
+                return Err(Error::new(format!(
+                    "Invalid range {:04X}-{:04X} for bank {}",
+                    min, max, name
+                )));
+            }
+
+            let data = read_bytes(input)?.into_iter().map(Word::new).collect();
+            banks.push(BankContents {
+                name,
+                range: MemoryRange::new(min, max),
+                data,
+            });
+        }
+
+        let vram = read_bytes(input)?;
+
+        let mut rpl_flags = [Word::ZERO; 0x10];
+        if version >= 2 {
+            for flag in rpl_flags.iter_mut() {
+                *flag = Word::new(read_u8(input)?);
+            }
+        }
+
+        let mut selected_planes = 1;
+        let mut audio = AudioPattern::new();
+        if version >= 3 {
+            selected_planes = read_u8(input)?;
+            for bits in audio.bits.iter_mut() {
+                *bits = Word::new(read_u8(input)?);
+            }
+            audio.pitch = Word::new(read_u8(input)?);
+        }
+
+        let mut halted = false;
+        let mut random = None;
+        if version >= 4 {
+            halted = read_u8(input)? != 0;
+            if read_u8(input)? != 0 {
+                let seed = read_u64(input)?;
+                let high = read_u64(input)?;
+                let low = read_u64(input)?;
+                random = Some(RandomState {
+                    seed,
+                    position: u128::from(high) << 64 | u128::from(low),
+                });
+            }
+        }
+
+        Ok(SaveState {
+            values,
+            program_counter,
+            address,
+            rpl_flags,
+            delay_timer,
+            sound_timer,
+            timer_accumulator,
+            stack,
+            banks,
+            vram,
+            selected_planes,
+            audio,
+            halted,
+            random,
+        })
+    }
+
+    pub fn write(&self, out: &mut impl Write) -> VoidResultChip8 {
+        out.write_all(MAGIC)?;
+        write_u16(out, VERSION)?;
+
+        for value in self.values.iter() {
+            out.write_all(&[(*value).into()])?;
+        }
+
+        write_u16(out, self.program_counter.into())?;
+        write_u16(out, self.address.into())?;
+
+        out.write_all(&[self.delay_timer.into(), self.sound_timer.into()])?;
+        write_u64(out, self.timer_accumulator.as_nanos() as u64)?;
+
+        write_u16(out, self.stack.len() as u16)?;
+        for addr in self.stack.iter() {
+            write_u16(out, (*addr).into())?;
+        }
+
+        write_u16(out, self.banks.len() as u16)?;
+        for bank in self.banks.iter() {
+            write_bytes(out, bank.name.as_bytes())?;
+            write_u16(out, bank.range.min.into())?;
+            write_u16(out, bank.range.max.into())?;
+
+            let data: Vec<u8> = bank.data.iter().map(|x| (*x).into()).collect();
+            write_bytes(out, &data)?;
+        }
+
+        write_bytes(out, &self.vram)?;
+
+        for flag in self.rpl_flags.iter() {
+            out.write_all(&[(*flag).into()])?;
+        }
+
+        out.write_all(&[self.selected_planes])?;
+        for bits in self.audio.bits.iter() {
+            out.write_all(&[(*bits).into()])?;
+        }
+        out.write_all(&[self.audio.pitch.into()])?;
+
+        out.write_all(&[self.halted as u8, self.random.is_some() as u8])?;
+        if let Some(random) = self.random {
+            write_u64(out, random.seed)?;
+            write_u64(out, (random.position >> 64) as u64)?;
+            write_u64(out, random.position as u64)?;
+        }
+        Ok(())
+    }
+}
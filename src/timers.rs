@@ -1,42 +1,50 @@
 use crate::core::Word;
 
-use std::time::{Duration, Instant};
+use std::time::Duration;
+
+/// How much time passes between decrements of the timers
+pub const TIMER_PERIOD: Duration = Duration::from_nanos(1000000000 / 60);
 
 #[derive(Debug)]
 pub struct Timers {
     pub delay_timer: Word,
     pub sound_timer: Word,
-    last_tick: Instant,
-    delay_accumulator: Duration,
+    pub(crate) timer_accumulator: Duration,
 }
 
-const DELAY_FREQUENCY: Duration = Duration::from_nanos(1000000000 / 60);
+impl Default for Timers {
+    fn default() -> Self {
+        Timers::new()
+    }
+}
 
 impl Timers {
     pub fn new() -> Timers {
         Timers {
             delay_timer: 0.into(),
             sound_timer: 0.into(),
-            last_tick: Instant::now(),
-            delay_accumulator: Duration::from_nanos(0),
+            timer_accumulator: Duration::from_nanos(0),
         }
     }
 
-    pub fn tick(&mut self) {
-        let now = Instant::now();
-        let elapsed = now.duration_since(self.last_tick);
-        self.delay_accumulator += elapsed;
-        self.last_tick = now;
+    pub fn tick(&mut self, elapsed: Duration) {
+        self.timer_accumulator += elapsed;
 
-        while self.delay_accumulator >= DELAY_FREQUENCY {
-            self.try_decrement_delay();
-            self.delay_accumulator -= DELAY_FREQUENCY;
+        while self.timer_accumulator >= TIMER_PERIOD {
+            Timers::try_decrement(&mut self.delay_timer);
+            Timers::try_decrement(&mut self.sound_timer);
+            self.timer_accumulator -= TIMER_PERIOD;
         }
     }
 
-    fn try_decrement_delay(&mut self) {
-        if self.delay_timer > 0.into() {
-            self.delay_timer -= 1;
+    /// The CPU beeps for as long as the sound timer is active
+    pub fn is_sound_active(&self) -> bool {
+        self.sound_timer > 0.into()
+    }
+
+    fn try_decrement(timer: &mut Word) {
+        if *timer > 0.into() {
+            *timer -= 1;
         }
     }
 }
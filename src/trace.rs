@@ -0,0 +1,370 @@
+use crate::binary::{read_u16, read_u64_or_end, read_u8, write_u16, write_u64, write_u8};
+use crate::core::{Address, Error, ResultChip8, VoidResultChip8, Word};
+use crate::memory::MemoryRange;
+use crate::opcodes::Opcode;
+use std::cell::RefCell;
+use std::convert::TryFrom;
+use std::fmt::{self, Display, Formatter};
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::rc::Rc;
+
+const MAGIC: &[u8; 4] = b"C8TR";
+/// Version 2 added the memory writes of every instruction
+const VERSION: u16 = 2;
+
+/// One instruction that ran, with the state of the CPU right after it
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub struct TraceEntry {
+    /// How many instructions ran before this one
+    pub cycle: u64,
+    pub program_counter: Address,
+    pub raw: u16,
+    /// The word after `raw`, for instructions that take 4 bytes
+    pub operand: Option<u16>,
+    pub opcode: Opcode,
+    pub values: [Word; 0x10],
+    pub address: Address,
+    pub stack_depth: usize,
+    /// Every memory write the instruction made, in order
+    pub writes: Vec<(Address, Word)>,
+}
+
+impl TraceEntry {
+    /// Describes every way `other` differs from this entry, ignoring the cycle count
+    pub fn differences(&self, other: &TraceEntry) -> Vec<String> {
+        let mut result = Vec::new();
+
+        if self.program_counter != other.program_counter {
+            result.push(format!(
+                "PC {} vs {}",
+                self.program_counter, other.program_counter
+            ));
+        }
+        if (self.raw, self.operand) != (other.raw, other.operand) {
+            result.push(format!("opcode {} vs {}", self.opcode, other.opcode));
+        }
+        for (i, (a, b)) in self.values.iter().zip(other.values.iter()).enumerate() {
+            if a != b {
+                result.push(format!("V{:X} {} vs {}", i, a, b));
+            }
+        }
+        if self.address != other.address {
+            result.push(format!("I {} vs {}", self.address, other.address));
+        }
+        if self.stack_depth != other.stack_depth {
+            result.push(format!(
+                "SP {:X} vs {:X}",
+                self.stack_depth, other.stack_depth
+            ));
+        }
+        if self.writes != other.writes {
+            result.push("memory writes".to_owned());
+        }
+
+        result
+    }
+}
+
+impl Display for TraceEntry {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let raw = match self.operand {
+            Some(operand) => format!("{:04X}{:04X}", self.raw, operand),
+            None => format!("{:04X}", self.raw),
+        };
+
+        write!(
+            f,
+            "{:010} {} {:<8} {:<24} V=",
+            self.cycle,
+            self.program_counter,
+            raw,
+            self.opcode.to_string()
+        )?;
+        for value in self.values.iter() {
+            write!(f, "{}", value)?;
+        }
+        write!(f, " I={} SP={:X}", self.address, self.stack_depth)?;
+
+        for (addr, value) in self.writes.iter() {
+            write!(f, " [{}]={}", addr, value)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Receives every instruction the CPU runs
+pub trait TraceSink {
+    fn trace(&mut self, entry: &TraceEntry) -> VoidResultChip8;
+
+    /// Called at the end of every frame
+    fn end_frame(&mut self) -> VoidResultChip8 {
+        Ok(())
+    }
+}
+
+/// Lets a sink be shared, to look at what it gathered while the CPU still owns it
+impl<T: TraceSink> TraceSink for Rc<RefCell<T>> {
+    fn trace(&mut self, entry: &TraceEntry) -> VoidResultChip8 {
+        self.borrow_mut().trace(entry)
+    }
+
+    fn end_frame(&mut self) -> VoidResultChip8 {
+        self.borrow_mut().end_frame()
+    }
+}
+
+#[derive(Eq, PartialEq, Clone, Copy, Debug)]
+pub enum TraceFormat {
+    /// One human-readable line per instruction
+    Text,
+    /// Fixed-layout records, much smaller and faster to write for long runs
+    Binary,
+}
+
+/// Writes a trace to a file, optionally only for instructions in some address ranges
+pub struct TraceWriter {
+    out: Box<dyn Write>,
+    format: TraceFormat,
+    /// Only instructions stored in these ranges are written, or all of them if it's empty
+    pub ranges: Vec<MemoryRange>,
+}
+
+impl TraceWriter {
+    pub fn create_file(path: &str, format: TraceFormat) -> ResultChip8<TraceWriter> {
+        TraceWriter::new(BufWriter::new(File::create(path)?), format)
+    }
+
+    pub fn new(out: impl Write + 'static, format: TraceFormat) -> ResultChip8<TraceWriter> {
+        let mut writer = TraceWriter {
+            out: Box::new(out),
+            format,
+            ranges: Vec::new(),
+        };
+
+        if format == TraceFormat::Binary {
+            writer.out.write_all(MAGIC)?;
+            write_u16(&mut writer.out, VERSION)?;
+        }
+
+        Ok(writer)
+    }
+
+    fn write_binary(&mut self, entry: &TraceEntry) -> VoidResultChip8 {
+        let out = &mut self.out;
+        write_u64(out, entry.cycle)?;
+        write_u16(out, entry.program_counter.into())?;
+        write_u16(out, entry.raw)?;
+        if let Some(operand) = entry.operand {
+            write_u16(out, operand)?;
+        }
+        for value in entry.values.iter() {
+            write_u8(out, (*value).into())?;
+        }
+        write_u16(out, entry.address.into())?;
+        let stack_depth = u8::try_from(entry.stack_depth)
+            .map_err(|_| Error::new(format!("Stack too deep to trace: {}", entry.stack_depth)))?;
+        write_u8(out, stack_depth)?;
+
+        let write_num = u8::try_from(entry.writes.len()).map_err(|_| {
+            Error::new(format!(
+                "Too many memory writes to trace: {}",
+                entry.writes.len()
+            ))
+        })?;
+        write_u8(out, write_num)?;
+        for (addr, value) in entry.writes.iter() {
+            write_u16(out, (*addr).into())?;
+            write_u8(out, (*value).into())?;
+        }
+
+        Ok(())
+    }
+}
+
+impl TraceSink for TraceWriter {
+    fn trace(&mut self, entry: &TraceEntry) -> VoidResultChip8 {
+        if !self.ranges.is_empty()
+            && !self
+                .ranges
+                .iter()
+                .any(|x| x.contains(entry.program_counter))
+        {
+            return Ok(());
+        }
+
+        match self.format {
+            TraceFormat::Text => writeln!(self.out, "{}", entry)?,
+            TraceFormat::Binary => self.write_binary(entry)?,
+        };
+        Ok(())
+    }
+
+    fn end_frame(&mut self) -> VoidResultChip8 {
+        // Flushing per frame rather than per instruction keeps tracing cheap, while a crash or
+        // Ctrl+C still leaves everything up to the last frame in the file
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+/// Reads back a trace written by `TraceWriter`, in either format
+pub struct TraceReader {
+    input: BufReader<File>,
+    format: TraceFormat,
+    version: u16,
+}
+
+impl TraceReader {
+    pub fn open_file(path: &str) -> ResultChip8<TraceReader> {
+        let mut input = BufReader::new(File::open(path)?);
+
+        let (format, version) = if input.fill_buf()?.starts_with(MAGIC) {
+            input.consume(MAGIC.len());
+            let version = read_u16(&mut input)?;
+            if version == 0 || version > VERSION {
+                return Err(Error::new(format!(
+                    "Unsupported trace version {}, the newest supported version is {}",
+                    version, VERSION
+                )));
+            }
+            (TraceFormat::Binary, version)
+        } else {
+            (TraceFormat::Text, VERSION)
+        };
+
+        Ok(TraceReader {
+            input,
+            format,
+            version,
+        })
+    }
+
+    fn read_text(&mut self) -> ResultChip8<Option<TraceEntry>> {
+        let mut line = String::new();
+        if self.input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+
+        let invalid = || Error::new(format!("Invalid trace line: {}", line.trim_end()));
+        let hex = |text: &str| u16::from_str_radix(text, 16).map_err(|_| invalid());
+
+        let mut words = line.split_whitespace();
+        let mut next = || words.next().ok_or_else(invalid);
+
+        let cycle = next()?.parse::<u64>().map_err(|_| invalid())?;
+        let program_counter = Address::new(hex(next()?)?);
+        let raw_text = next()?;
+        let (raw, operand) = match raw_text.len() {
+            4 => (hex(raw_text)?, None),
+            8 => (hex(&raw_text[..4])?, Some(hex(&raw_text[4..])?)),
+            _ => return Err(invalid()),
+        };
+
+        // The opcode text has spaces in it, so skip to the registers instead of parsing it
+        let mut words = line.split_whitespace().skip_while(|x| !x.starts_with("V="));
+        let mut next = || words.next().ok_or_else(invalid);
+
+        let values_text = &next()?[2..];
+        if values_text.len() != 0x20 || !values_text.is_ascii() {
+            return Err(invalid());
+        }
+        let mut values = [Word::new(0u8); 0x10];
+        for (i, value) in values.iter_mut().enumerate() {
+            let byte = u8::from_str_radix(&values_text[i * 2..i * 2 + 2], 16);
+            *value = Word::new(byte.map_err(|_| invalid())?);
+        }
+
+        let address = Address::new(hex(next()?.strip_prefix("I=").ok_or_else(invalid)?)?);
+        let stack_depth = next()?.strip_prefix("SP=").ok_or_else(invalid)?;
+        let stack_depth = usize::from_str_radix(stack_depth, 16).map_err(|_| invalid())?;
+
+        let mut writes = Vec::new();
+        for write in words {
+            let (addr, value) = write
+                .strip_prefix('[')
+                .and_then(|x| x.split_once("]="))
+                .ok_or_else(invalid)?;
+            let value = u8::from_str_radix(value, 16).map_err(|_| invalid())?;
+            writes.push((Address::new(hex(addr)?), Word::new(value)));
+        }
+
+        Ok(Some(TraceEntry {
+            cycle,
+            program_counter,
+            raw,
+            operand,
+            opcode: decode(raw, operand)?,
+            values,
+            address,
+            stack_depth,
+            writes,
+        }))
+    }
+
+    fn read_binary(&mut self) -> ResultChip8<Option<TraceEntry>> {
+        // Records go until the end of the file, since tracing can be interrupted at any time
+        let cycle = match read_u64_or_end(&mut self.input)? {
+            Some(x) => x,
+            None => return Ok(None),
+        };
+
+        let input = &mut self.input;
+        let program_counter = Address::new(read_u16(input)?);
+        let raw = read_u16(input)?;
+        let operand = if Opcode::has_operand(raw) {
+            Some(read_u16(input)?)
+        } else {
+            None
+        };
+
+        let mut values = [Word::new(0u8); 0x10];
+        for value in values.iter_mut() {
+            *value = Word::new(read_u8(input)?);
+        }
+        let address = Address::new(read_u16(input)?);
+        let stack_depth = read_u8(input)?.into();
+
+        let write_num = if self.version >= 2 {
+            read_u8(input)?
+        } else {
+            0
+        };
+        let mut writes = Vec::with_capacity(write_num.into());
+        for _ in 0..write_num {
+            writes.push((Address::new(read_u16(input)?), Word::new(read_u8(input)?)));
+        }
+
+        Ok(Some(TraceEntry {
+            cycle,
+            program_counter,
+            raw,
+            operand,
+            opcode: decode(raw, operand)?,
+            values,
+            address,
+            stack_depth,
+            writes,
+        }))
+    }
+}
+
+impl Iterator for TraceReader {
+    type Item = ResultChip8<TraceEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let result = match self.format {
+            TraceFormat::Text => self.read_text(),
+            TraceFormat::Binary => self.read_binary(),
+        };
+        result.transpose()
+    }
+}
+
+fn decode(raw: u16, operand: Option<u16>) -> ResultChip8<Opcode> {
+    match operand {
+        Some(operand) => Opcode::decode_with_operand(raw, operand),
+        None => Opcode::decode(raw),
+    }
+}
@@ -0,0 +1,155 @@
+use chip8::assembler;
+use std::fs;
+
+#[test]
+fn pseudocode_lines() {
+    let rom = assembler::assemble(
+        "
+        start: V0 += 01 ; comment
+        draw *I at (V0; V1) size 8x5
+        draw *I at (V0; V1) size 8x5 ; comment; with (parentheses)
+        goto 200
+        ",
+    )
+    .unwrap();
+    assert_eq!(
+        rom,
+        [0x70, 0x01, 0xD0, 0x15, 0xD0, 0x15, 0x12, 0x00].to_vec()
+    );
+}
+
+fn assert_error(source: &str, expected: &str) {
+    match assembler::assemble(source) {
+        Ok(rom) => panic!("Expected an error, got {:02X?}", rom),
+        Err(err) => assert!(
+            err.to_string().starts_with(expected),
+            "{} doesn't start with {}",
+            err,
+            expected
+        ),
+    }
+}
+
+#[test]
+fn labels() {
+    let rom = assembler::assemble(
+        "
+        start:
+            CALL sub        ; defined later
+            LD I, data + 1
+            JP start
+        sub: RET
+        data: db 1, 2
+        ",
+    )
+    .unwrap();
+    assert_eq!(
+        rom,
+        [0x22, 0x06, 0xA2, 0x09, 0x12, 0x00, 0x00, 0xEE, 0x01, 0x02].to_vec()
+    );
+}
+
+#[test]
+fn constants() {
+    let rom = assembler::assemble(
+        "
+        const SPEED = 3
+        const END = table + SPEED - 1
+        const LATE = table
+            LD V0, SPEED
+            LD I, END
+            LD I, LONG LATE
+        table: db SPEED
+        ",
+    )
+    .unwrap();
+    assert_eq!(
+        rom,
+        [0x60, 0x03, 0xA2, 0x0A, 0xF0, 0x00, 0x02, 0x08, 0x03].to_vec()
+    );
+
+    assert_error(
+        "const FIRST = SECOND\nconst SECOND = FIRST + 1\nLD V0, FIRST",
+        "<source>:2:16: `FIRST` is defined in terms of itself",
+    );
+}
+
+#[test]
+fn data() {
+    let rom = assembler::assemble(
+        r#"
+        db 0x12, 0b1010, 255, "AB"
+        dw 0x1234, -1, end
+        sprite ..####.. #......#
+        sprite ########........
+        end:
+        "#,
+    )
+    .unwrap();
+    assert_eq!(
+        rom,
+        [
+            0x12, 0x0A, 0xFF, 0x41, 0x42, // db
+            0x12, 0x34, 0xFF, 0xFF, 0x02, 0x0F, // dw
+            0x3C, 0x81, // 8 pixel sprite
+            0xFF, 0x00, // 16 pixel sprite
+        ]
+        .to_vec()
+    );
+
+    assert_error("db 256", "<source>:1:4: 256 doesn't fit in a byte");
+    assert_error("dw 0x10000", "<source>:1:4: 65536 doesn't fit in 16 bits");
+    assert_error(
+        "sprite ..##",
+        "<source>:1:8: Sprite rows must be 8 or 16 pixels",
+    );
+}
+
+#[test]
+fn includes() {
+    let directory = std::env::temp_dir().join(format!("chip8-asm-{}", std::process::id()));
+    let nested = directory.join("lib");
+    fs::create_dir_all(&nested).unwrap();
+
+    fs::write(
+        directory.join("main.asm"),
+        "include \"lib/sub.asm\"\nCALL sub\n",
+    )
+    .unwrap();
+    // Paths are relative to the file that includes them
+    fs::write(
+        nested.join("sub.asm"),
+        "JP over\ninclude \"data.asm\"\nover:\nsub: RET\n",
+    )
+    .unwrap();
+    fs::write(nested.join("data.asm"), "db 0xAB\n").unwrap();
+    fs::write(nested.join("loop.asm"), "CLS\ninclude \"loop.asm\"\n").unwrap();
+
+    let rom = assembler::assemble_file(directory.join("main.asm").to_str().unwrap()).unwrap();
+    assert_eq!(rom, [0x12, 0x03, 0xAB, 0x00, 0xEE, 0x22, 0x03].to_vec());
+
+    let loop_path = nested.join("loop.asm");
+    let err = assembler::assemble_file(loop_path.to_str().unwrap()).unwrap_err();
+    assert!(
+        err.to_string()
+            .starts_with(&format!("{}:2:9: ", loop_path.display())),
+        "{}",
+        err
+    );
+    assert!(err.to_string().contains("includes itself"), "{}", err);
+
+    fs::remove_dir_all(&directory).unwrap();
+}
+
+#[test]
+fn errors_have_locations() {
+    assert_error("CLS\n  FOO V0", "<source>:2:3: Unknown instruction `FOO`");
+    assert_error("CLS\nJP nowhere", "<source>:2:4: Unknown name `nowhere`");
+    assert_error(
+        "a: CLS\n  a: RET",
+        "<source>:2:3: `a` was already defined at <source>:1:1",
+    );
+    assert_error("LD V0, 0x100", "<source>:1:8: 256 doesn't fit in a byte");
+    assert_error("LD V0, 1 2", "<source>:1:");
+    assert_error("V0 += 01\nLD V0 , 12$", "<source>:2:11: Unexpected `$`");
+}
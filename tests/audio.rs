@@ -0,0 +1,98 @@
+use chip8::audio::WavAudio;
+use chip8::input::ScriptedInput;
+use chip8::CPU;
+use std::cell::RefCell;
+use std::convert::TryInto;
+use std::io::Cursor;
+use std::rc::Rc;
+
+const ROM: &[u8] = &[
+    0x60, 0x1E, // 0200: V0 = 1E
+    0xF0, 0x18, // 0202: sound timer = V0
+    0x12, 0x04, // 0204: goto 0204
+];
+
+fn u32_at(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+#[test]
+fn wav_follows_the_sound_timer() {
+    let wav = Rc::new(RefCell::new(
+        WavAudio::new(Cursor::new(Vec::new())).unwrap(),
+    ));
+    let mut cpu = CPU::new(ScriptedInput::new());
+    cpu.audio_sink = Box::new(wav.clone());
+    cpu.load_rom(ROM).unwrap();
+
+    for _ in 0..60 {
+        cpu.frame(4).unwrap();
+    }
+    wav.borrow_mut().finish().unwrap();
+
+    let wav = wav.borrow();
+    let data = wav.get_ref().get_ref();
+    assert_eq!(&data[0..4], b"RIFF");
+    assert_eq!(&data[8..16], b"WAVEfmt ");
+    assert_eq!(u32_at(data, 24), 44100);
+    assert_eq!(&data[36..40], b"data");
+
+    // 60 frames are a little less than a second, since a frame is rounded down to whole ns
+    let samples = &data[44..];
+    assert_eq!(samples.len(), 44099);
+    assert_eq!(u32_at(data, 4), 36 + 44099);
+    assert_eq!(u32_at(data, 40), 44099);
+
+    // The timer is set during the first frame, and is 0 by the end of the 30th
+    let playing = samples.iter().take_while(|x| **x != 0x80).count();
+    assert!((29 * 734..=29 * 735 + 1).contains(&playing), "{}", playing);
+    assert!(samples[..playing].iter().all(|x| *x == 0xC0 || *x == 0x40));
+    assert!(samples[playing..].iter().all(|x| *x == 0x80));
+
+    // The default pattern is a square wave of 4 bits on and 4 bits off, at 4000 bits/s
+    let first_low = samples.iter().position(|x| *x == 0x40).unwrap();
+    assert_eq!(first_low, 45);
+}
+
+#[test]
+fn wav_plays_the_loaded_pattern() {
+    let mut rom = vec![
+        0xA2, 0x0C, // 0200: I = 020C
+        0xF0, 0x02, // 0202: audio = *I
+        0x60, 0x70, // 0204: V0 = 70
+        0xF0, 0x3A, // 0206: pitch = V0
+        0xF0, 0x18, // 0208: sound timer = V0
+        0x12, 0x0A, // 020A: goto 020A
+    ];
+    let mut pattern = [0; 16];
+    pattern[0] = 0xFF;
+    rom.extend_from_slice(&pattern);
+
+    let wav = Rc::new(RefCell::new(
+        WavAudio::new(Cursor::new(Vec::new())).unwrap(),
+    ));
+    let mut cpu = CPU::new(ScriptedInput::new());
+    cpu.audio_sink = Box::new(wav.clone());
+    cpu.load_rom(&rom).unwrap();
+
+    for _ in 0..10 {
+        cpu.frame(4).unwrap();
+    }
+    wav.borrow_mut().finish().unwrap();
+
+    let loaded: Vec<u8> = cpu.audio.bits.iter().map(|x| u8::from(*x)).collect();
+    assert_eq!(loaded, pattern.to_vec());
+    assert_eq!(u8::from(cpu.audio.pitch), 0x70);
+
+    // A pitch of 112 plays 8000 bits/s, so the 8 high bits out of 128 last 45 samples out of every 705.6
+    let wav = wav.borrow();
+    let samples = &wav.get_ref().get_ref()[44..];
+    let start = samples.iter().position(|x| *x != 0x80).unwrap();
+    let high = samples[start..].iter().take_while(|x| **x == 0xC0).count();
+    let low = samples[start + high..]
+        .iter()
+        .take_while(|x| **x == 0x40)
+        .count();
+    assert_eq!((high, low), (45, 661));
+    assert_eq!(samples[start + high + low], 0xC0);
+}
@@ -0,0 +1,150 @@
+use chip8::core::Address;
+use chip8::debugger::{opcode_kind, opcode_kinds, Debugger};
+use chip8::input::ScriptedInput;
+use chip8::{Opcode, CPU};
+
+const ROM: &[u8] = &[
+    0x60, 0x05, // 0200: V0 = 05
+    0x22, 0x08, // 0202: call 0208
+    0x12, 0x02, // 0204: goto 0202
+    0x00, 0x00, // 0206
+    0x61, 0x07, // 0208: V1 = 07
+    0xD0, 0x01, // 020A: draw *I at (V0; V0) size 8x1
+    0x00, 0xEE, // 020C: return
+];
+
+fn new_debugger() -> Debugger {
+    let mut cpu = CPU::new(ScriptedInput::new());
+    cpu.load_rom(ROM).unwrap();
+    Debugger::new(cpu, 10)
+}
+
+fn run(debugger: &mut Debugger, line: &str) -> String {
+    let mut out = Vec::new();
+    assert!(debugger.execute(line, &mut out).unwrap(), "{}", line);
+    String::from_utf8(out).unwrap()
+}
+
+fn pc(debugger: &Debugger) -> Address {
+    debugger.cpu.registers.program_counter
+}
+
+#[test]
+fn stepping() {
+    let mut debugger = new_debugger();
+    assert_eq!(run(&mut debugger, "s 2"), "=> 0208: V1 = 07\n");
+    assert_eq!(debugger.cpu.stack.len(), 1);
+
+    assert_eq!(run(&mut debugger, "finish"), "=> 0204: goto 0202\n");
+    assert!(debugger.cpu.stack.is_empty());
+    assert!(debugger.execute("finish", &mut Vec::new()).is_err());
+
+    // Calls run as a whole
+    run(&mut debugger, "s");
+    assert_eq!(run(&mut debugger, "next"), "=> 0204: goto 0202\n");
+    assert_eq!(u8::from(debugger.cpu.registers.values[1]), 0x07);
+}
+
+#[test]
+fn breakpoints() {
+    let mut debugger = new_debugger();
+    assert_eq!(run(&mut debugger, "b 20C"), "Breakpoint at 020C\n");
+    assert_eq!(
+        run(&mut debugger, "c"),
+        "Breakpoint at 020C\n=> 020C: return\n"
+    );
+
+    // Continuing from a breakpoint runs its instruction first
+    run(&mut debugger, "d 20C");
+    assert_eq!(run(&mut debugger, "b 20A"), "Breakpoint at 020A\n");
+    assert_eq!(pc(&debugger), Address::new(0x20Cu16));
+    run(&mut debugger, "c");
+    assert_eq!(pc(&debugger), Address::new(0x20Au16));
+
+    assert!(debugger.execute("d 20C", &mut Vec::new()).is_err());
+    assert_eq!(run(&mut debugger, "bl"), "020A\n");
+}
+
+#[test]
+fn opcode_breakpoints() {
+    let mut debugger = new_debugger();
+    assert_eq!(run(&mut debugger, "bo Draw"), "Breakpoint on Draw\n");
+    assert_eq!(
+        run(&mut debugger, "c"),
+        "Breakpoint on Draw\n=> 020A: draw *I at (V0; V0) size 8x1\n"
+    );
+
+    run(&mut debugger, "d Draw");
+    assert!(debugger.execute("d Draw", &mut Vec::new()).is_err());
+    assert_eq!(run(&mut debugger, "bl"), "");
+
+    // Kinds are checked, so that a typo doesn't silently never stop
+    let err = debugger.execute("bo draw", &mut Vec::new()).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Unknown opcode kind draw, did you mean Draw?"
+    );
+    let err = debugger.execute("bo Paint", &mut Vec::new()).unwrap_err();
+    assert_eq!(err.to_string(), "Unknown opcode kind Paint");
+    assert_eq!(run(&mut debugger, "bl"), "");
+}
+
+#[test]
+fn opcode_kind_names() {
+    assert_eq!(opcode_kind(&Opcode::decode(0xD015).unwrap()), "Draw");
+    assert_eq!(
+        opcode_kind(&Opcode::decode_with_operand(0xF000, 0x1234).unwrap()),
+        "AssignLongAddress"
+    );
+
+    // Every variant can be decoded, so every kind can be broken on
+    let kinds = opcode_kinds();
+    assert_eq!(kinds.len(), 37);
+    assert!(kinds.contains(&"LoadValueRange"));
+}
+
+#[test]
+fn watchpoints() {
+    let mut debugger = new_debugger();
+    assert_eq!(run(&mut debugger, "w x 208 209"), "Watchpoint #1\n");
+    assert_eq!(run(&mut debugger, "w w 300 = 01"), "Watchpoint #2\n");
+    assert_eq!(
+        run(&mut debugger, "bl"),
+        "#1 x 0208-0209\n#2 w 0300-0300 = 01\n"
+    );
+
+    let stop = run(&mut debugger, "c");
+    assert!(stop.starts_with("Watchpoint #1"), "{}", stop);
+    assert_eq!(pc(&debugger), Address::new(0x20Au16));
+
+    run(&mut debugger, "dw 1");
+    assert!(debugger.execute("dw 1", &mut Vec::new()).is_err());
+    assert!(debugger.execute("w q 300", &mut Vec::new()).is_err());
+    assert!(debugger.execute("w r 301 300", &mut Vec::new()).is_err());
+}
+
+#[test]
+fn changing_state() {
+    let mut debugger = new_debugger();
+    run(&mut debugger, "set v3 2A");
+    run(&mut debugger, "set I 300");
+    run(&mut debugger, "poke 300 12 34");
+    assert_eq!(u8::from(debugger.cpu.registers.values[3]), 0x2A);
+    assert_eq!(run(&mut debugger, "x 300 3"), "0300: 12 34 00\n");
+    assert!(debugger.execute("set V3 100", &mut Vec::new()).is_err());
+    assert!(debugger.execute("set VG 1", &mut Vec::new()).is_err());
+
+    assert_eq!(run(&mut debugger, "asm 206 V2 += 01"), "0206: V2 += 01\n");
+    assert_eq!(
+        run(&mut debugger, "dis 204 2"),
+        "   0204: goto 0202\n   0206: V2 += 01\n"
+    );
+
+    run(&mut debugger, "key A down");
+    assert!(debugger.cpu.input.is_down(0xA).unwrap());
+    run(&mut debugger, "key A up");
+    assert!(!debugger.cpu.input.is_down(0xA).unwrap());
+    assert!(debugger.execute("key 10 down", &mut Vec::new()).is_err());
+
+    assert!(!debugger.execute("q", &mut Vec::new()).unwrap());
+}
@@ -0,0 +1,117 @@
+use chip8::assembler;
+use chip8::core::Address;
+use chip8::cpu::PROGRAM_START;
+use chip8::disassembler::{self, LineContent};
+
+/// Writes `rom` as source like `view --source` and assembles it again like `asm`
+fn reassemble(rom: &[u8]) -> Vec<u8> {
+    let mut source = Vec::new();
+    disassembler::write_source(rom, Address::new(PROGRAM_START), &mut source).unwrap();
+    let source = String::from_utf8(source).unwrap();
+    assembler::assemble(&source).unwrap_or_else(|e| panic!("{}\n{}", e, source))
+}
+
+#[test]
+fn source_round_trips() {
+    let roms: &[&[u8]] = &[
+        // Straight-line code with a subroutine and a sprite
+        &[0xA2, 0x08, 0x22, 0x06, 0x12, 0x04, 0x00, 0xEE, 0xF0, 0x90],
+        // A call into the second byte of a jump
+        &[0x12, 0x03, 0x44, 0x22, 0x01, 0x12, 0x05],
+        // Code that only runs at odd addresses
+        &[0x12, 0x03, 0x00, 0x60, 0x05, 0x12, 0x03],
+        // A jump into the operand of F000 NNNN
+        &[0xF0, 0x00, 0x12, 0x02, 0x12, 0x03, 0x00],
+        // A ROM with an odd length
+        &[0x00, 0xE0, 0x12, 0x00, 0xFF],
+    ];
+
+    for rom in roms {
+        assert_eq!(reassemble(rom), rom.to_vec());
+    }
+}
+
+/// Every line of `disassemble_flow`, as text
+fn flow(rom: &[u8]) -> Vec<String> {
+    disassembler::disassemble_flow(rom, Address::new(PROGRAM_START))
+        .into_iter()
+        .map(|line| match line.content {
+            LineContent::Opcode(_, x) => format!("{}: {}", line.address, x),
+            LineContent::Invalid(x, _) => format!("{}: invalid {:04X}", line.address, x),
+            LineContent::LoneByte(x) => format!("{}: byte {:02X}", line.address, x),
+            LineContent::Data(x) => format!("{}: data {:02X}", line.address, x),
+        })
+        .collect()
+}
+
+#[test]
+fn flow_follows_odd_jumps() {
+    let rom = [
+        0x12, 0x03, // 0200: goto 0203
+        0x00, // 0202
+        0x60, 0x05, // 0203: V0 = 05
+        0x12, 0x03, // 0205: goto 0203
+    ];
+    assert_eq!(
+        flow(&rom),
+        vec![
+            "0200: goto 0203",
+            "0202: data 00",
+            "0203: V0 = 05",
+            "0205: goto 0203",
+        ]
+    );
+}
+
+#[test]
+fn flow_skips_long_addresses() {
+    let rom = [
+        0x30, 0x00, // 0200: if V0 == 00 { skip }
+        0xF0, 0x00, 0x12, 0x34, // 0202: I = long 1234
+        0x00, 0xE0, // 0206: clear()
+        0x12, 0x08, // 0208: goto 0208
+    ];
+
+    // The skip lands after the operand, which is never read as an instruction
+    assert_eq!(
+        flow(&rom),
+        vec![
+            "0200: if V0 == 00 { skip }",
+            "0202: I = long 1234",
+            "0206: clear()",
+            "0208: goto 0208",
+        ]
+    );
+}
+
+#[test]
+fn flow_follows_jump_tables() {
+    let rom = [
+        0xB2, 0x06, // 0200: goto 0206 + V0
+        0xAB, 0xCD, // 0202
+        0x12, 0x34, // 0204
+        0x12, 0x0C, // 0206: goto 020C
+        0x12, 0x0E, // 0208: goto 020E
+        0xFF, 0xFF, // 020A
+        0x00, 0xE0, // 020C: clear()
+        0x12, 0x0E, // 020E: goto 020E
+    ];
+
+    // Bytes before the table look like code, but nothing reaches them
+    assert_eq!(
+        flow(&rom),
+        vec![
+            "0200: goto 0206 + V0",
+            "0202: data AB",
+            "0203: data CD",
+            "0204: data 12",
+            "0205: data 34",
+            "0206: goto 020C",
+            "0208: goto 020E",
+            "020A: data FF",
+            "020B: data FF",
+            "020C: clear()",
+            "020E: goto 020E",
+        ]
+    );
+}
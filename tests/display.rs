@@ -0,0 +1,148 @@
+use chip8::input::ScriptedInput;
+use chip8::CPU;
+
+fn run(rom: &[u8], steps: usize) -> CPU {
+    let mut cpu = CPU::new(ScriptedInput::new());
+    cpu.load_rom(rom).unwrap();
+    step(&mut cpu, steps);
+    cpu
+}
+
+fn step(cpu: &mut CPU, steps: usize) {
+    for _ in 0..steps {
+        cpu.step().unwrap();
+    }
+}
+
+fn lit(cpu: &CPU) -> Vec<(usize, usize)> {
+    let mut pixels = Vec::new();
+    for y in 0..cpu.vram.height() {
+        for x in 0..cpu.vram.width() {
+            if cpu.vram.get(x, y).unwrap() != 0 {
+                pixels.push((x, y));
+            }
+        }
+    }
+    pixels
+}
+
+fn first_row(cpu: &CPU, len: usize) -> Vec<u8> {
+    (0..len).map(|x| cpu.vram.get(x, 0usize).unwrap()).collect()
+}
+
+#[test]
+fn high_resolution() {
+    let rom = [
+        0x00, 0xFF, // 0200: high_res()
+        0xA2, 0x0C, // 0202: I = 020C
+        0x60, 0x64, // 0204: V0 = 64
+        0x61, 0x28, // 0206: V1 = 28
+        0xD0, 0x11, // 0208: draw *I at (V0; V1) size 8x1
+        0x00, 0xFE, // 020A: low_res()
+        0x80, // 020C
+    ];
+
+    let mut cpu = run(&rom, 1);
+    assert!(cpu.vram.is_high_resolution());
+    assert_eq!((cpu.vram.width(), cpu.vram.height()), (128, 64));
+
+    step(&mut cpu, 4);
+    assert_eq!(lit(&cpu), vec![(100, 40)]);
+
+    step(&mut cpu, 1);
+    assert!(!cpu.vram.is_high_resolution());
+    assert_eq!((cpu.vram.width(), cpu.vram.height()), (64, 32));
+    assert_eq!(lit(&cpu), vec![]);
+}
+
+#[test]
+fn large_sprites() {
+    let mut rom = vec![
+        0x00, 0xFF, // 0200: high_res()
+        0xA2, 0x0C, // 0202: I = 020C
+        0x60, 0x02, // 0204: V0 = 02
+        0x61, 0x03, // 0206: V1 = 03
+        0xD0, 0x10, // 0208: draw *I at (V0; V1) size 16x16
+        0xD0, 0x10, // 020A: draw *I at (V0; V1) size 16x16
+    ];
+    let mut sprite = [0; 32];
+    sprite[0..2].copy_from_slice(&[0x80, 0x01]);
+    sprite[30..32].copy_from_slice(&[0xFF, 0xFF]);
+    rom.extend_from_slice(&sprite);
+
+    let mut cpu = run(&rom, 5);
+    let mut expected = vec![(2, 3), (17, 3)];
+    expected.extend((2..18).map(|x| (x, 18)));
+    assert_eq!(lit(&cpu), expected);
+    assert_eq!(u8::from(cpu.registers.values[0xF]), 0);
+
+    step(&mut cpu, 1);
+    assert_eq!(lit(&cpu), vec![]);
+    assert_eq!(u8::from(cpu.registers.values[0xF]), 1);
+}
+
+#[test]
+fn scroll() {
+    let rom = [
+        0x00, 0xFF, // 0200: high_res()
+        0xA2, 0x18, // 0202: I = 0218
+        0x60, 0x0A, // 0204: V0 = 0A
+        0x61, 0x0A, // 0206: V1 = 0A
+        0xD0, 0x11, // 0208: draw *I at (V0; V1) size 8x1
+        0x00, 0xC3, // 020A: scroll_down(3)
+        0x00, 0xFB, // 020C: scroll_right(4)
+        0x00, 0xD2, // 020E: scroll_up(2)
+        0x00, 0xFC, // 0210: scroll_left(4)
+        0x00, 0xFC, // 0212: scroll_left(4)
+        0x00, 0xFC, // 0214: scroll_left(4)
+        0x00, 0xFC, // 0216: scroll_left(4)
+        0x80, // 0218
+    ];
+
+    let mut cpu = run(&rom, 5);
+    assert_eq!(lit(&cpu), vec![(10, 10)]);
+
+    for expected in &[(10, 13), (14, 13), (14, 11), (10, 11), (6, 11), (2, 11)] {
+        step(&mut cpu, 1);
+        assert_eq!(lit(&cpu), vec![*expected]);
+    }
+
+    // Whatever scrolls off the screen is lost
+    step(&mut cpu, 1);
+    assert_eq!(lit(&cpu), vec![]);
+}
+
+#[test]
+fn planes() {
+    let rom = [
+        0xA2, 0x12, // 0200: I = 0212
+        0xF3, 0x01, // 0202: plane(3)
+        0xD0, 0x01, // 0204: draw *I at (V0; V0) size 8x1
+        0xF2, 0x01, // 0206: plane(2)
+        0xD0, 0x01, // 0208: draw *I at (V0; V0) size 8x1
+        0xF1, 0x01, // 020A: plane(1)
+        0x00, 0xFB, // 020C: scroll_right(4)
+        0x00, 0xE0, // 020E: clear()
+        0xF4, 0x01, // 0210: plane(4)
+        0xC0, 0xA0, // 0212
+    ];
+
+    // Each selected plane draws its own sprite, one after the other
+    let mut cpu = run(&rom, 3);
+    assert_eq!(first_row(&cpu, 3), vec![3, 1, 2]);
+    assert_eq!(cpu.vram.selected_planes(), 3);
+
+    // With only the second plane selected, it draws the first sprite
+    step(&mut cpu, 2);
+    assert_eq!(first_row(&cpu, 3), vec![1, 3, 2]);
+    assert_eq!(u8::from(cpu.registers.values[0xF]), 1);
+
+    // Scrolling and clearing only affect the selected planes
+    step(&mut cpu, 2);
+    assert_eq!(first_row(&cpu, 7), vec![0, 2, 2, 0, 1, 1, 0]);
+    step(&mut cpu, 1);
+    assert_eq!(lit(&cpu), vec![(1, 0), (2, 0)]);
+
+    // There are only two planes
+    assert!(cpu.step().is_err());
+}
@@ -0,0 +1,175 @@
+use chip8::debugger::Debugger;
+use chip8::gdb::GdbStub;
+use chip8::input::ScriptedInput;
+use chip8::CPU;
+
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+
+const ROM: &[u8] = &[
+    0x60, 0x05, // 0200: V0 = 05
+    0x22, 0x08, // 0202: call 0208
+    0x12, 0x02, // 0204: goto 0202
+    0x00, 0x00, // 0206
+    0x61, 0x07, // 0208: V1 = 07
+    0x00, 0xEE, // 020A: return
+];
+
+/// A minimal RSP client that checks every acknowledgement and checksum it receives
+struct Client {
+    stream: TcpStream,
+    ack: bool,
+}
+
+impl Client {
+    fn send(&mut self, packet: &str) {
+        let checksum = packet.bytes().fold(0u8, |acc, x| acc.wrapping_add(x));
+        write!(self.stream, "${}#{:02x}", packet, checksum).unwrap();
+        if self.ack {
+            assert_eq!(self.read_byte(), b'+');
+        }
+    }
+
+    fn receive(&mut self) -> String {
+        assert_eq!(self.read_byte(), b'$');
+
+        let mut data = Vec::new();
+        loop {
+            match self.read_byte() {
+                b'#' => break,
+                x => data.push(x),
+            }
+        }
+
+        let digits = [self.read_byte(), self.read_byte()];
+        let checksum = u8::from_str_radix(std::str::from_utf8(&digits).unwrap(), 16).unwrap();
+        assert_eq!(
+            checksum,
+            data.iter().fold(0u8, |acc, x| acc.wrapping_add(*x))
+        );
+
+        String::from_utf8(data).unwrap()
+    }
+
+    fn request(&mut self, packet: &str) -> String {
+        self.send(packet);
+        self.receive()
+    }
+
+    fn read_byte(&mut self) -> u8 {
+        let mut byte = [0u8];
+        self.stream.read_exact(&mut byte).unwrap();
+        byte[0]
+    }
+}
+
+fn connect() -> (Client, thread::JoinHandle<()>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let server = thread::spawn(move || {
+        let mut cpu = CPU::new(ScriptedInput::new());
+        cpu.load_rom(ROM).unwrap();
+
+        let (stream, _) = listener.accept().unwrap();
+        stream.set_nodelay(true).unwrap();
+        let mut stub = GdbStub::new(Debugger::new(cpu, 16));
+        stub.serve(stream.try_clone().unwrap(), stream).unwrap();
+    });
+
+    let stream = TcpStream::connect(addr).unwrap();
+    stream.set_nodelay(true).unwrap();
+    (Client { stream, ack: true }, server)
+}
+
+#[test]
+fn registers_and_memory() {
+    let (mut client, server) = connect();
+
+    assert!(client
+        .request("qSupported:swbreak+")
+        .starts_with("PacketSize="));
+    assert_eq!(client.request("?"), "S05");
+    assert_eq!(
+        client.request("g"),
+        format!("{}00000200", "00".repeat(0x10))
+    );
+
+    assert_eq!(client.request("P3=2a"), "OK");
+    assert_eq!(client.request("P10=0123"), "OK");
+    assert_eq!(client.request("p3"), "2a");
+    assert_eq!(client.request("p10"), "0123");
+    assert_eq!(client.request("p12"), "E01");
+
+    assert_eq!(client.request("m200,4"), "60052208");
+    assert_eq!(client.request("M300,2:abcd"), "OK");
+    assert_eq!(client.request("m300,2"), "abcd");
+    assert_eq!(client.request("M300,3:abcd"), "E01");
+
+    // Ranges that go past the largest address must fail instead of overflowing
+    assert_eq!(client.request("mffffffffffffffff,10"), "E01");
+    assert_eq!(client.request("mffff,10"), "E01");
+    assert_eq!(client.request("Mffffffffffffffff,2:abcd"), "E01");
+    assert_eq!(client.request("Mffff,2:abcd"), "E01");
+
+    assert_eq!(client.request("QStartNoAckMode"), "OK");
+    client.ack = false;
+    assert_eq!(client.request("vMustReplyEmpty"), "");
+
+    assert_eq!(client.request("D"), "OK");
+    server.join().unwrap();
+}
+
+#[test]
+fn breakpoints_and_stepping() {
+    let (mut client, server) = connect();
+
+    assert_eq!(client.request("s"), "S05");
+    assert_eq!(client.request("p0"), "05");
+    assert_eq!(client.request("p11"), "0202");
+
+    assert_eq!(client.request("Z0,20a,2"), "OK");
+    assert_eq!(client.request("c"), "S05");
+    assert_eq!(client.request("p11"), "020a");
+    assert_eq!(client.request("p1"), "07");
+
+    // Continuing from a breakpoint must not stop on it again right away
+    assert_eq!(client.request("c"), "S05");
+    assert_eq!(client.request("p11"), "020a");
+
+    assert_eq!(client.request("z0,20a,2"), "OK");
+    client.send("c");
+    client.stream.write_all(&[0x03]).unwrap();
+    assert_eq!(client.receive(), "S02");
+
+    // Replacing the return with an exit instruction ends the program
+    assert_eq!(client.request("M20a,2:00fd"), "OK");
+    assert_eq!(client.request("c"), "W00");
+
+    client.send("k");
+    server.join().unwrap();
+}
+
+#[test]
+fn watchpoints() {
+    let (mut client, server) = connect();
+
+    // Make the subroutine store V0 and V1 at 0300
+    assert_eq!(client.request("M208,6:a300f15500ee"), "OK");
+
+    assert_eq!(client.request("Z2,301,1"), "OK");
+    assert_eq!(client.request("c"), "T05watch:301;");
+    assert_eq!(client.request("p11"), "020c");
+    assert_eq!(client.request("m300,2"), "0500");
+
+    assert_eq!(client.request("z2,301,1"), "OK");
+    assert_eq!(client.request("Z2,301,ffffffffffffffff"), "E01");
+    assert_eq!(client.request("Z2,ffff,2"), "E01");
+    assert_eq!(client.request("Z4,300,2"), "OK");
+    assert_eq!(client.request("c"), "T05awatch:300;");
+    assert_eq!(client.request("p11"), "020c");
+
+    assert_eq!(client.request("D"), "OK");
+    server.join().unwrap();
+}
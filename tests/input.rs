@@ -0,0 +1,67 @@
+use chip8::core::Address;
+use chip8::input::{InputSource, ScriptedInput};
+use chip8::CPU;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+const ROM: &[u8] = &[
+    0xF3, 0x0A, // 0200: V3 = wait_for_key()
+    0xE3, 0x9E, // 0202: if V3 == key { skip }
+    0x12, 0x02, // 0204: goto 0202
+    0xE3, 0xA1, // 0206: if V3 != key { skip }
+    0x12, 0x06, // 0208: goto 0206
+    0x12, 0x0A, // 020A: goto 020A
+];
+
+fn new_cpu() -> (CPU, Rc<RefCell<ScriptedInput>>) {
+    let input = Rc::new(RefCell::new(ScriptedInput::new()));
+    let mut cpu = CPU::new(input.clone());
+    cpu.load_rom(ROM).unwrap();
+    (cpu, input)
+}
+
+fn step(cpu: &mut CPU, count: usize) -> u16 {
+    for _ in 0..count {
+        cpu.step().unwrap();
+    }
+    cpu.registers.program_counter.into()
+}
+
+#[test]
+fn held_keys() {
+    let (mut cpu, input) = new_cpu();
+
+    // Nothing happens until a key is down
+    assert_eq!(step(&mut cpu, 5), 0x200);
+    input.borrow_mut().hold(0xB).unwrap();
+    assert_eq!(step(&mut cpu, 1), 0x202);
+    assert_eq!(u8::from(cpu.registers.values[3]), 0xB);
+
+    // The key is still down, so the skip goes past the loop
+    assert_eq!(step(&mut cpu, 1), 0x206);
+    assert_eq!(step(&mut cpu, 4), 0x206);
+
+    input.borrow_mut().release(0xB).unwrap();
+    assert_eq!(step(&mut cpu, 1), 0x20A);
+
+    assert!(input.borrow_mut().hold(0x10).is_err());
+}
+
+#[test]
+fn scheduled_keys() {
+    let (mut cpu, input) = new_cpu();
+    input.borrow_mut().schedule(3, 0x4, true).unwrap();
+    input.borrow_mut().schedule(6, 0x4, false).unwrap();
+
+    // The key goes down right before the 4th instruction and up before the 7th
+    assert_eq!(step(&mut cpu, 3), 0x200);
+    assert_eq!(step(&mut cpu, 1), 0x202);
+    assert_eq!(step(&mut cpu, 1), 0x206);
+    assert_eq!(step(&mut cpu, 1), 0x208);
+    assert_eq!(step(&mut cpu, 1), 0x206);
+    assert_eq!(step(&mut cpu, 1), 0x20A);
+
+    assert_eq!(input.borrow().ticks(), 8);
+    assert!(!input.borrow().is_down(0x4).unwrap());
+    assert_eq!(cpu.registers.program_counter, Address::new(0x20Au16));
+}
@@ -0,0 +1,63 @@
+use chip8::core::Address;
+use chip8::input::ScriptedInput;
+use chip8::CPU;
+
+fn run(rom: &[u8], steps: usize) -> CPU {
+    let mut cpu = CPU::new(ScriptedInput::new());
+    cpu.load_rom(rom).unwrap();
+    for _ in 0..steps {
+        cpu.step().unwrap();
+    }
+    cpu
+}
+
+fn peek(cpu: &CPU, addr: u16, len: u16) -> Vec<u8> {
+    (addr..addr + len)
+        .map(|x| u8::from(cpu.memory.peek(Address::new(x)).unwrap()))
+        .collect()
+}
+
+#[test]
+fn register_ranges() {
+    let rom = [
+        0xA3, 0x00, // 0200: I = 0300
+        0x61, 0x11, // 0202: V1 = 11
+        0x62, 0x22, // 0204: V2 = 22
+        0x63, 0x33, // 0206: V3 = 33
+        0x51, 0x32, // 0208: *I = range(V1, V3)
+        0xA3, 0x10, // 020A: I = 0310
+        0x53, 0x12, // 020C: *I = range(V3, V1)
+        0xA3, 0x00, // 020E: I = 0300
+        0x56, 0x43, // 0210: range(V6, V4) = *I
+    ];
+
+    // Reversed ranges go through the registers backwards, and I is never changed
+    let cpu = run(&rom, 9);
+    assert_eq!(peek(&cpu, 0x300, 4), vec![0x11, 0x22, 0x33, 0x00]);
+    assert_eq!(peek(&cpu, 0x310, 4), vec![0x33, 0x22, 0x11, 0x00]);
+    let values: Vec<u8> = (4..=6).map(|x| cpu.registers.values[x].into()).collect();
+    assert_eq!(values, vec![0x33, 0x22, 0x11]);
+    assert_eq!(cpu.registers.address, Address::new(0x300u16));
+}
+
+#[test]
+fn skips_long_addresses() {
+    let rom = [
+        0x60, 0x01, // 0200: V0 = 01
+        0x30, 0x01, // 0202: if V0 == 01 { skip }
+        0xF0, 0x00, 0x12, 0x34, // 0204: I = long 1234
+        0x40, 0x01, // 0208: if V0 != 01 { skip }
+        0xF0, 0x00, 0x02, 0x46, // 020A: I = long 0246
+        0x00, 0xE0, // 020E: clear()
+    ];
+
+    // A skipped F000 takes its operand along
+    let mut cpu = run(&rom, 2);
+    assert_eq!(cpu.registers.program_counter, Address::new(0x208u16));
+
+    cpu.step().unwrap();
+    assert_eq!(cpu.registers.program_counter, Address::new(0x20Au16));
+    cpu.step().unwrap();
+    assert_eq!(cpu.registers.program_counter, Address::new(0x20Eu16));
+    assert_eq!(cpu.registers.address, Address::new(0x246u16));
+}
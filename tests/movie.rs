@@ -0,0 +1,125 @@
+use chip8::input::{InputSource, ScriptedInput, KEY_NUM};
+use chip8::movie::{Movie, MovieEvent, MovieHeader, MovieRecorder};
+use chip8::quirks::Quirks;
+use std::env;
+use std::fs;
+use std::io::{self, Cursor, Read};
+use std::path::PathBuf;
+
+const HEADER: MovieHeader = MovieHeader {
+    rom_hash: 0x0123456789ABCDEF,
+    seed: 42,
+    instructions_per_frame: 4,
+    quirks: Quirks::COSMAC_VIP,
+};
+
+fn temp_path(name: &str) -> PathBuf {
+    env::temp_dir().join(format!("chip8-movie-{}-{}", std::process::id(), name))
+}
+
+/// The keys held after every tick, the way the CPU sees them before each instruction
+fn key_states(input: &mut impl InputSource, ticks: usize) -> Vec<Vec<bool>> {
+    (0..ticks)
+        .map(|_| {
+            input.tick().unwrap();
+            (0..KEY_NUM).map(|x| input.is_down(x).unwrap()).collect()
+        })
+        .collect()
+}
+
+fn header_bytes() -> Vec<u8> {
+    let mut data = b"C8MV\x00\x02".to_vec();
+    data.extend_from_slice(&HEADER.rom_hash.to_be_bytes());
+    data.extend_from_slice(&HEADER.seed.to_be_bytes());
+    data.extend_from_slice(&HEADER.instructions_per_frame.to_be_bytes());
+    // Shift uses VY, clip sprites, logic resets VF, and I ends up after the last register
+    data.extend_from_slice(&[0b01101, 2]);
+    data
+}
+
+#[test]
+fn plays_back_what_was_recorded() {
+    // The recorder only looks at its input once per frame, so these ticks are frames
+    let mut input = ScriptedInput::new();
+    input.schedule(1, 5, true).unwrap();
+    input.schedule(2, 0xA, true).unwrap();
+    input.schedule(3, 5, false).unwrap();
+
+    let path = temp_path("round-trip");
+    let mut recorder = MovieRecorder::create_file(path.to_str().unwrap(), HEADER, input).unwrap();
+    let recorded = key_states(&mut recorder, 20);
+    drop(recorder);
+
+    let movie = Movie::load_file(path.to_str().unwrap()).unwrap();
+    fs::remove_file(&path).unwrap();
+    assert_eq!(movie.header, HEADER);
+    assert_eq!(
+        movie.events,
+        vec![
+            MovieEvent {
+                frame: 1,
+                key: 5,
+                down: true
+            },
+            MovieEvent {
+                frame: 2,
+                key: 0xA,
+                down: true
+            },
+            MovieEvent {
+                frame: 3,
+                key: 5,
+                down: false
+            },
+        ]
+    );
+
+    let played = key_states(&mut movie.to_input().unwrap(), 20);
+    assert_eq!(played, recorded);
+    assert!(recorded[4][5] && !recorded[3][5]);
+}
+
+#[test]
+fn rejects_broken_movies() {
+    assert!(Movie::read(&mut Cursor::new(b"C8ST\x00\x01".to_vec())).is_err());
+
+    let mut newer = header_bytes();
+    newer[5] = 3;
+    assert!(Movie::read(&mut Cursor::new(newer)).is_err());
+
+    // Quirks that don't exist
+    let quirks = header_bytes().len() - 2;
+    let mut bad_flags = header_bytes();
+    bad_flags[quirks] = 0x20;
+    assert!(Movie::read(&mut Cursor::new(bad_flags)).is_err());
+    let mut bad_increment = header_bytes();
+    bad_increment[quirks + 1] = 3;
+    assert!(Movie::read(&mut Cursor::new(bad_increment)).is_err());
+
+    let mut bad_key = header_bytes();
+    bad_key.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 1, 0x10, 1]);
+    assert!(Movie::read(&mut Cursor::new(bad_key)).is_err());
+
+    let mut truncated = header_bytes();
+    truncated.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 1, 0x5]);
+    assert!(Movie::read(&mut Cursor::new(truncated)).is_err());
+}
+
+/// Fails every read, like a disk error would
+struct BrokenReader;
+
+impl Read for BrokenReader {
+    fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+        Err(io::Error::other("Broken"))
+    }
+}
+
+#[test]
+fn only_the_end_of_the_file_ends_the_events() {
+    let movie = Movie::read(&mut Cursor::new(header_bytes())).unwrap();
+    assert_eq!(movie.header, HEADER);
+    assert!(movie.events.is_empty());
+
+    let mut input = Cursor::new(header_bytes()).chain(BrokenReader);
+    assert!(Movie::read(&mut input).is_err());
+}
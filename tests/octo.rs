@@ -0,0 +1,193 @@
+use chip8::octo;
+
+#[test]
+fn control_flow() {
+    let rom = octo::compile(
+        "
+        :alias x v1
+        :const LIMIT 20
+        : main
+          loop
+            x += 1
+            if x == LIMIT then x := 0
+            if x < 5 begin
+              draw
+            else
+              v2 := 0
+            end
+          again
+        : draw
+          i := long sprite
+          sprite x x 1
+          ;
+        : sprite 0xFF
+        ",
+    )
+    .unwrap();
+
+    assert_eq!(
+        rom,
+        [
+            0x71, 0x01, // 0200: x += 1
+            0x41, 0x14, // 0202: skip unless x == 20
+            0x61, 0x00, // 0204: x := 0
+            0x6F, 0x05, // 0206: vf := 5
+            0x8F, 0x17, // 0208: vf =- x
+            0x3F, 0x00, // 020A: skip if x < 5
+            0x12, 0x12, // 020C: jump to else
+            0x22, 0x16, // 020E: draw
+            0x12, 0x14, // 0210: jump to end
+            0x62, 0x00, // 0212: v2 := 0
+            0x12, 0x00, // 0214: again
+            0xF0, 0x00, 0x02, 0x1E, // 0216: i := long sprite
+            0xD1, 0x11, // 021A: sprite x x 1
+            0x00, 0xEE, // 021C: return
+            0xFF, // 021E: sprite
+        ]
+        .to_vec()
+    );
+}
+
+#[test]
+fn errors_have_locations() {
+    let error = octo::compile(": main\n  v0 := 300").unwrap_err();
+    assert!(error.to_string().starts_with("<source>:2:9:"), "{}", error);
+
+    let error = octo::compile(": main\n  loop\n  clear").unwrap_err();
+    assert!(error.to_string().starts_with("<source>:2:3:"), "{}", error);
+}
+
+#[test]
+fn subtracting_immediates() {
+    let rom = octo::compile(": main\n  v0 -= 1\n  v1 -= 200\n  v2 -= 0\n  v3 -= -1").unwrap();
+    assert_eq!(
+        rom,
+        [
+            0x70, 0xFF, // 0200: v0 += 255
+            0x71, 0x38, // 0202: v1 += 56
+            0x72, 0x00, // 0204: v2 += 0
+            0x73, 0x01, // 0206: v3 += 1
+        ]
+        .to_vec()
+    );
+
+    assert!(octo::compile(": main\n  v0 -= 256").is_err());
+}
+
+#[test]
+fn data_before_main() {
+    let rom = octo::compile(": data 0x12 0x34\n: main\n  i := data\n  jump main").unwrap();
+    assert_eq!(
+        rom,
+        [
+            0x12, 0x04, // 0200: jump main
+            0x12, 0x34, // 0202: data
+            0xA2, 0x02, // 0204: i := data
+            0x12, 0x04, // 0206: jump main
+        ]
+        .to_vec()
+    );
+}
+
+#[test]
+fn macros() {
+    let rom = octo::compile(
+        "
+        :macro swap a b { vf := a a := b b := vf }
+        : main
+          swap v1 v2
+          swap v3 v4
+        ",
+    )
+    .unwrap();
+    assert_eq!(
+        rom,
+        [0x8F, 0x10, 0x81, 0x20, 0x82, 0xF0, 0x8F, 0x30, 0x83, 0x40, 0x84, 0xF0].to_vec()
+    );
+}
+
+#[test]
+fn calc_and_byte() {
+    let rom = octo::compile(
+        "
+        :const BASE 3
+        :calc SIZE { BASE * 2 + 1 }
+        :calc NESTED { ( 1 + 2 ) * 4 }
+        : main
+          v0 := SIZE
+          v1 := NESTED
+          :byte SIZE
+          :byte { 2 pow 4 - 1 }
+          :byte { HERE - 0x200 }
+        ",
+    )
+    .unwrap();
+    // Expressions have no precedence and are evaluated right to left
+    assert_eq!(rom, [0x60, 0x09, 0x61, 0x0C, 0x09, 0x08, 0x06].to_vec());
+}
+
+#[test]
+fn unpack() {
+    let rom = octo::compile(
+        "
+        : main
+          :unpack 0xA later
+          :unpack 0xB main
+        : later
+        ",
+    )
+    .unwrap();
+    assert_eq!(
+        rom,
+        [0x60, 0xA2, 0x61, 0x08, 0x60, 0xB2, 0x61, 0x00].to_vec()
+    );
+}
+
+#[test]
+fn org() {
+    let rom = octo::compile(
+        "
+        : main
+          jump far
+        :org 0x208
+        : far
+          return
+        ",
+    )
+    .unwrap();
+    assert_eq!(
+        rom,
+        [0x12, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xEE].to_vec()
+    );
+
+    assert!(octo::compile(": main :org 0x100").is_err());
+}
+
+#[test]
+fn while_loops() {
+    let rom = octo::compile(
+        "
+        : main
+          loop
+            while v0 != 5
+            v0 += 1
+            while v1 key
+          again
+        ",
+    )
+    .unwrap();
+    assert_eq!(
+        rom,
+        [
+            0x40, 0x05, // 0200: skip if v0 != 5
+            0x12, 0x0C, // 0202: break
+            0x70, 0x01, // 0204: v0 += 1
+            0xE1, 0x9E, // 0206: skip if v1 is held
+            0x12, 0x0C, // 0208: break
+            0x12, 0x00, // 020A: again
+        ]
+        .to_vec()
+    );
+
+    assert!(octo::compile(": main while v0 == 1").is_err());
+}
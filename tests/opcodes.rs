@@ -0,0 +1,94 @@
+use chip8::opcodes::{Condition, OpcodeParam};
+use chip8::Opcode;
+
+#[test]
+fn encode_is_the_inverse_of_decode() {
+    for value in 0..=0xFFFFu16 {
+        if Opcode::has_operand(value) {
+            for operand in 0..=0xFFFFu16 {
+                let opcode = Opcode::decode_with_operand(value, operand).unwrap();
+                assert_eq!(opcode.encode().unwrap(), (value, Some(operand)));
+            }
+            continue;
+        }
+
+        if let Ok(opcode) = Opcode::decode(value) {
+            assert_eq!(
+                opcode.encode().ok(),
+                Some((value, None)),
+                "{:04X} decodes to {:?}",
+                value,
+                opcode
+            );
+            assert_eq!(opcode.encode_bytes().unwrap(), value.to_be_bytes().to_vec());
+        }
+    }
+}
+
+#[test]
+fn register_comparisons() {
+    assert_eq!(
+        Opcode::decode(0x5120).unwrap(),
+        Opcode::CondJump {
+            left: OpcodeParam::Register(1),
+            right: OpcodeParam::Register(2),
+            cond: Condition::Equal,
+        }
+    );
+    assert_eq!(
+        Opcode::decode(0x9AB0).unwrap(),
+        Opcode::CondJump {
+            left: OpcodeParam::Register(0xA),
+            right: OpcodeParam::Register(0xB),
+            cond: Condition::NotEqual,
+        }
+    );
+    assert!(Opcode::decode(0x5121).is_err());
+    assert!(Opcode::decode(0x912F).is_err());
+}
+
+#[test]
+fn out_of_range_fields() {
+    assert!(Opcode::Draw {
+        x: 0,
+        y: 0x10,
+        height: 5
+    }
+    .encode()
+    .is_err());
+    assert!(Opcode::Jump(0x1000u16.into()).encode().is_err());
+    assert!(Opcode::CallNative(0x00E0u16.into()).encode().is_err());
+}
+
+#[test]
+fn parse_is_the_inverse_of_display() {
+    for value in 0..=0xFFFFu16 {
+        let opcode = match Opcode::decode(value) {
+            Ok(x) => x,
+            Err(_) => continue,
+        };
+
+        let text = opcode.to_string();
+        let parsed: Opcode = text.parse().unwrap_or_else(|x| panic!("{}: {}", text, x));
+        assert_eq!(parsed, opcode, "{}", text);
+    }
+
+    let range: Opcode = "*I = range(V3, V1)".parse().unwrap();
+    assert_eq!(range, Opcode::decode(0x5312).unwrap());
+    let range: Opcode = "range(V0, V2) = *I".parse().unwrap();
+    assert_eq!(range, Opcode::decode(0x5023).unwrap());
+
+    let long: Opcode = "I = long 1234".parse().unwrap();
+    assert_eq!(long, Opcode::decode_with_operand(0xF000, 0x1234).unwrap());
+}
+
+#[test]
+fn parse_errors() {
+    assert!("V3 += 5".parse::<Opcode>().is_ok());
+    assert!("V3 += 100".parse::<Opcode>().is_err());
+    assert!("VG = V1".parse::<Opcode>().is_err());
+    assert!("goto 1000".parse::<Opcode>().is_err());
+    assert!("V1 = V2 - V3".parse::<Opcode>().is_err());
+    assert!("draw *I at (V0; V1) size 8x0".parse::<Opcode>().is_err());
+    assert!("return now".parse::<Opcode>().is_err());
+}
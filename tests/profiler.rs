@@ -0,0 +1,113 @@
+use chip8::core::Address;
+use chip8::input::ScriptedInput;
+use chip8::profiler::{Profiler, SubroutineProfile};
+use chip8::CPU;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+const ROM: &[u8] = &[
+    0x60, 0x02, // 0200: V0 = 02
+    0x22, 0x10, // 0202: call 0210
+    0x12, 0x04, // 0204: goto 0204
+    0x40, 0x00, // 0206: if V0 != 00 { skip }
+    0x00, 0xEE, // 0208: return
+    0x70, 0xFF, // 020A: V0 += FF
+    0x22, 0x06, // 020C: call 0206
+    0x00, 0xEE, // 020E: return
+    0x22, 0x06, // 0210: call 0206
+    0x00, 0xEE, // 0212: return
+];
+
+fn profile() -> Rc<RefCell<Profiler>> {
+    let profiler = Rc::new(RefCell::new(Profiler::new()));
+    let mut cpu = CPU::new(ScriptedInput::new());
+    cpu.tracers.push(Box::new(profiler.clone()));
+    cpu.load_rom(ROM).unwrap();
+
+    // Returns from the recursion after 14 instructions, then loops in place 3 times
+    cpu.frame(17).unwrap();
+    profiler
+}
+
+#[test]
+fn hotspots() {
+    let profiler = profile();
+    let profiler = profiler.borrow();
+    assert_eq!((profiler.instructions, profiler.frames), (17, 1));
+
+    let hotspots: Vec<(u16, u64)> = profiler
+        .hotspots()
+        .into_iter()
+        .map(|(addr, count)| (addr.into(), count))
+        .collect();
+    assert_eq!(
+        hotspots,
+        vec![
+            (0x204, 3),
+            (0x206, 3),
+            (0x20A, 2),
+            (0x20C, 2),
+            (0x20E, 2),
+            (0x200, 1),
+            (0x202, 1),
+            (0x208, 1),
+            (0x210, 1),
+            (0x212, 1),
+        ]
+    );
+}
+
+#[test]
+fn subroutines() {
+    let profiler = profile();
+
+    // Every level of the recursion is in 0206, but its instructions only count once
+    assert_eq!(
+        profiler.borrow().subroutines(),
+        vec![
+            SubroutineProfile {
+                address: Address::new(0x210u16),
+                calls: 1,
+                total: 12,
+                own: 2,
+            },
+            SubroutineProfile {
+                address: Address::new(0x206u16),
+                calls: 3,
+                total: 10,
+                own: 10,
+            },
+        ]
+    );
+}
+
+#[test]
+fn folded_stacks() {
+    let mut out = Vec::new();
+    profile().borrow().write_folded(&mut out).unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "main 5\n\
+         main;sub_0210 2\n\
+         main;sub_0210;sub_0206 4\n\
+         main;sub_0210;sub_0206;sub_0206 4\n\
+         main;sub_0210;sub_0206;sub_0206;sub_0206 2\n"
+    );
+}
+
+#[test]
+fn report() {
+    let mut out = Vec::new();
+    profile().borrow().write_report(&mut out, 2).unwrap();
+    let text = String::from_utf8(out).unwrap();
+    let lines: Vec<&str> = text.lines().collect();
+
+    assert_eq!(lines[0], "17 instructions ran in 1 frames, 17.0 per frame");
+    assert_eq!(lines[4], "           3  17.65%        3.0  0204: goto 0204");
+    assert_eq!(
+        lines[5],
+        "           3  17.65%        3.0  0206: if V0 != 00 { skip }"
+    );
+    assert_eq!(lines[6], "");
+    assert_eq!(lines.len(), 11);
+}
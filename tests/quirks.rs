@@ -0,0 +1,144 @@
+use chip8::core::{Address, Word};
+use chip8::input::ScriptedInput;
+use chip8::quirks::Quirks;
+use chip8::{Opcode, CPU};
+
+fn run(quirks: Quirks, rom: &[u8], steps: usize) -> CPU {
+    let mut cpu = CPU::new(ScriptedInput::new());
+    cpu.quirks = quirks;
+    cpu.load_rom(rom).unwrap();
+    for _ in 0..steps {
+        cpu.step().unwrap();
+    }
+    cpu
+}
+
+fn value(cpu: &CPU, reg: usize) -> u8 {
+    cpu.registers.values[reg].into()
+}
+
+#[test]
+fn shift() {
+    let rom = [
+        0x61, 0x10, // V1 = 10
+        0x62, 0x03, // V2 = 03
+        0x81, 0x26, // V1 = V2 >> 1
+    ];
+
+    let cpu = run(Quirks::SUPER_CHIP, &rom, 3);
+    assert_eq!((value(&cpu, 1), value(&cpu, 0xF)), (0x08, 0));
+
+    let cpu = run(Quirks::COSMAC_VIP, &rom, 3);
+    assert_eq!((value(&cpu, 1), value(&cpu, 0xF)), (0x01, 1));
+}
+
+#[test]
+fn shift_is_shown_the_way_it_runs() {
+    let rom = [0x81, 0x26];
+    let at = Address::new(0x200u16);
+
+    let text = run(Quirks::default(), &rom, 0).decode_at(at).unwrap();
+    assert_eq!(text.to_string(), "V1 >>= 1");
+    let text = run(Quirks::COSMAC_VIP, &rom, 0).decode_at(at).unwrap();
+    assert_eq!(text.to_string(), "V1 = V2 >> 1");
+
+    let opcode = Opcode::decode(0x812E).unwrap();
+    assert_eq!(Quirks::XO_CHIP.apply(opcode), opcode);
+    assert_eq!(
+        Quirks::CHIP_48.apply(opcode),
+        Opcode::decode(0x811E).unwrap()
+    );
+}
+
+#[test]
+fn load_store_increment() {
+    let rom = [
+        0xA3, 0x00, // I = 300
+        0xF2, 0x55, // *I = [V0..=V2]
+        0xF1, 0x65, // [V0..=V1] = *I
+    ];
+
+    let presets = [
+        (Quirks::COSMAC_VIP, 0x305),
+        (Quirks::CHIP_48, 0x303),
+        (Quirks::SUPER_CHIP, 0x300),
+    ];
+    for (quirks, address) in presets.iter() {
+        let cpu = run(*quirks, &rom, 3);
+        assert_eq!(cpu.registers.address, Address::new(*address as u16));
+    }
+}
+
+#[test]
+fn offset_jump() {
+    let rom = [
+        0x60, 0x04, // V0 = 04
+        0x62, 0x08, // V2 = 08
+        0xB2, 0x10, // goto 210 + V0
+    ];
+
+    let cpu = run(Quirks::COSMAC_VIP, &rom, 3);
+    assert_eq!(cpu.registers.program_counter, Address::new(0x214u16));
+
+    let cpu = run(Quirks::CHIP_48, &rom, 3);
+    assert_eq!(cpu.registers.program_counter, Address::new(0x218u16));
+}
+
+#[test]
+fn logic_flag() {
+    let rom = [
+        0x6F, 0x01, // VF = 01
+        0x81, 0x21, // V1 |= V2
+    ];
+
+    assert_eq!(value(&run(Quirks::COSMAC_VIP, &rom, 2), 0xF), 0);
+    assert_eq!(value(&run(Quirks::SUPER_CHIP, &rom, 2), 0xF), 1);
+}
+
+#[test]
+fn sprite_clipping() {
+    let rom = [
+        0x60, 0x3E, // V0 = 3E
+        0x61, 0x00, // V1 = 00
+        0xA2, 0x08, // I = 208
+        0xD0, 0x11, // draw 8x1 at (V0; V1)
+        0xFF, // sprite
+    ];
+
+    let cpu = run(Quirks::SUPER_CHIP, &rom, 4);
+    assert_eq!(cpu.vram.get(63usize, 0usize).unwrap(), 1);
+    assert_eq!(cpu.vram.get(0usize, 0usize).unwrap(), 0);
+
+    let cpu = run(Quirks::XO_CHIP, &rom, 4);
+    assert_eq!(cpu.vram.get(63usize, 0usize).unwrap(), 1);
+    assert_eq!(cpu.vram.get(0usize, 0usize).unwrap(), 1);
+}
+
+#[test]
+fn extended_memory() {
+    let high = Address::new(0xFFF0u16);
+    let cpu = run(Quirks::XO_CHIP, &[], 0);
+    assert_eq!(cpu.memory.peek(high).unwrap(), Word::ZERO);
+    let cpu = run(Quirks::COSMAC_VIP, &[], 0);
+    assert!(cpu.memory.peek(high).is_err());
+}
+
+#[test]
+fn presets_are_distinct() {
+    let presets = [
+        Quirks::COSMAC_VIP,
+        Quirks::CHIP_48,
+        Quirks::SUPER_CHIP,
+        Quirks::XO_CHIP,
+    ];
+    for (i, a) in presets.iter().enumerate() {
+        for b in presets[i + 1..].iter() {
+            assert_ne!(a, b);
+        }
+    }
+
+    for name in Quirks::PRESET_NAMES {
+        assert!(Quirks::from_name(name).is_some());
+    }
+    assert!(Quirks::from_name("Chip48").is_none());
+}
@@ -0,0 +1,36 @@
+use chip8::input::ScriptedInput;
+use chip8::random::SeededRandom;
+use chip8::state::SaveState;
+use chip8::CPU;
+
+const ROM: &[u8] = &[
+    0xA2, 0x0E, // 0200: I = 020E
+    0xC0, 0x3F, // 0202: V0 = rand() & 3F
+    0xC1, 0x1F, // 0204: V1 = rand() & 1F
+    0xD0, 0x11, // 0206: draw *I at (V0; V1) size 8x1
+    0xF2, 0x07, // 0208: V2 = delay_timer
+    0xF0, 0x15, // 020A: delay_timer = V0
+    0x12, 0x02, // 020C: goto 0202
+    0x80, // 020E: sprite
+];
+
+/// Runs the ROM for 100 frames and returns everything it can observe
+fn run(seed: u64) -> Vec<u8> {
+    let mut cpu = CPU::new(ScriptedInput::new());
+    cpu.random = Box::new(SeededRandom::new(seed));
+    cpu.load_rom(ROM).unwrap();
+    for _ in 0..100 {
+        cpu.frame(16).unwrap();
+    }
+
+    let mut bytes = Vec::new();
+    SaveState::capture(&cpu).unwrap().write(&mut bytes).unwrap();
+    bytes
+}
+
+#[test]
+fn seeded_runs_are_reproducible() {
+    assert_eq!(run(1234), run(1234));
+    assert_ne!(run(1234), run(1235));
+}
+
@@ -0,0 +1,68 @@
+use chip8::input::ScriptedInput;
+use chip8::rewind::RewindBuffer;
+use chip8::state::SaveState;
+use chip8::CPU;
+
+const ROM: &[u8] = &[
+    0xA3, 0x00, // 0200: I = 0300
+    0x70, 0x01, // 0202: V0 += 01
+    0xF0, 0x55, // 0204: *I = [V0..=V0]
+    0xD0, 0x01, // 0206: draw *I at (V0; V0) size 8x1
+    0x12, 0x02, // 0208: goto 0202
+];
+
+fn to_bytes(state: &SaveState) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    state.write(&mut bytes).unwrap();
+    bytes
+}
+
+/// Runs 8 frames, pushing the state after each of them
+fn record(rewind: &mut RewindBuffer) -> (CPU, Vec<Vec<u8>>) {
+    let mut cpu = CPU::new(ScriptedInput::new());
+    cpu.load_rom(ROM).unwrap();
+
+    let mut history = Vec::new();
+    for _ in 0..8 {
+        cpu.frame(4).unwrap();
+        let state = SaveState::capture(&cpu).unwrap();
+        history.push(to_bytes(&state));
+        rewind.push(state);
+    }
+    (cpu, history)
+}
+
+#[test]
+fn restores_earlier_states() {
+    let mut rewind = RewindBuffer::new(5);
+    let (mut cpu, history) = record(&mut rewind);
+    assert_eq!(rewind.len(), 5);
+    assert_ne!(history[3], history[7]);
+
+    // Only the newest states are kept, and each one comes back exactly
+    let mut popped = Vec::new();
+    for expected in history.iter().rev().take(5) {
+        let state = rewind.pop().unwrap();
+        assert_eq!(&to_bytes(&state), expected);
+        popped.push(state);
+    }
+    assert!(rewind.pop().is_none());
+    assert!(rewind.is_empty());
+
+    // Running on from the oldest one gets to the same states again
+    popped.last().unwrap().restore(&mut cpu).unwrap();
+    assert_eq!(to_bytes(&SaveState::capture(&cpu).unwrap()), history[3]);
+    cpu.frame(4).unwrap();
+    assert_eq!(to_bytes(&SaveState::capture(&cpu).unwrap()), history[4]);
+}
+
+#[test]
+fn layout_changes_drop_older_states() {
+    let mut rewind = RewindBuffer::new(10);
+    let (mut cpu, _) = record(&mut rewind);
+    assert_eq!(rewind.len(), 8);
+
+    cpu.vram.set_high_resolution(true).unwrap();
+    rewind.push(SaveState::capture(&cpu).unwrap());
+    assert_eq!(rewind.len(), 1);
+}
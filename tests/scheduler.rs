@@ -0,0 +1,81 @@
+use chip8::input::ScriptedInput;
+use chip8::scheduler::{Clock, FrameScheduler, Speed};
+use chip8::timers::TIMER_PERIOD;
+use chip8::CPU;
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+/// Only moves forward when the scheduler sleeps, so frames take exactly as long as intended
+struct FakeClock(Rc<Cell<Instant>>);
+
+impl Clock for FakeClock {
+    fn now(&self) -> Instant {
+        self.0.get()
+    }
+
+    fn sleep(&mut self, duration: Duration) {
+        self.0.set(self.0.get() + duration);
+    }
+}
+
+fn new_scheduler() -> (FrameScheduler, Rc<Cell<Instant>>) {
+    let time = Rc::new(Cell::new(Instant::now()));
+    let mut scheduler = FrameScheduler::new(10);
+    scheduler.clock = Box::new(FakeClock(time.clone()));
+    (scheduler, time)
+}
+
+/// Runs `frames` frames of an endless loop, returning how long they took and how many
+/// instructions ran
+fn run(scheduler: &mut FrameScheduler, time: &Cell<Instant>, frames: u32) -> (Duration, u64) {
+    let input = Rc::new(RefCell::new(ScriptedInput::new()));
+    let mut cpu = CPU::new(input.clone());
+    cpu.load_rom(&[0x12, 0x00]).unwrap();
+
+    let mut remaining = frames;
+    let start = time.get();
+    scheduler
+        .run(&mut cpu, |_, scheduler| {
+            remaining -= 1;
+            if remaining == 0 {
+                scheduler.stop();
+            }
+            Ok(())
+        })
+        .unwrap();
+
+    let ticks = input.borrow().ticks();
+    (time.get() - start, ticks)
+}
+
+#[test]
+fn speed_only_changes_frame_pacing() {
+    let (mut scheduler, time) = new_scheduler();
+    let (normal, ticks) = run(&mut scheduler, &time, 6);
+    assert_eq!(normal, TIMER_PERIOD * 6);
+    assert_eq!(ticks, 60);
+
+    // 4 times as many frames in the same time
+    scheduler.toggle(Speed::FastForward);
+    let (fast, ticks) = run(&mut scheduler, &time, 24);
+    assert_eq!(fast, TIMER_PERIOD.div_f64(4.0) * 24);
+    assert_eq!(ticks, 240);
+
+    // A quarter as many frames in the same time
+    scheduler.toggle(Speed::SlowMotion);
+    assert_eq!(scheduler.speed, Speed::SlowMotion);
+    let (slow, ticks) = run(&mut scheduler, &time, 2);
+    assert_eq!(slow, TIMER_PERIOD.div_f64(0.25) * 2);
+    assert_eq!(ticks, 20);
+}
+
+#[test]
+fn toggling_twice_goes_back_to_normal() {
+    let mut scheduler = FrameScheduler::new(10);
+    scheduler.toggle(Speed::FastForward);
+    assert_eq!(scheduler.multiplier(), FrameScheduler::DEFAULT_FAST_FORWARD);
+    scheduler.toggle(Speed::FastForward);
+    assert_eq!(scheduler.speed, Speed::Normal);
+    assert_eq!(scheduler.multiplier(), 1.0);
+}
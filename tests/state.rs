@@ -0,0 +1,150 @@
+use chip8::core::{Address, Word};
+use chip8::input::ScriptedInput;
+use chip8::random::SeededRandom;
+use chip8::state::SaveState;
+use chip8::CPU;
+
+const ROM: &[u8] = &[
+    0x60, 0x05, // 0200: V0 = 05
+    0xA3, 0x00, // 0202: I = 0300
+    0xF0, 0x55, // 0204: store V0 at 0300
+    0xA2, 0x10, // 0206: I = 0210
+    0xD0, 0x05, // 0208: draw 8x5 at (05, 05)
+    0x22, 0x0E, // 020A: call 020E
+    0x12, 0x0C, // 020C: goto 020C
+    0x12, 0x0E, // 020E: goto 020E
+    0xF0, 0x90, 0x90, 0x90, 0xF0, // 0210: sprite
+];
+
+fn new_cpu() -> CPU {
+    let mut cpu = CPU::new(ScriptedInput::new());
+    cpu.load_rom(ROM).unwrap();
+    cpu
+}
+
+fn run_cpu() -> CPU {
+    let mut cpu = new_cpu();
+    for _ in 0..8 {
+        cpu.step().unwrap();
+    }
+    cpu.timers.delay_timer = Word::new(0x30u8);
+    cpu
+}
+
+fn to_bytes(state: &SaveState) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    state.write(&mut bytes).unwrap();
+    bytes
+}
+
+/// Writes the state as `version`, which has fewer fields at the end than the newest one.
+/// The state must not have a random state, which would make the newest fields longer.
+fn to_version(state: &SaveState, version: u16) -> Vec<u8> {
+    let mut bytes = to_bytes(state);
+    bytes[4..6].copy_from_slice(&version.to_be_bytes());
+    let removed = match version {
+        1 => 0x10 + 1 + 0x10 + 1 + 2,
+        2 => 1 + 0x10 + 1 + 2,
+        3 => 2,
+        _ => 0,
+    };
+    bytes.truncate(bytes.len() - removed);
+    bytes
+}
+
+#[test]
+fn restores_every_version() {
+    let cpu = run_cpu();
+    assert_eq!(cpu.registers.program_counter, Address::new(0x20Eu16));
+    assert_eq!(cpu.stack.len(), 1);
+    let expected = to_bytes(&SaveState::capture(&cpu).unwrap());
+
+    for version in 1..=4 {
+        let bytes = to_version(&SaveState::capture(&cpu).unwrap(), version);
+        let state = SaveState::read(&mut bytes.as_slice()).unwrap();
+
+        let mut restored = new_cpu();
+        state.restore(&mut restored).unwrap();
+        let actual = to_bytes(&SaveState::capture(&restored).unwrap());
+        assert_eq!(actual, expected, "version {}", version);
+
+        assert_eq!(
+            restored.memory.peek(Address::new(0x300u16)).unwrap(),
+            Word::new(5u8)
+        );
+        assert_eq!(restored.vram.get(5usize, 5usize).unwrap(), 1);
+    }
+}
+
+#[test]
+fn restores_newer_fields() {
+    let mut cpu = run_cpu();
+    cpu.registers.rpl_flags[3] = Word::new(0x42u8);
+    cpu.vram.select_planes(2).unwrap();
+    cpu.audio.bits[0] = Word::new(0xAAu8);
+    cpu.audio.pitch = Word::new(0x70u8);
+
+    let state = SaveState::capture(&cpu).unwrap();
+    let bytes = to_bytes(&state);
+    let mut restored = new_cpu();
+    SaveState::read(&mut bytes.as_slice())
+        .unwrap()
+        .restore(&mut restored)
+        .unwrap();
+
+    assert_eq!(restored.registers.rpl_flags[3], Word::new(0x42u8));
+    assert_eq!(restored.vram.selected_planes(), 2);
+    assert_eq!(restored.audio, cpu.audio);
+    assert_eq!(to_bytes(&SaveState::capture(&restored).unwrap()), bytes);
+}
+
+#[test]
+fn restores_the_random_sequence() {
+    let mut cpu = run_cpu();
+    cpu.random = Box::new(SeededRandom::new(7));
+    cpu.random.next_word();
+    cpu.halted = true;
+
+    let bytes = to_bytes(&SaveState::capture(&cpu).unwrap());
+    let mut restored = new_cpu();
+    SaveState::read(&mut bytes.as_slice())
+        .unwrap()
+        .restore(&mut restored)
+        .unwrap();
+
+    assert!(restored.halted);
+    let expected: Vec<_> = (0..8).map(|_| cpu.random.next_word()).collect();
+    let actual: Vec<_> = (0..8).map(|_| restored.random.next_word()).collect();
+    assert_eq!(actual, expected);
+}
+
+#[test]
+fn failed_restores_change_nothing() {
+    let good = SaveState::capture(&run_cpu()).unwrap();
+
+    let mut bad_planes = good.clone();
+    bad_planes.selected_planes = 0xFF;
+    let mut bad_vram = good.clone();
+    bad_vram.vram.pop();
+    let mut bad_bank = good.clone();
+    bad_bank.banks[0].data.pop();
+
+    for state in [bad_planes, bad_vram, bad_bank] {
+        let mut cpu = new_cpu();
+        let before = to_bytes(&SaveState::capture(&cpu).unwrap());
+        assert!(state.restore(&mut cpu).is_err());
+        assert_eq!(to_bytes(&SaveState::capture(&cpu).unwrap()), before);
+    }
+}
+
+#[test]
+fn rejects_other_files() {
+    let bytes = to_bytes(&SaveState::capture(&run_cpu()).unwrap());
+
+    let mut newer = bytes.clone();
+    newer[4..6].copy_from_slice(&5u16.to_be_bytes());
+    assert!(SaveState::read(&mut newer.as_slice()).is_err());
+
+    assert!(SaveState::read(&mut &b"C8MV\x00\x01"[..]).is_err());
+    assert!(SaveState::read(&mut &bytes[..bytes.len() - 1]).is_err());
+}
@@ -0,0 +1,253 @@
+use chip8::core::{Address, Word};
+use chip8::input::ScriptedInput;
+use chip8::memory::MemoryRange;
+use chip8::opcodes::Opcode;
+use chip8::trace::{TraceEntry, TraceFormat, TraceReader, TraceSink, TraceWriter};
+use chip8::CPU;
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+fn temp_path(name: &str) -> PathBuf {
+    env::temp_dir().join(format!("chip8-trace-{}-{}", std::process::id(), name))
+}
+
+fn entry(cycle: u64, raw: u16, operand: Option<u16>) -> TraceEntry {
+    let opcode = match operand {
+        Some(x) => Opcode::decode_with_operand(raw, x),
+        None => Opcode::decode(raw),
+    };
+    let mut values = [Word::new(0u8); 0x10];
+    values[3] = Word::new(cycle as u8);
+
+    TraceEntry {
+        cycle,
+        program_counter: Address::new(0x200 + 2 * cycle as u16),
+        raw,
+        operand,
+        opcode: opcode.unwrap(),
+        values,
+        address: Address::new(0x300u16),
+        stack_depth: 1,
+        writes: Vec::new(),
+    }
+}
+
+fn entries() -> Vec<TraceEntry> {
+    let mut store = entry(2, 0xF355, None);
+    store.writes = vec![
+        (Address::new(0x300u16), Word::new(1u8)),
+        (Address::new(0x301u16), Word::new(0xFEu8)),
+    ];
+    vec![
+        entry(0, 0x6301, None),
+        entry(1, 0xF000, Some(0x1234)),
+        store,
+    ]
+}
+
+fn write_trace(name: &str, format: TraceFormat, entries: &[TraceEntry]) -> PathBuf {
+    let path = temp_path(name);
+    let mut writer = TraceWriter::create_file(path.to_str().unwrap(), format).unwrap();
+    for entry in entries {
+        writer.trace(entry).unwrap();
+    }
+    writer.end_frame().unwrap();
+    path
+}
+
+fn read_trace(path: &PathBuf) -> Vec<TraceEntry> {
+    let reader = TraceReader::open_file(path.to_str().unwrap()).unwrap();
+    let entries = reader.collect::<Result<Vec<_>, _>>().unwrap();
+    fs::remove_file(path).unwrap();
+    entries
+}
+
+#[test]
+fn reads_back_what_was_written() {
+    for (name, format) in [("text", TraceFormat::Text), ("binary", TraceFormat::Binary)] {
+        let path = write_trace(name, format, &entries());
+        assert_eq!(read_trace(&path), entries(), "{} format", name);
+    }
+}
+
+#[test]
+fn text_format() {
+    let path = write_trace("lines", TraceFormat::Text, &entries()[2..]);
+    let text = fs::read_to_string(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+
+    assert!(text.starts_with("0000000002 0204 F355"), "{}", text);
+    assert!(
+        text.trim_end()
+            .ends_with("V=00000002000000000000000000000000 I=0300 SP=1 [0300]=01 [0301]=FE"),
+        "{}",
+        text
+    );
+}
+
+#[test]
+fn ranges_filter_instructions() {
+    let path = temp_path("ranges");
+    let mut writer = TraceWriter::create_file(path.to_str().unwrap(), TraceFormat::Binary).unwrap();
+    writer.ranges.push(MemoryRange::new(0x202u16, 0x203u16));
+    for entry in entries().iter() {
+        writer.trace(entry).unwrap();
+    }
+    drop(writer);
+
+    assert_eq!(read_trace(&path), entries()[1..2].to_vec());
+}
+
+#[test]
+fn frames_are_flushed() {
+    let rom = [
+        0xA3, 0x00, // 0200: I = 0300
+        0x60, 0x2A, // 0202: V0 = 2A
+        0xF0, 0x55, // 0204: *I = [V0..=V0]
+        0x12, 0x06, // 0206: goto 0206
+    ];
+
+    let path = temp_path("frames");
+    let writer = TraceWriter::create_file(path.to_str().unwrap(), TraceFormat::Binary).unwrap();
+    let mut cpu = CPU::new(ScriptedInput::new());
+    cpu.tracers.push(Box::new(writer));
+    cpu.load_rom(&rom).unwrap();
+    cpu.frame(5).unwrap();
+
+    // Everything is readable while the CPU still owns the writer
+    let entries = read_trace(&path);
+    let cycles: Vec<u64> = entries.iter().map(|x| x.cycle).collect();
+    assert_eq!(cycles, vec![0, 1, 2, 3, 4]);
+    let pcs: Vec<u16> = entries.iter().map(|x| x.program_counter.into()).collect();
+    assert_eq!(pcs, vec![0x200, 0x202, 0x204, 0x206, 0x206]);
+    assert_eq!(
+        entries[2].writes,
+        vec![(Address::new(0x300u16), Word::new(0x2Au8))]
+    );
+    assert!(entries[3].writes.is_empty());
+}
+
+#[test]
+fn truncated_entries_are_errors() {
+    let path = write_trace("truncated", TraceFormat::Binary, &entries());
+    let data = fs::read(&path).unwrap();
+    fs::write(&path, &data[..data.len() - 1]).unwrap();
+
+    let reader = TraceReader::open_file(path.to_str().unwrap()).unwrap();
+    let results: Vec<_> = reader.collect();
+    fs::remove_file(&path).unwrap();
+    assert_eq!(results.len(), 3);
+    assert!(results[..2].iter().all(|x| x.is_ok()));
+    assert!(results[2].is_err());
+}
+
+#[test]
+fn values_that_do_not_fit_are_rejected() {
+    let mut deep = entry(0, 0x00EE, None);
+    deep.stack_depth = 0x100;
+    let mut busy = entry(0, 0xFF55, None);
+    busy.writes = vec![(Address::new(0x300u16), Word::new(0u8)); 0x100];
+
+    for bad in [deep, busy] {
+        let path = temp_path("bad");
+        let mut writer =
+            TraceWriter::create_file(path.to_str().unwrap(), TraceFormat::Binary).unwrap();
+        assert!(writer.trace(&bad).is_err());
+        drop(writer);
+        fs::remove_file(&path).unwrap();
+    }
+}
+
+#[test]
+fn older_and_newer_versions() {
+    // Version 1 had no memory writes
+    let path = temp_path("v1");
+    let mut data = b"C8TR\x00\x01".to_vec();
+    data.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 7, 0x02, 0x00, 0x63, 0x01]);
+    data.extend_from_slice(&[0; 0x10]);
+    data.extend_from_slice(&[0x03, 0x00, 0x01]);
+    fs::write(&path, &data).unwrap();
+
+    let mut expected = entry(7, 0x6301, None);
+    expected.program_counter = Address::new(0x200u16);
+    expected.values[3] = Word::new(0u8);
+    assert_eq!(read_trace(&path), vec![expected]);
+
+    let path = temp_path("v3");
+    fs::write(&path, b"C8TR\x00\x03").unwrap();
+    assert!(TraceReader::open_file(path.to_str().unwrap()).is_err());
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn differences() {
+    let a = entry(0, 0x6301, None);
+    assert!(a.differences(&a.clone()).is_empty());
+
+    let mut b = entry(5, 0x6301, None);
+    b.values = a.values;
+    b.program_counter = a.program_counter;
+    assert!(a.differences(&b).is_empty(), "the cycle count is ignored");
+
+    b.values[0xA] = Word::new(0x42u8);
+    b.address = Address::new(0x302u16);
+    b.stack_depth = 2;
+    b.writes.push((Address::new(0x300u16), Word::new(0u8)));
+    assert_eq!(
+        a.differences(&b),
+        [
+            "VA 00 vs 42",
+            "I 0300 vs 0302",
+            "SP 1 vs 2",
+            "memory writes"
+        ]
+    );
+
+    let c = entry(0, 0x6302, None);
+    assert_eq!(a.differences(&c), ["opcode V3 = 01 vs V3 = 02"]);
+}
+
+fn trace_diff(a: &[TraceEntry], b: &[TraceEntry]) -> String {
+    let path_a = write_trace("diff-a", TraceFormat::Binary, a);
+    let path_b = write_trace("diff-b", TraceFormat::Text, b);
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_chip8"))
+        .arg("trace-diff")
+        .arg(&path_a)
+        .arg(&path_b)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(b"\n").unwrap();
+    let output = child.wait_with_output().unwrap();
+
+    fs::remove_file(path_a).unwrap();
+    fs::remove_file(path_b).unwrap();
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn trace_diff_finds_the_first_difference() {
+    let output = trace_diff(&entries(), &entries());
+    assert!(
+        output.contains("The traces are identical, 3 instructions long"),
+        "{}",
+        output
+    );
+
+    let mut changed = entries();
+    changed[1].values[0] = Word::new(1u8);
+    let output = trace_diff(&entries(), &changed);
+    assert!(
+        output.contains("The traces differ after 1 instructions: V0 00 vs 01"),
+        "{}",
+        output
+    );
+
+    let output = trace_diff(&entries(), &entries()[..2]);
+    assert!(output.contains("ends after 2 instructions"), "{}", output);
+}